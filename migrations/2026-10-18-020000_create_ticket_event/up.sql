@@ -0,0 +1,16 @@
+-- Your SQL goes here
+create table ticket_event(
+    id serial primary key,
+    ticket_id integer not null references ticket_info (id),
+    employee_id integer null references employee_info (id),
+    event_type smallint not null,
+    old_state smallint null,
+    new_state smallint not null,
+    comment varchar(500) null,
+    created_time timestamp default CURRENT_TIMESTAMP not null
+);
+create index ticket_event_ticket_id_idx on ticket_event (ticket_id, created_time);
+comment on column ticket_event.employee_id is '操作人的员工ID，为空表示系统自动操作';
+comment on column ticket_event.event_type is '0创建，1审批通过，2驳回，3接取，4协助，5完成';
+comment on column ticket_event.old_state is '操作前工单状态，创建时为空';
+comment on column ticket_event.new_state is '操作后工单状态';
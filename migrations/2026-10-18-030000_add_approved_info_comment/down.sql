@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+alter table approved_info drop column comment;
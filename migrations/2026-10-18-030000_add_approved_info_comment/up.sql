@@ -0,0 +1,3 @@
+-- Your SQL goes here
+alter table approved_info add column comment varchar(500) null;
+comment on column approved_info.comment is '审批意见，驳回时一般写驳回理由';
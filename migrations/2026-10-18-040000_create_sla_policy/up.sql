@@ -0,0 +1,20 @@
+-- Your SQL goes here
+create table sla_policy(
+    id serial primary key,
+    system_id integer not null references system_info (id),
+    state smallint not null,
+    department_id integer null references operation_info (id),
+    min_amount integer null,
+    max_amount integer null,
+    warning_hours integer not null,
+    overdue_hours integer not null,
+    check (warning_hours > 0 and warning_hours <= overdue_hours),
+    check (min_amount is null or max_amount is null or min_amount < max_amount)
+);
+create index sla_policy_system_id_idx on sla_policy (system_id, state);
+comment on column sla_policy.state is '工单处于这个状态时适用';
+comment on column sla_policy.department_id is '为空表示所有部门';
+comment on column sla_policy.min_amount is '金额下限（含），为空表示不限';
+comment on column sla_policy.max_amount is '金额上限（不含），为空表示不限';
+comment on column sla_policy.warning_hours is '进入该状态多少小时后预警';
+comment on column sla_policy.overdue_hours is '进入该状态多少小时后超时';
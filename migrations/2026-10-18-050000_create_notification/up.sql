@@ -0,0 +1,13 @@
+-- Your SQL goes here
+create table notification(
+    id serial primary key,
+    employee_id integer not null references employee_info (id),
+    ticket_id integer not null references ticket_info (id),
+    kind smallint not null,
+    content varchar(500) not null,
+    is_read boolean not null default false,
+    created_time timestamp default CURRENT_TIMESTAMP not null
+);
+create index notification_employee_id_idx on notification (employee_id, is_read, created_time);
+comment on column notification.employee_id is '接收人';
+comment on column notification.kind is '0待审批，1审批通过，2驳回，3退回修改，4可接取，5被接取，6协助请求';
@@ -0,0 +1,32 @@
+-- Your SQL goes here
+create table webhook(
+    id serial primary key,
+    system_id integer not null references system_info (id),
+    url varchar(500) not null,
+    secret varchar(255) not null,
+    event_types smallint[] not null default '{}',
+    created_time timestamp default CURRENT_TIMESTAMP not null
+);
+create index webhook_system_id_idx on webhook (system_id);
+comment on column webhook.secret is '用来给请求体做 HMAC-SHA256 签名';
+comment on column webhook.event_types is '订阅的工单事件类型，为空表示全部';
+
+create table webhook_delivery(
+    id serial primary key,
+    webhook_id integer not null references webhook (id) on delete cascade,
+    ticket_id integer not null references ticket_info (id),
+    event_type smallint not null,
+    payload text not null,
+    state smallint not null default 0,
+    attempts integer not null default 0,
+    next_attempt_time timestamp not null,
+    last_status integer null,
+    last_error varchar(500) null,
+    created_time timestamp default CURRENT_TIMESTAMP not null,
+    delivered_time timestamp null
+);
+create index webhook_delivery_webhook_id_idx on webhook_delivery (webhook_id, created_time);
+create index webhook_delivery_pending_idx on webhook_delivery (next_attempt_time) where state = 0;
+comment on column webhook_delivery.state is '0等待投递，1投递成功，2重试次数用完';
+comment on column webhook_delivery.attempts is '已经尝试了几次';
+comment on column webhook_delivery.last_status is '最后一次请求的 HTTP 状态码，连不上为空';
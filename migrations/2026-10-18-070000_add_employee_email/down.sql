@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+alter table notification drop column email_attempts;
+alter table notification drop column email_state;
+alter table employee_info drop column email_opt_out;
+alter table employee_info drop column email;
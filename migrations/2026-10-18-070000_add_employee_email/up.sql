@@ -0,0 +1,10 @@
+-- Your SQL goes here
+alter table employee_info add column email varchar(255) null;
+alter table employee_info add column email_opt_out boolean not null default false;
+comment on column employee_info.email is '接收邮件通知的地址';
+comment on column employee_info.email_opt_out is '为 true 表示不接收邮件通知';
+
+alter table notification add column email_state smallint null;
+alter table notification add column email_attempts integer not null default 0;
+create index notification_email_state_idx on notification (id) where email_state = 0;
+comment on column notification.email_state is '为空表示不发邮件，0等待发送，1已发送，2发送失败';
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+drop index assist_employee_info_assist_employee_idx;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- 之前部门需求的状态写的是工单的 Open(2)，按人数重新算
+update assist_department_info set state = case when current_num >= total_num then 1 else 0 end;
+delete from assist_employee_info a using assist_employee_info b
+    where a.assist_id = b.assist_id and a.employee_id = b.employee_id and a.id > b.id;
+create unique index assist_employee_info_assist_employee_idx on assist_employee_info (assist_id, employee_id);
+comment on column assist_info.state is '0还在招人，1人齐了，2完成，3撤销';
+comment on column assist_info.receiver_id is '负责人，第一个接的人，退出后交给其他参与人';
+comment on column assist_department_info.state is '0还在招人，1人满了';
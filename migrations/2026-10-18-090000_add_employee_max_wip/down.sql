@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+alter table employee_info drop column max_wip;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+alter table employee_info add column max_wip integer not null default 1 check (max_wip > 0);
+comment on column employee_info.max_wip is '同时处理的工单上限，主工单和协助工单一起算';
+comment on column employee_info.state is '0空闲，1忙碌，由正在处理的工单数和 max_wip 算出来';
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+alter table employee_info drop column last_assigned_time;
+alter table system_info drop column dispatch_cursor;
+alter table system_info drop column dispatch_strategy;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+alter table system_info add column dispatch_strategy smallint not null default 0;
+alter table system_info add column dispatch_cursor integer null;
+comment on column system_info.dispatch_strategy is '0手动接单，1轮流派单，2派给手上工单最少的人，3派给最久没接单的人';
+comment on column system_info.dispatch_cursor is '轮流派单时上一次派给的员工id';
+
+alter table employee_info add column last_assigned_time timestamp null;
+comment on column employee_info.last_assigned_time is '最近一次接单或被派单的时间';
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+alter table employee_operation_info drop column is_lead;
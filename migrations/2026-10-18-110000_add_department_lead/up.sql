@@ -0,0 +1,3 @@
+-- Your SQL goes here
+alter table employee_operation_info add column is_lead boolean not null default false;
+comment on column employee_operation_info.is_lead is '是不是这个部门的负责人，负责人可以改派本部门的工单';
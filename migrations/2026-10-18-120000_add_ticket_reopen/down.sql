@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+alter table system_info drop column reopen_window_hours;
+alter table ticket_info drop column reopen_count;
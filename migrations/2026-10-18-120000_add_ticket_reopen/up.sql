@@ -0,0 +1,6 @@
+-- Your SQL goes here
+alter table ticket_info add column reopen_count integer not null default 0;
+comment on column ticket_info.reopen_count is '关闭之后被创建人重新打开的次数';
+
+alter table system_info add column reopen_window_hours integer not null default 72 check (reopen_window_hours >= 0);
+comment on column system_info.reopen_window_hours is '工单关闭多少小时内创建人可以重新打开，0表示不能重新打开';
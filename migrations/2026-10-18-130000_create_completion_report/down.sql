@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+alter table system_info drop column confirm_timeout_hours;
+drop table completion_report_cost;
+drop table completion_report;
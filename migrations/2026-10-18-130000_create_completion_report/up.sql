@@ -0,0 +1,24 @@
+-- Your SQL goes here
+create table completion_report(
+    id serial primary key,
+    ticket_id integer not null references ticket_info (id),
+    employee_id integer not null references employee_info (id),
+    notes text not null,
+    images text[] not null default '{}',
+    created_time timestamp default CURRENT_TIMESTAMP not null
+);
+create index completion_report_ticket_id_idx on completion_report (ticket_id, created_time);
+comment on column completion_report.notes is '做了哪些工作';
+comment on column completion_report.images is '完工照片的地址';
+
+create table completion_report_cost(
+    id serial primary key,
+    report_id integer not null references completion_report (id) on delete cascade,
+    reason varchar(255) not null,
+    amount integer not null check (amount >= 0)
+);
+create index completion_report_cost_report_id_idx on completion_report_cost (report_id);
+comment on column completion_report_cost.amount is '实际花费';
+
+alter table system_info add column confirm_timeout_hours integer not null default 72 check (confirm_timeout_hours > 0);
+comment on column system_info.confirm_timeout_hours is '提交完工报告多少小时后创建人还没确认就自动关闭';
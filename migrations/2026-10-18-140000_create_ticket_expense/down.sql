@@ -0,0 +1,15 @@
+-- This file should undo anything in `up.sql`
+alter table system_info drop column overrun_threshold_percent;
+drop table overrun_approval;
+
+create table completion_report_cost(
+    id serial primary key,
+    report_id integer not null references completion_report (id) on delete cascade,
+    reason varchar(255) not null,
+    amount integer not null check (amount >= 0)
+);
+create index completion_report_cost_report_id_idx on completion_report_cost (report_id);
+comment on column completion_report_cost.amount is '实际花费';
+insert into completion_report_cost (report_id, reason, amount)
+select report_id, reason, amount from ticket_expense where report_id is not null order by id;
+drop table ticket_expense;
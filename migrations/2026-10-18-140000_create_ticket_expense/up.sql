@@ -0,0 +1,43 @@
+-- Your SQL goes here
+create table ticket_expense(
+    id serial primary key,
+    ticket_id integer not null references ticket_info (id),
+    employee_id integer not null references employee_info (id),
+    report_id integer references completion_report (id) on delete set null,
+    reason varchar(255) not null,
+    amount integer not null check (amount >= 0),
+    receipt varchar(255),
+    created_time timestamp default CURRENT_TIMESTAMP not null
+);
+create index ticket_expense_ticket_id_idx on ticket_expense (ticket_id);
+comment on column ticket_expense.report_id is '随完工报告一起提交的花费';
+comment on column ticket_expense.amount is '实际花费';
+comment on column ticket_expense.receipt is '票据的地址';
+
+-- 完工报告里的花费并进台账
+insert into ticket_expense (ticket_id, employee_id, report_id, reason, amount, created_time)
+select r.ticket_id, r.employee_id, r.id, c.reason, c.amount, r.created_time
+from completion_report_cost c
+join completion_report r on r.id = c.report_id
+order by c.id;
+drop table completion_report_cost;
+
+create table overrun_approval(
+    id serial primary key,
+    ticket_id integer not null references ticket_info (id),
+    amount integer not null check (amount > 0),
+    approval_id integer references approval_info (id),
+    state smallint not null default 0,
+    last_approver_id integer references employee_info (id),
+    comment text,
+    created_time timestamp default CURRENT_TIMESTAMP not null,
+    finished_time timestamp
+);
+-- 一个工单同时只有一个超支审批
+create unique index overrun_approval_pending_idx on overrun_approval (ticket_id) where state = 0;
+comment on column overrun_approval.amount is '超出预算的部分';
+comment on column overrun_approval.approval_id is '当前轮到的审批级别';
+comment on column overrun_approval.state is '0 审批中 1 通过 2 驳回';
+
+alter table system_info add column overrun_threshold_percent integer not null default 10 check (overrun_threshold_percent >= 0);
+comment on column system_info.overrun_threshold_percent is '实际花费超过预算多少百分比需要重新审批';
@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+alter table approval_info drop column currency;
+alter table ticket_info drop column currency;
+alter table system_info drop column currency;
+
+alter table sla_policy alter column max_amount type integer using round(max_amount);
+alter table sla_policy alter column min_amount type integer using round(min_amount);
+alter table overrun_approval alter column amount type integer using round(amount);
+alter table ticket_expense alter column amount type integer using round(amount);
+alter table approval_info alter column amount type integer using round(amount);
+alter table fund_list alter column amount type integer using round(amount);
+alter table ticket_info alter column amount type integer using round(amount);
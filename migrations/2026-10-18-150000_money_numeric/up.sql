@@ -0,0 +1,17 @@
+-- Your SQL goes here
+-- 金额改成两位小数的定点数
+alter table ticket_info alter column amount type numeric(14, 2);
+alter table fund_list alter column amount type numeric(14, 2);
+alter table approval_info alter column amount type numeric(14, 2);
+alter table ticket_expense alter column amount type numeric(14, 2);
+alter table overrun_approval alter column amount type numeric(14, 2);
+alter table sla_policy alter column min_amount type numeric(14, 2);
+alter table sla_policy alter column max_amount type numeric(14, 2);
+
+-- 币种，工单和审批级别创建时跟随系统
+alter table system_info add column currency varchar(3) not null default 'CNY' check (currency ~ '^[A-Z]{3}$');
+alter table ticket_info add column currency varchar(3) not null default 'CNY' check (currency ~ '^[A-Z]{3}$');
+alter table approval_info add column currency varchar(3) not null default 'CNY' check (currency ~ '^[A-Z]{3}$');
+comment on column system_info.currency is '系统使用的币种，ISO 4217 代码';
+comment on column ticket_info.currency is '工单金额的币种';
+comment on column approval_info.currency is '审批额度的币种';
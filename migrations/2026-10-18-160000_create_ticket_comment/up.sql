@@ -0,0 +1,12 @@
+-- Your SQL goes here
+create table ticket_comment(
+    id serial primary key,
+    ticket_id integer not null references ticket_info (id),
+    employee_id integer not null references employee_info (id),
+    content varchar(2000) not null,
+    mentioned_ids integer[] not null default '{}',
+    created_time timestamp default CURRENT_TIMESTAMP not null
+);
+create index ticket_comment_ticket_id_idx on ticket_comment (ticket_id);
+comment on column ticket_comment.employee_id is '发评论的人';
+comment on column ticket_comment.mentioned_ids is '评论里 @ 到的人，已经发过通知';
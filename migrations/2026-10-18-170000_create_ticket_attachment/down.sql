@@ -0,0 +1,24 @@
+-- This file should undo anything in `up.sql`
+alter table ticket_info add column image varchar(255);
+alter table completion_report add column images text[] not null default '{}';
+
+-- 只能放回一张图片，取工单上最早的那个附件
+update ticket_info t set image = a.url
+from (
+    select distinct on (ticket_id) ticket_id, url
+    from ticket_attachment
+    where ticket_id is not null and comment_id is null and report_id is null
+    order by ticket_id, id
+) a
+where a.ticket_id = t.id and length(a.url) <= 255;
+
+update completion_report r set images = a.urls
+from (
+    select report_id, array_agg(url order by id) as urls
+    from ticket_attachment
+    where report_id is not null
+    group by report_id
+) a
+where a.report_id = r.id;
+
+drop table ticket_attachment;
@@ -0,0 +1,59 @@
+-- Your SQL goes here
+create table ticket_attachment(
+    id serial primary key,
+    ticket_id integer references ticket_info (id),
+    comment_id integer references ticket_comment (id),
+    report_id integer references completion_report (id),
+    uploader_id integer references employee_info (id),
+    url varchar(500) not null,
+    filename varchar(255) not null,
+    content_type varchar(100) not null default 'application/octet-stream',
+    size bigint check (size >= 0),
+    checksum varchar(64),
+    created_time timestamp default CURRENT_TIMESTAMP not null,
+    check (comment_id is null or report_id is null)
+);
+create index ticket_attachment_ticket_id_idx on ticket_attachment (ticket_id);
+create index ticket_attachment_comment_id_idx on ticket_attachment (comment_id);
+create index ticket_attachment_report_id_idx on ticket_attachment (report_id);
+comment on column ticket_attachment.ticket_id is '为空表示已上传还没挂到工单上';
+comment on column ticket_attachment.comment_id is '评论里的附件，为空表示不属于评论';
+comment on column ticket_attachment.report_id is '完工报告里的附件，为空表示不属于完工报告';
+comment on column ticket_attachment.filename is '上传时的原始文件名';
+comment on column ticket_attachment.size is '字节数，从旧的图片地址迁移过来的没有';
+comment on column ticket_attachment.checksum is '文件内容的 sha256，十六进制，从旧的图片地址迁移过来的没有';
+
+-- 旧的图片地址挪进附件表，上传人算创建人，文件名取地址最后一段
+insert into ticket_attachment (ticket_id, uploader_id, url, filename, content_type, created_time)
+select id, creator_id, image, left(regexp_replace(image, '^.*/', ''), 255),
+    case lower(substring(image from '\.([A-Za-z0-9]+)$'))
+        when 'png' then 'image/png'
+        when 'jpg' then 'image/jpeg'
+        when 'jpeg' then 'image/jpeg'
+        when 'gif' then 'image/gif'
+        when 'webp' then 'image/webp'
+        when 'pdf' then 'application/pdf'
+        else 'application/octet-stream'
+    end,
+    created_time
+from ticket_info
+where image is not null and image <> '';
+
+insert into ticket_attachment (ticket_id, report_id, uploader_id, url, filename, content_type, created_time)
+select r.ticket_id, r.id, r.employee_id, x.url, left(regexp_replace(x.url, '^.*/', ''), 255),
+    case lower(substring(x.url from '\.([A-Za-z0-9]+)$'))
+        when 'png' then 'image/png'
+        when 'jpg' then 'image/jpeg'
+        when 'jpeg' then 'image/jpeg'
+        when 'gif' then 'image/gif'
+        when 'webp' then 'image/webp'
+        when 'pdf' then 'application/pdf'
+        else 'application/octet-stream'
+    end,
+    r.created_time
+from completion_report r, unnest(r.images) with ordinality as x(url, n)
+where x.url <> ''
+order by r.id, x.n;
+
+alter table ticket_info drop column image;
+alter table completion_report drop column images;
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+alter table ticket_info drop column closed_time;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+alter table ticket_info add column closed_time timestamp;
+comment on column ticket_info.closed_time is '确认完工或超时自动关闭的时间，重新打开的期限从这里算';
+
+-- 已经关闭的工单取最后一次确认的时间，没有事件的用完工时间
+update ticket_info t set closed_time = coalesce(
+    (select max(e.created_time) from ticket_event e where e.ticket_id = t.id and e.event_type = 13),
+    t.finished_time
+)
+where t.state = 4;
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+alter table notification drop column email_next_attempt_time;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+alter table notification add column email_next_attempt_time timestamp null;
+comment on column notification.email_next_attempt_time is '邮件下次可以发送的时间，为空表示马上可以发，发送中和失败重试时往后推';
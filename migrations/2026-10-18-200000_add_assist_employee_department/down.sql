@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+alter table assist_employee_info drop column department_id;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+alter table assist_employee_info add column department_id integer null references operation_info (id);
+comment on column assist_employee_info.department_id is '接的时候占了哪个部门的名额，为空是以前接的，当时每个所在部门都占了一个';
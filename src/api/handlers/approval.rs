@@ -1,52 +1,103 @@
 use actix_web::{web, HttpRequest, HttpResponse};
+use diesel::{Connection, PgConnection};
 
 use crate::{
     api::{
         request::approval::{ApproveRejectTicketRequest, MGetApprovalLevelByCompanyRequest},
         response::approval::MGetApprovalLevelByCompanyResponse,
     },
-    error::{new_ok_error, AppError},
+    error::{new_conflict_error, new_forbidden_error, new_ok_error, AppError},
     models::{
         approval::{Approval, ApprovalWithTicket},
+        dispatch,
+        employee::Employee,
         ticket::Ticket,
     },
     utils::{
         auth::{get_current_employee, get_current_system},
         constant::{
-            APPROVE_RESULT_APPROVED, APPROVE_RESULT_REJECTED, TICKET_STATE_OPEN,
-            TICKET_STATE_REJECTED,
+            TicketState, APPROVE_RESULT_APPROVED, APPROVE_RESULT_REJECTED, APPROVE_RESULT_RETURNED,
         },
         response::{new_ok_response, CommonResponse},
     },
     AppState,
 };
 
+// 锁住工单后检查：同一系统、轮到这一级审批、还在审批中
+fn get_approvable_ticket(
+    conn: &mut PgConnection,
+    ticket_id: i32,
+    employee: &Employee,
+    approval_id: i32,
+) -> Result<Ticket, AppError> {
+    let ticket = Ticket::get_by_id_for_update(conn, ticket_id)?;
+    if ticket.system_id != employee.system_id {
+        return Err(new_forbidden_error("不能审批其他系统的工单"));
+    }
+    if !matches!(
+        ticket.state,
+        TicketState::Unapproved | TicketState::Approving
+    ) {
+        return Err(new_conflict_error(&format!(
+            "工单当前状态为{}，不在审批中",
+            ticket.state.name()
+        )));
+    }
+    if ticket.approval_id != Some(approval_id) {
+        return Err(new_forbidden_error("当前不是你所在的审批层级审批该工单"));
+    }
+    Ok(ticket)
+}
+
+// 空白的意见当作没写
+fn get_comment(form: &ApproveRejectTicketRequest) -> Result<Option<&str>, AppError> {
+    let comment = form
+        .comment
+        .as_deref()
+        .map(str::trim)
+        .filter(|x| !x.is_empty());
+    if comment.map_or(0, |x| x.chars().count()) > 500 {
+        return Err(new_ok_error("审批意见不能超过500字"));
+    }
+    Ok(comment)
+}
+
 // 审批一个工单
 pub async fn approve_ticket(
     app_state: web::Data<AppState>,
     req: HttpRequest,
-    form: web::Query<ApproveRejectTicketRequest>,
+    form: web::Json<ApproveRejectTicketRequest>,
 ) -> Result<HttpResponse, AppError> {
     let mut conn = app_state.conn()?;
     let employee = get_current_employee(&req, &mut conn)?;
     if let Some(approval_id) = employee.approval_id {
-        ApprovalWithTicket::create(
-            &mut conn,
-            form.ticket_id,
-            approval_id,
-            employee.id,
-            APPROVE_RESULT_APPROVED,
-        )?;
-        if !Ticket::update_next_current_approval_id(
-            &mut conn,
-            form.ticket_id,
-            employee.company_name,
-            employee.id,
-        )? {
-            // 如果能找到下一个审批的人，就还是审批状态
-            // 如果没有，就通过
-            Ticket::open(&mut conn, form.ticket_id)?;
-        }
+        let comment = get_comment(&form)?;
+        conn.transaction::<_, AppError, _>(|conn| {
+            get_approvable_ticket(conn, form.ticket_id, &employee, approval_id)?;
+            ApprovalWithTicket::create(
+                conn,
+                form.ticket_id,
+                approval_id,
+                employee.id,
+                APPROVE_RESULT_APPROVED,
+                comment,
+            )?;
+            if Ticket::update_next_current_approval_id(
+                conn,
+                form.ticket_id,
+                employee.company_name,
+                employee.id,
+            )? {
+                // 如果能找到下一个审批的人，就还是审批状态
+                Ticket::approve(conn, form.ticket_id, employee.id)?;
+            } else {
+                // 如果没有，就通过
+                Ticket::open(conn, form.ticket_id, employee.id)?;
+                dispatch::auto_dispatch(conn, form.ticket_id, None)?;
+            }
+            Ok(())
+        })?;
+        app_state.events.publish_ticket(&mut conn, form.ticket_id);
         Ok(HttpResponse::Ok().json(new_ok_response("已通过")))
     } else {
         Err(new_ok_error("你不是审批人"))
@@ -57,25 +108,63 @@ pub async fn approve_ticket(
 pub async fn reject_ticket(
     app_state: web::Data<AppState>,
     req: HttpRequest,
-    form: web::Query<ApproveRejectTicketRequest>,
+    form: web::Json<ApproveRejectTicketRequest>,
 ) -> Result<HttpResponse, AppError> {
     let mut conn = app_state.conn()?;
     let employee = get_current_employee(&req, &mut conn)?;
     if let Some(approval_id) = employee.approval_id {
-        ApprovalWithTicket::create(
-            &mut conn,
-            form.ticket_id,
-            approval_id,
-            employee.id,
-            APPROVE_RESULT_REJECTED,
-        )?;
-        Ticket::reject(&mut conn, form.ticket_id)?;
+        let comment = get_comment(&form)?;
+        conn.transaction::<_, AppError, _>(|conn| {
+            get_approvable_ticket(conn, form.ticket_id, &employee, approval_id)?;
+            ApprovalWithTicket::create(
+                conn,
+                form.ticket_id,
+                approval_id,
+                employee.id,
+                APPROVE_RESULT_REJECTED,
+                comment,
+            )?;
+            Ticket::reject(conn, form.ticket_id, employee.id)?;
+            Ok(())
+        })?;
+        app_state.events.publish_ticket(&mut conn, form.ticket_id);
         Ok(HttpResponse::Ok().json(new_ok_response("已驳回")))
     } else {
         Err(new_ok_error("你不是审批人"))
     }
 }
 
+// 退回给创建人修改，改完重新从第一级开始审批
+pub async fn return_ticket(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<ApproveRejectTicketRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    if let Some(approval_id) = employee.approval_id {
+        // 不写意见创建人不知道该改什么
+        let comment = get_comment(&form)?.ok_or_else(|| new_ok_error("退回修改需要填写意见"))?;
+        conn.transaction::<_, AppError, _>(|conn| {
+            get_approvable_ticket(conn, form.ticket_id, &employee, approval_id)?;
+            ApprovalWithTicket::create(
+                conn,
+                form.ticket_id,
+                approval_id,
+                employee.id,
+                APPROVE_RESULT_RETURNED,
+                Some(comment),
+            )?;
+            Ticket::send_back(conn, form.ticket_id, employee.id)?;
+            Ok(())
+        })?;
+        app_state.events.publish_ticket(&mut conn, form.ticket_id);
+        Ok(HttpResponse::Ok().json(new_ok_response("已退回修改")))
+    } else {
+        Err(new_ok_error("你不是审批人"))
+    }
+}
+
 pub async fn get_approval_levels_by_company(
     app_state: web::Data<AppState>,
     req: HttpRequest,
@@ -83,13 +172,13 @@ pub async fn get_approval_levels_by_company(
 ) -> Result<HttpResponse, AppError> {
     let mut conn = app_state.conn()?;
     let system = get_current_system(&req, &mut conn)?;
-    let company_name = if form.company.len() > 0 {
+    let company_name = if !form.company.is_empty() {
         Some(form.company.clone())
     } else {
         None
     };
     let mut approvals = Approval::mget_by_company(&mut conn, system.id, company_name)?;
-    if approvals.len() == 0 {
+    if approvals.is_empty() {
         approvals = Approval::mget_by_company(&mut conn, system.id, None)?;
     }
     let resp = MGetApprovalLevelByCompanyResponse {
@@ -97,3 +186,240 @@ pub async fn get_approval_levels_by_company(
     };
     Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use serde_json::json;
+
+    use diesel::prelude::*;
+
+    use crate::{
+        models::ticket::Ticket,
+        schema::notification,
+        utils::{
+            constant::{
+                TicketState, ACCOUNT_TYPE_APPLICANT, ACCOUNT_TYPE_APPROVER, ACCOUNT_TYPE_OPERATOR,
+                NOTIFICATION_KIND_DISPATCHED,
+            },
+            testing::{self, create_employee, create_system, create_ticket},
+        },
+    };
+
+    fn approve(ticket_id: i32) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/ticket/approve")
+            .set_json(json!({ "ticket_id": ticket_id }))
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_approve_ticket_authorization() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let other = create_system(&mut conn, true);
+        let (l1, l2) = (ts.approvals[0].id, ts.approvals[1].id);
+        let (applicant, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![],
+            TicketState::Unapproved,
+            None,
+        );
+        Ticket::update_approval_id(&mut conn, ticket.id, Some(l1)).unwrap();
+        let token = |conn: &mut _, system_id, approval_id| {
+            let (_, account) = create_employee(
+                conn,
+                system_id,
+                ACCOUNT_TYPE_APPROVER,
+                Some(approval_id),
+                vec![],
+            );
+            account.generate_token().unwrap()
+        };
+        let l1_token = token(&mut conn, ts.system.id, l1);
+        let l2_token = token(&mut conn, ts.system.id, l2);
+        let other_token = token(&mut conn, other.system.id, other.approvals[0].id);
+
+        // 其他系统的审批人
+        let (status, _) = testing::call(&pool, approve(ticket.id), &other_token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        // 还没轮到 L2
+        let (status, _) = testing::call(&pool, approve(ticket.id), &l2_token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let ticket = Ticket::get_by_id(&mut conn, ticket.id).unwrap();
+        assert_eq!(ticket.state, TicketState::Unapproved);
+
+        // 金额 500 超过 L1 的额度，L1 通过后交给 L2
+        let (status, body) = testing::call(&pool, approve(ticket.id), &l1_token).await;
+        assert!(!testing::is_error(status, &body));
+        let ticket = Ticket::get_by_id(&mut conn, ticket.id).unwrap();
+        assert_eq!(ticket.state, TicketState::Approving);
+        assert_eq!(ticket.approval_id, Some(l2));
+        let (status, _) = testing::call(&pool, approve(ticket.id), &l1_token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = testing::call(&pool, approve(ticket.id), &l2_token).await;
+        assert!(!testing::is_error(status, &body));
+        let ticket = Ticket::get_by_id(&mut conn, ticket.id).unwrap();
+        assert_eq!(ticket.state, TicketState::Open);
+
+        // 已经通过的工单不能再驳回
+        let req = test::TestRequest::post()
+            .uri("/ticket/reject")
+            .set_json(json!({ "ticket_id": ticket.id, "comment": "太贵了" }));
+        let (status, _) = testing::call(&pool, req, &l2_token).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_reject_ticket_comment() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let l1 = ts.approvals[0].id;
+        let (applicant, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let (approver, approver_account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPROVER,
+            Some(l1),
+            vec![],
+        );
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![],
+            TicketState::Unapproved,
+            None,
+        );
+        Ticket::update_approval_id(&mut conn, ticket.id, Some(l1)).unwrap();
+
+        let req = test::TestRequest::post()
+            .uri("/ticket/reject")
+            .set_json(json!({ "ticket_id": ticket.id, "comment": "  预算不足  " }));
+        let token = approver_account.generate_token().unwrap();
+        let (status, body) = testing::call(&pool, req, &token).await;
+        assert!(!testing::is_error(status, &body));
+
+        // 申请人能看到是谁、哪一级、为什么驳回
+        let req = test::TestRequest::get().uri(&format!("/ticket?ticket_id={}", ticket.id));
+        let token = account.generate_token().unwrap();
+        let (_, body) = testing::call(&pool, req, &token).await;
+        let approvals = &body["data"]["approvals"];
+        assert_eq!(approvals.as_array().unwrap().len(), 1);
+        assert_eq!(approvals[0]["approver"], approver.name);
+        assert_eq!(approvals[0]["approval_name"], "L1");
+        assert_eq!(approvals[0]["result"], 0);
+        assert_eq!(approvals[0]["comment"], "预算不足");
+        assert_eq!(body["data"]["check_name"], approver.name);
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_auto_dispatch_round_robin() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let (d1, d2) = (ts.departments[0].id, ts.departments[1].id);
+        let l2 = ts.approvals[1].id;
+        let (applicant, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let (_, approver) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPROVER,
+            Some(l2),
+            vec![d1],
+        );
+        let token = approver.generate_token().unwrap();
+        let (a, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        let (b, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        // 别的部门的不派
+        create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d2],
+        );
+
+        let strategy = || {
+            test::TestRequest::put()
+                .uri("/system/dispatch")
+                .set_json(json!({ "strategy": "round_robin" }))
+        };
+        let (status, _) = testing::call(&pool, strategy(), &token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = testing::call(&pool, strategy(), &ts.admin_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(body["data"]["strategy"], "round_robin");
+
+        let mut receivers = vec![];
+        for _ in 0..3 {
+            let ticket = create_ticket(
+                &mut conn,
+                ts.system.id,
+                applicant.id,
+                vec![d1],
+                TicketState::Approving,
+                None,
+            );
+            Ticket::update_approval_id(&mut conn, ticket.id, Some(l2)).unwrap();
+            let (status, body) = testing::call(&pool, approve(ticket.id), &token).await;
+            assert!(!testing::is_error(status, &body));
+            let ticket = Ticket::get_by_id(&mut conn, ticket.id).unwrap();
+            receivers.push((ticket.state, ticket.receiver_id));
+        }
+        // 两个人各派一个，都到上限之后第三个留在待接取
+        assert_eq!(
+            receivers,
+            vec![
+                (TicketState::Assigned, Some(a.id)),
+                (TicketState::Assigned, Some(b.id)),
+                (TicketState::Open, None),
+            ]
+        );
+        let dispatched: i64 = notification::table
+            .filter(notification::employee_id.eq(a.id))
+            .filter(notification::kind.eq(NOTIFICATION_KIND_DISPATCHED))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(dispatched, 1);
+    }
+}
@@ -1,10 +1,11 @@
-use crate::api::request::auth::RegisterAdminRequest;
-use crate::api::response::auth::RegisterAdminResponse;
+use crate::api::request::auth::{RegisterAdminRequest, UpdateEmailRequest};
+use crate::api::response::auth::{EmailSettingsResponse, RegisterAdminResponse};
 use crate::error::new_ok_error;
 use crate::models::employee::{Employee, InsertEmployee};
 use crate::models::system::System;
-use crate::utils::auth::{get_current_system, get_current_user};
+use crate::utils::auth::{get_current_employee, get_current_system, get_current_user};
 use crate::utils::constant::{ACCOUNT_TYPE_ADMIN, SEX_MALE};
+use crate::utils::mailer::normalize_email;
 use crate::utils::response::CommonResponse;
 use crate::{
     api::{request::auth::LoginRequest, response::auth::AccountResponse},
@@ -53,6 +54,24 @@ pub async fn get_myself(
     Ok(HttpResponse::Ok().json(resp))
 }
 
+// 设置自己接收邮件通知的地址，或者退订
+pub async fn update_email(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<UpdateEmailRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let email = normalize_email(form.email.as_deref())?;
+    let employee =
+        Employee::update_email(&mut conn, employee.id, email.as_deref(), form.email_opt_out)?;
+    let resp = EmailSettingsResponse {
+        email: employee.email,
+        email_opt_out: employee.email_opt_out,
+    };
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
 pub async fn register_admin(
     app_state: web::Data<AppState>,
     form: web::Json<RegisterAdminRequest>,
@@ -70,6 +89,8 @@ pub async fn register_admin(
             system_id: system.id,
             sex: SEX_MALE,
             company_name: None,
+            email: None,
+            max_wip: None,
         },
     )?;
     let (account, token) = Account::register(
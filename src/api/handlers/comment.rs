@@ -0,0 +1,235 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use diesel::{Connection, PgConnection};
+
+use crate::{
+    api::{
+        request::{comment::CreateCommentRequest, ticket::GetTicketByIDRequest},
+        response::comment::{CommentResponse, CommentsResponse},
+    },
+    error::{new_forbidden_error, new_ok_error, AppError},
+    models::{
+        attachment::{Attachment, AttachmentOwner},
+        comment::{self, Comment, InsertComment},
+        employee::Employee,
+        ticket::Ticket,
+    },
+    utils::{auth::get_current_employee, response::CommonResponse},
+    AppState,
+};
+
+// 只有工单参与人和系统管理员能看和发评论，返回能看评论的人
+fn check_viewer(
+    conn: &mut PgConnection,
+    ticket: &Ticket,
+    employee: &Employee,
+) -> Result<Vec<i32>, AppError> {
+    if ticket.system_id != employee.system_id {
+        return Err(new_forbidden_error("系统ID不匹配"));
+    }
+    let viewer_ids = comment::mget_viewer_ids(conn, ticket)?;
+    if !viewer_ids.contains(&employee.id) {
+        return Err(new_forbidden_error("只有工单参与人和管理员可以查看评论"));
+    }
+    Ok(viewer_ids)
+}
+
+pub async fn create_comment(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<CreateCommentRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let content = form.content.trim();
+    if content.is_empty() || content.chars().count() > 2000 {
+        return Err(new_ok_error("评论不能为空，不能超过2000字"));
+    }
+    let ticket = Ticket::get_by_id(&mut conn, form.ticket_id)?;
+    let viewer_ids = check_viewer(&mut conn, &ticket, &employee)?;
+    let comment = conn.transaction::<_, AppError, _>(|conn| {
+        let mentioned_ids =
+            comment::resolve_mentions(conn, ticket.system_id, content, &viewer_ids, employee.id)?;
+        let comment = Comment::create(
+            conn,
+            &ticket,
+            &employee,
+            InsertComment {
+                ticket_id: ticket.id,
+                employee_id: employee.id,
+                content,
+                mentioned_ids,
+                created_time: Utc::now().naive_local(),
+            },
+        )?;
+        Attachment::attach(
+            conn,
+            form.attachment_ids.as_deref().unwrap_or_default(),
+            employee.id,
+            AttachmentOwner {
+                ticket_id: Some(ticket.id),
+                comment_id: Some(comment.id),
+                ..Default::default()
+            },
+        )?;
+        Ok(comment)
+    })?;
+    let resp = CommentResponse::try_from((&mut conn, comment, employee.name))?;
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+pub async fn get_comments(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Query<GetTicketByIDRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let ticket = Ticket::get_by_id(&mut conn, form.ticket_id)?;
+    check_viewer(&mut conn, &ticket, &employee)?;
+    let comments = Comment::mget_by_ticket_id(&mut conn, ticket.id)?;
+    let resp = CommentsResponse::try_from((&mut conn, comments))?;
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use serde_json::json;
+
+    use crate::{
+        models::{approval::ApprovalWithTicket, notification::Notification},
+        utils::{
+            constant::{
+                TicketState, ACCOUNT_TYPE_APPLICANT, ACCOUNT_TYPE_APPROVER, ACCOUNT_TYPE_OPERATOR,
+                NOTIFICATION_KIND_MENTIONED,
+            },
+            testing::{self, create_attachment, create_employee, create_system, create_ticket},
+        },
+    };
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_ticket_comments() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let d1 = ts.departments[0].id;
+        let (applicant, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let applicant_token = account.generate_token().unwrap();
+        let (operator, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        let operator_token = account.generate_token().unwrap();
+        let (approver, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPROVER,
+            Some(ts.approvals[0].id),
+            vec![],
+        );
+        let approver_token = account.generate_token().unwrap();
+        let (outsider, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        let outsider_token = account.generate_token().unwrap();
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![d1],
+            TicketState::Assigned,
+            Some(operator.id),
+        );
+        ApprovalWithTicket::create(
+            &mut conn,
+            ticket.id,
+            ts.approvals[0].id,
+            approver.id,
+            1,
+            None,
+        )
+        .unwrap();
+        let post = |content: &str| {
+            test::TestRequest::post()
+                .uri("/ticket/comment")
+                .set_json(json!({ "ticket_id": ticket.id, "content": content }))
+        };
+        let list =
+            || test::TestRequest::get().uri(&format!("/ticket/comments?ticket_id={}", ticket.id));
+        let mentioned = |conn: &mut crate::AppConn, employee_id: i32| {
+            Notification::mget_by_employee_id(conn, employee_id, false, 100, 1)
+                .unwrap()
+                .into_iter()
+                .filter(|x| x.kind == NOTIFICATION_KIND_MENTIONED && x.ticket_id == ticket.id)
+                .count()
+        };
+
+        let (status, _) = testing::call(&pool, post("路过"), &outsider_token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = testing::call(&pool, list(), &outsider_token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = testing::call(&pool, post("  "), &applicant_token).await;
+        assert!(testing::is_error(status, &body));
+
+        // 看不到评论的人 @ 了也不通知，自己 @ 自己也不通知
+        let content = format!(
+            "@{} 什么时候能修好？抄送@{}，@{}",
+            operator.name, outsider.name, applicant.name
+        );
+        let (status, body) = testing::call(&pool, post(&content), &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(body["data"]["employee_name"], applicant.name.as_str());
+        let mentions = body["data"]["mentions"].as_array().unwrap();
+        assert_eq!(mentions.len(), 1);
+        assert_eq!(mentions[0]["employee_id"], operator.id);
+        assert_eq!(mentioned(&mut conn, operator.id), 1);
+        assert_eq!(mentioned(&mut conn, outsider.id), 0);
+        assert_eq!(mentioned(&mut conn, applicant.id), 0);
+
+        let content = format!("@{} 明天上午", approver.name);
+        let (status, body) = testing::call(&pool, post(&content), &operator_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(mentioned(&mut conn, approver.id), 1);
+
+        let photo = create_attachment(&mut conn, operator.id, "现场.png");
+        let req = test::TestRequest::post()
+            .uri("/ticket/comment")
+            .set_json(json!({
+                "ticket_id": ticket.id,
+                "content": "现场照片",
+                "attachment_ids": [photo.id],
+            }));
+        let (status, body) = testing::call(&pool, req, &operator_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(body["data"]["attachments"][0]["attachment_id"], photo.id);
+
+        for token in [&approver_token, &ts.admin_token] {
+            let (status, body) = testing::call(&pool, list(), token).await;
+            assert!(!testing::is_error(status, &body));
+            let comments = body["data"]["comments"].as_array().unwrap();
+            assert_eq!(comments.len(), 3);
+            assert_eq!(comments[0]["employee_id"], applicant.id);
+            assert_eq!(
+                comments[1]["content"],
+                "@".to_string() + &approver.name + " 明天上午"
+            );
+            assert_eq!(comments[1]["attachments"], json!([]));
+            assert_eq!(comments[2]["attachments"][0]["filename"], "现场.png");
+        }
+    }
+}
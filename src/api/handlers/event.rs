@@ -0,0 +1,205 @@
+use std::{pin::pin, time::Duration};
+
+use actix_web::{
+    rt,
+    web::{self, Bytes},
+    HttpRequest, HttpResponse,
+};
+use futures::{
+    future::{select, Either},
+    stream,
+};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    error::AppError,
+    models::department::EmployeeWithDepartments,
+    utils::{
+        auth::get_current_employee,
+        broadcast::{Subscriber, TicketUpdate},
+        constant::SSE_KEEPALIVE_SECS,
+    },
+    AppState,
+};
+
+fn to_event(update: &TicketUpdate) -> Bytes {
+    let data = serde_json::to_string(update).unwrap_or_default();
+    Bytes::from(format!("event: ticket\ndata: {}\n\n", data))
+}
+
+// 推送调用人能看到的工单变化，断线后前端自己重连并重新拉一次列表
+pub async fn get_events(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let subscriber = Subscriber {
+        employee_id: employee.id,
+        system_id: employee.system_id,
+        approval_id: employee.approval_id,
+        department_ids: EmployeeWithDepartments::mget_department_id_by_employee_id(
+            &mut conn,
+            employee.id,
+        )?,
+    };
+    // 连接在推送期间一直不占数据库连接
+    drop(conn);
+    let rx = app_state.events.subscribe();
+
+    let ready = stream::once(async {
+        Ok::<_, AppError>(Bytes::from_static(b"event: ready\ndata: {}\n\n"))
+    });
+    let updates = stream::unfold((rx, subscriber), |(mut rx, subscriber)| async move {
+        loop {
+            let received = {
+                let recv = pin!(rx.recv());
+                let keepalive = pin!(rt::time::sleep(Duration::from_secs(SSE_KEEPALIVE_SECS)));
+                match select(recv, keepalive).await {
+                    Either::Left((x, _)) => Some(x),
+                    Either::Right(_) => None,
+                }
+            };
+            let chunk = match received {
+                None => Bytes::from_static(b": keepalive\n\n"),
+                Some(Ok(update)) if subscriber.can_see(&update) => to_event(&update),
+                Some(Ok(_)) => continue,
+                // 处理太慢丢了消息，让前端重新拉一次
+                Some(Err(RecvError::Lagged(_))) => {
+                    Bytes::from_static(b"event: lagged\ndata: {}\n\n")
+                }
+                Some(Err(RecvError::Closed)) => return None,
+            };
+            return Some((Ok(chunk), (rx, subscriber)));
+        }
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(futures::StreamExt::chain(ready, updates)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::poll_fn, pin::Pin, time::Duration};
+
+    use actix_web::{body::MessageBody, rt, test, web, App};
+    use serde_json::json;
+
+    use crate::{
+        models::ticket::Ticket,
+        router,
+        utils::{
+            auth::Authorization,
+            broadcast::Broadcaster,
+            constant::{
+                TicketState, ACCOUNT_TYPE_APPLICANT, ACCOUNT_TYPE_APPROVER, ACCOUNT_TYPE_OPERATOR,
+            },
+            testing::{self, create_employee, create_system, create_ticket},
+        },
+        AppState,
+    };
+
+    // 读下一段 SSE，超时说明没有推送
+    async fn next_chunk(body: &mut Pin<Box<impl MessageBody>>) -> String {
+        let chunk = rt::time::timeout(
+            Duration::from_secs(5),
+            poll_fn(|cx| body.as_mut().poll_next(cx)),
+        )
+        .await
+        .expect("no event received");
+        let chunk = chunk.unwrap().ok().unwrap();
+        String::from_utf8(chunk.to_vec()).unwrap()
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_events_scoped_by_department() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let (d1, d2) = (ts.departments[0].id, ts.departments[1].id);
+        let l2 = ts.approvals[1].id;
+        let (applicant, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let token = |conn: &mut _, account_type, approval_id, department_ids| {
+            let (_, account) = create_employee(
+                conn,
+                ts.system.id,
+                account_type,
+                approval_id,
+                department_ids,
+            );
+            account.generate_token().unwrap()
+        };
+        let l2_token = token(&mut conn, ACCOUNT_TYPE_APPROVER, Some(l2), vec![]);
+        let d1_token = token(&mut conn, ACCOUNT_TYPE_OPERATOR, None, vec![d1]);
+        let d2_token = token(&mut conn, ACCOUNT_TYPE_OPERATOR, None, vec![d2]);
+        let mut tickets = vec![];
+        for department_id in [d1, d2] {
+            let ticket = create_ticket(
+                &mut conn,
+                ts.system.id,
+                applicant.id,
+                vec![department_id],
+                TicketState::Approving,
+                None,
+            );
+            Ticket::update_approval_id(&mut conn, ticket.id, Some(l2)).unwrap();
+            tickets.push(ticket.id);
+        }
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState {
+                    pool: pool.clone(),
+                    events: Broadcaster::default(),
+                }))
+                .wrap(Authorization)
+                .configure(router::routes),
+        )
+        .await;
+        let subscribe = |token: &str| {
+            test::TestRequest::get()
+                .uri("/events")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request()
+        };
+        let mut d1_body = Box::pin(
+            test::call_service(&app, subscribe(&d1_token))
+                .await
+                .into_body(),
+        );
+        let mut d2_body = Box::pin(
+            test::call_service(&app, subscribe(&d2_token))
+                .await
+                .into_body(),
+        );
+        assert!(next_chunk(&mut d1_body).await.starts_with("event: ready"));
+        assert!(next_chunk(&mut d2_body).await.starts_with("event: ready"));
+
+        for ticket_id in tickets.iter() {
+            let req = test::TestRequest::post()
+                .uri("/ticket/approve")
+                .insert_header(("Authorization", format!("Bearer {}", l2_token)))
+                .set_json(json!({ "ticket_id": ticket_id }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success());
+        }
+
+        // D2 的人看不到 D1 的工单，第一条就是自己部门的
+        for (body, ticket_id) in [(&mut d1_body, tickets[0]), (&mut d2_body, tickets[1])] {
+            let chunk = next_chunk(body).await;
+            let data = chunk.strip_prefix("event: ticket\ndata: ").unwrap().trim();
+            let data: serde_json::Value = serde_json::from_str(data).unwrap();
+            assert_eq!(data["ticket_id"], ticket_id);
+            assert_eq!(data["state"], json!(TicketState::Open));
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use diesel::{Connection, PgConnection};
+
+use crate::{
+    api::{
+        request::{
+            expense::{AddExpenseRequest, OverrunActionRequest},
+            ticket::GetTicketByIDRequest,
+        },
+        response::expense::{ExpenseSummaryResponse, PendingOverrunsResponse},
+    },
+    error::{new_conflict_error, new_forbidden_error, new_ok_error, AppError},
+    models::{
+        employee::Employee,
+        expense::{self, Expense, InsertExpense, Overrun},
+        ticket::Ticket,
+    },
+    utils::{
+        auth::get_current_employee,
+        constant::{TicketState, OVERRUN_STATE_PENDING},
+        money::Money,
+        response::{new_ok_response, CommonResponse},
+    },
+    AppState,
+};
+
+// 接受人处理过程中记一笔花费，超过阈值会提交超支审批
+pub async fn add_expense(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<AddExpenseRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let reason = form.reason.trim();
+    if reason.is_empty() || reason.chars().count() > 255 {
+        return Err(new_ok_error("用途不能为空，不能超过255字"));
+    }
+    let receipt = form
+        .receipt
+        .as_deref()
+        .map(str::trim)
+        .filter(|x| !x.is_empty());
+    let (overrun, currency) = conn.transaction::<_, AppError, _>(|conn| {
+        let ticket = Ticket::get_by_id_for_update(conn, form.ticket_id)?;
+        if ticket.receiver_id != Some(employee.id) {
+            return Err(new_forbidden_error("只有接受人可以记录花费"));
+        }
+        if ticket.state != TicketState::Assigned {
+            return Err(new_conflict_error(&format!(
+                "工单当前状态为{}，不能记录花费",
+                ticket.state.name()
+            )));
+        }
+        Expense::mcreate(
+            conn,
+            vec![InsertExpense {
+                ticket_id: ticket.id,
+                employee_id: employee.id,
+                report_id: None,
+                reason,
+                amount: form.amount.clone(),
+                receipt,
+                created_time: Utc::now().naive_local(),
+            }],
+        )?;
+        Ok((expense::check_overrun(conn, ticket.id)?, ticket.currency))
+    })?;
+    app_state.events.publish_ticket(&mut conn, form.ticket_id);
+    let resp = match overrun {
+        Some(x) if x.state == OVERRUN_STATE_PENDING => new_ok_response(&format!(
+            "已记录，超出预算 {}，已提交审批",
+            Money::new(x.amount, &currency)
+        )),
+        _ => new_ok_response("已记录"),
+    };
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+pub async fn get_expense_summary(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Query<GetTicketByIDRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let ticket = Ticket::get_by_id(&mut conn, form.ticket_id)?;
+    if employee.system_id == ticket.system_id {
+        let resp = ExpenseSummaryResponse::try_from((&mut conn, ticket))?;
+        Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+    } else {
+        Err(new_ok_error("系统ID不匹配"))
+    }
+}
+
+// 轮到我这一级审批的超支
+pub async fn get_pending_overruns(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let approval_id = employee
+        .approval_id
+        .ok_or_else(|| new_ok_error("你不是审批人"))?;
+    let overruns =
+        Overrun::mget_pending_by_approval_id(&mut conn, employee.system_id, approval_id)?;
+    let resp = PendingOverrunsResponse::try_from((&mut conn, overruns))?;
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+// 先锁工单再锁超支审批，检查是不是轮到这一级
+fn get_approvable_overrun(
+    conn: &mut PgConnection,
+    overrun_id: i32,
+    employee: &Employee,
+) -> Result<Overrun, AppError> {
+    let approval_id = employee
+        .approval_id
+        .ok_or_else(|| new_ok_error("你不是审批人"))?;
+    let ticket_id = Overrun::get_by_id(conn, overrun_id)?.ticket_id;
+    let ticket = Ticket::get_by_id_for_update(conn, ticket_id)?;
+    if ticket.system_id != employee.system_id {
+        return Err(new_forbidden_error("不能审批其他系统的工单"));
+    }
+    let overrun = Overrun::get_by_id_for_update(conn, overrun_id)?;
+    if overrun.state != OVERRUN_STATE_PENDING {
+        return Err(new_conflict_error("超支审批已经结束"));
+    }
+    if overrun.approval_id != Some(approval_id) {
+        return Err(new_forbidden_error("当前不是你所在的审批层级审批该超支"));
+    }
+    Ok(overrun)
+}
+
+fn get_comment(form: &OverrunActionRequest) -> Result<Option<&str>, AppError> {
+    let comment = form
+        .comment
+        .as_deref()
+        .map(str::trim)
+        .filter(|x| !x.is_empty());
+    if comment.map_or(0, |x| x.chars().count()) > 500 {
+        return Err(new_ok_error("审批意见不能超过500字"));
+    }
+    Ok(comment)
+}
+
+pub async fn approve_overrun(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<OverrunActionRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let comment = get_comment(&form)?;
+    let overrun = conn.transaction::<_, AppError, _>(|conn| {
+        let overrun = get_approvable_overrun(conn, form.overrun_id, &employee)?;
+        Overrun::approve(conn, &overrun, &employee, comment)
+    })?;
+    app_state
+        .events
+        .publish_ticket(&mut conn, overrun.ticket_id);
+    Ok(HttpResponse::Ok().json(new_ok_response("已通过")))
+}
+
+pub async fn reject_overrun(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<OverrunActionRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let comment = get_comment(&form)?;
+    let overrun = conn.transaction::<_, AppError, _>(|conn| {
+        let overrun = get_approvable_overrun(conn, form.overrun_id, &employee)?;
+        Overrun::reject(conn, &overrun, &employee, comment)
+    })?;
+    app_state
+        .events
+        .publish_ticket(&mut conn, overrun.ticket_id);
+    Ok(HttpResponse::Ok().json(new_ok_response("已驳回")))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use serde_json::json;
+
+    use crate::{
+        models::{expense::Overrun, ticket::Ticket},
+        utils::{
+            constant::{
+                TicketState, ACCOUNT_TYPE_APPLICANT, ACCOUNT_TYPE_APPROVER, ACCOUNT_TYPE_OPERATOR,
+                OVERRUN_STATE_APPROVED, OVERRUN_STATE_PENDING, OVERRUN_STATE_REJECTED,
+            },
+            money::Amount,
+            testing::{self, create_employee, create_system, create_ticket},
+        },
+    };
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_overrun_approval() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let d1 = ts.departments[0].id;
+        let (applicant, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let applicant_token = account.generate_token().unwrap();
+        let (operator, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        let operator_token = account.generate_token().unwrap();
+        let token = |conn: &mut _, approval_id| {
+            let (_, account) = create_employee(
+                conn,
+                ts.system.id,
+                ACCOUNT_TYPE_APPROVER,
+                Some(approval_id),
+                vec![],
+            );
+            account.generate_token().unwrap()
+        };
+        let l1_token = token(&mut conn, ts.approvals[0].id);
+        let l2_token = token(&mut conn, ts.approvals[1].id);
+        // 预算 500，默认超过 10% 要审批
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![d1],
+            TicketState::Assigned,
+            Some(operator.id),
+        );
+        let add = |ticket_id: i32, amount: i32| {
+            test::TestRequest::post().uri("/expense").set_json(json!({
+                "ticket_id": ticket_id,
+                "reason": "材料",
+                "amount": amount,
+                "receipt": "/static/receipt.png",
+            }))
+        };
+        let summary = |ticket_id: i32| {
+            test::TestRequest::get().uri(&format!("/expense?ticket_id={}", ticket_id))
+        };
+        let overrun_action = |uri: &str, overrun_id: i32| {
+            test::TestRequest::post()
+                .uri(uri)
+                .set_json(json!({ "overrun_id": overrun_id }))
+        };
+
+        let (status, _) = testing::call(&pool, add(ticket.id, 300), &applicant_token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        // 金额格式不对直接拒绝
+        for amount in [json!("12.345"), json!("1e3"), json!(-1), json!("abc")] {
+            let req = test::TestRequest::post().uri("/expense").set_json(json!({
+                "ticket_id": ticket.id,
+                "reason": "材料",
+                "amount": amount,
+            }));
+            let (status, _) = testing::call(&pool, req, &operator_token).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+        let (status, body) = testing::call(&pool, add(ticket.id, 300), &operator_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert!(Overrun::get_pending_by_ticket_id(&mut conn, ticket.id)
+            .unwrap()
+            .is_none());
+        let (status, body) = testing::call(&pool, add(ticket.id, 400), &operator_token).await;
+        assert!(!testing::is_error(status, &body));
+        let overrun = Overrun::get_pending_by_ticket_id(&mut conn, ticket.id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(overrun.amount, Amount::from(200));
+        assert_eq!(overrun.approval_id, Some(ts.approvals[0].id));
+
+        let (status, body) = testing::call(&pool, summary(ticket.id), &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(body["data"]["currency"], "CNY");
+        assert_eq!(body["data"]["budget"], "500.00");
+        assert_eq!(body["data"]["actual"], "700.00");
+        assert_eq!(body["data"]["variance"], "200.00");
+        assert_eq!(body["data"]["variance_percent"], 40.0);
+        assert_eq!(body["data"]["expenses"].as_array().unwrap().len(), 2);
+
+        // 完工之后超支没批完不能确认
+        let req = test::TestRequest::post().uri("/ticket/finish").set_json(
+            json!({ "ticket_id": ticket.id, "notes": "修好了", "costs": [{ "reason": "人工", "amount": 50 }] }),
+        );
+        let (status, body) = testing::call(&pool, req, &operator_token).await;
+        assert!(!testing::is_error(status, &body));
+        let overrun = Overrun::get_by_id(&mut conn, overrun.id).unwrap();
+        assert_eq!(overrun.amount, Amount::from(250));
+        let confirm = || {
+            test::TestRequest::post()
+                .uri("/ticket/confirm")
+                .set_json(json!({ "ticket_id": ticket.id }))
+        };
+        let (status, _) = testing::call(&pool, confirm(), &applicant_token).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // 超过 L1 的额度，L1 批完交给 L2
+        let (status, _) = testing::call(
+            &pool,
+            overrun_action("/expense/overrun/approve", overrun.id),
+            &l2_token,
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let req = test::TestRequest::get().uri("/expense/overrun");
+        let (status, body) = testing::call(&pool, req, &l1_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(body["data"]["overruns"][0]["overrun_id"], overrun.id);
+        assert_eq!(body["data"]["overruns"][0]["actual"], "750.00");
+        for token in [&l1_token, &l2_token] {
+            let (status, body) = testing::call(
+                &pool,
+                overrun_action("/expense/overrun/approve", overrun.id),
+                token,
+            )
+            .await;
+            assert!(!testing::is_error(status, &body));
+        }
+        let overrun = Overrun::get_by_id(&mut conn, overrun.id).unwrap();
+        assert_eq!(overrun.state, OVERRUN_STATE_APPROVED);
+        assert_eq!(overrun.approval_id, None);
+        let (status, body) = testing::call(&pool, summary(ticket.id), &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(body["data"]["budget"], "750.00");
+        assert_eq!(body["data"]["approved_overrun"], "250.00");
+        assert_eq!(body["data"]["variance"], "0.00");
+        let (status, body) = testing::call(&pool, confirm(), &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(
+            Ticket::get_by_id(&mut conn, ticket.id).unwrap().state,
+            TicketState::Closed
+        );
+
+        // 阈值设成 0，超一分钱都要审批，驳回之后预算不变
+        let req = test::TestRequest::put()
+            .uri("/system/overrun")
+            .set_json(json!({ "percent": 0 }));
+        let (status, body) = testing::call(&pool, req, &ts.admin_token).await;
+        assert!(!testing::is_error(status, &body));
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![d1],
+            TicketState::Assigned,
+            Some(operator.id),
+        );
+        let (status, body) = testing::call(&pool, add(ticket.id, 500), &operator_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert!(Overrun::get_pending_by_ticket_id(&mut conn, ticket.id)
+            .unwrap()
+            .is_none());
+        let req = test::TestRequest::post().uri("/expense").set_json(json!({
+            "ticket_id": ticket.id,
+            "reason": "螺丝",
+            "amount": "0.01",
+        }));
+        let (status, body) = testing::call(&pool, req, &operator_token).await;
+        assert!(!testing::is_error(status, &body));
+        let overrun = Overrun::get_pending_by_ticket_id(&mut conn, ticket.id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(overrun.state, OVERRUN_STATE_PENDING);
+        assert_eq!(overrun.amount, "0.01".parse().unwrap());
+        let (status, body) = testing::call(
+            &pool,
+            overrun_action("/expense/overrun/reject", overrun.id),
+            &l1_token,
+        )
+        .await;
+        assert!(!testing::is_error(status, &body));
+        let overrun = Overrun::get_by_id(&mut conn, overrun.id).unwrap();
+        assert_eq!(overrun.state, OVERRUN_STATE_REJECTED);
+        let (status, _) = testing::call(
+            &pool,
+            overrun_action("/expense/overrun/approve", overrun.id),
+            &l1_token,
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, body) = testing::call(&pool, summary(ticket.id), &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(body["data"]["budget"], "500.00");
+        assert_eq!(body["data"]["variance"], "0.01");
+    }
+}
@@ -10,6 +10,7 @@ use crate::{
     models::{approval::Approval, ticket::Ticket},
     utils::{
         auth::{get_current_employee, get_current_system},
+        money::Amount,
         response::CommonResponse,
     },
     AppState,
@@ -41,7 +42,9 @@ pub async fn get_pie_chart_data(
             resp.approving += temp.approving;
             resp.available += temp.available;
             resp.received += temp.received;
+            resp.awaiting_confirmation += temp.awaiting_confirmation;
             resp.closed += temp.closed;
+            resp.reopened += temp.reopened;
         }
         Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
     } else {
@@ -59,13 +62,13 @@ pub async fn get_bar_chart_data(
 
     let date = form.date.clone();
 
-    let _start_times = vec![
+    let _start_times = [
         "0:00:00", "4:00:00", "8:00:00", "12:00:00", "16:00:00", "20:00:00",
     ];
-    let end_times = vec![
+    let end_times = [
         "3:59:59", "7:59:59", "11:59:59", "15:59:59", "21:59:59", "23:59:59",
     ];
-    let periods = vec![
+    let periods = [
         "0:00-4:00",
         "4:00-8:00",
         "8:00-12:00",
@@ -109,7 +112,7 @@ pub async fn get_bar_chart_data(
                     Ticket::get_bar_chart_data(&mut conn, system.id, now, weekday as i32, None)?;
                 resp.push(state);
                 weekday = weekday.pred();
-                now = now - chrono::Duration::days(1);
+                now -= chrono::Duration::days(1);
             }
             Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
         }
@@ -126,10 +129,10 @@ pub async fn get_table(
     let mut conn = app_state.conn()?;
     let system = get_current_system(&req, &mut conn)?;
     let employee = get_current_employee(&req, &mut conn)?;
-    if let Some(approval_id) = employee.approval_id {
+    if employee.approval_id.is_some() {
         let mut approvals = Approval::mget_by_company(&mut conn, system.id, employee.company_name)?;
         approvals.sort_by(|a, b| a.amount.cmp(&b.amount));
-        let mut ranges = vec![0];
+        let mut ranges = vec![Amount::zero()];
         for approval in approvals.into_iter() {
             ranges.push(approval.amount);
         }
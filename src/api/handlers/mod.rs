@@ -1,7 +1,13 @@
 pub mod approval;
 pub mod auth;
+pub mod comment;
 pub mod department;
+pub mod event;
+pub mod expense;
 pub mod figure;
+pub mod notification;
+pub mod sla;
 pub mod system;
 pub mod ticket;
 pub mod upload;
+pub mod webhook;
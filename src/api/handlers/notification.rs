@@ -0,0 +1,157 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::{
+    api::{
+        request::notification::{MGetNotificationByPageRequest, ReadNotificationRequest},
+        response::notification::{MGetNotificationByPageResponse, ReadNotificationResponse},
+    },
+    error::{new_ok_error, AppError},
+    models::notification::Notification,
+    utils::{auth::get_current_employee, response::CommonResponse},
+    AppState,
+};
+
+pub async fn get_notifications_by_page(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Query<MGetNotificationByPageRequest>,
+) -> Result<HttpResponse, AppError> {
+    if form.size <= 0 || form.page <= 0 {
+        return Err(new_ok_error("分页参数不合法"));
+    }
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let (total, unread) = Notification::get_count_by_employee_id(&mut conn, employee.id)?;
+    let notifications = Notification::mget_by_employee_id(
+        &mut conn,
+        employee.id,
+        form.unread_only.unwrap_or(false),
+        form.size,
+        form.page,
+    )?;
+    let resp = MGetNotificationByPageResponse {
+        total,
+        unread,
+        notifications: notifications.into_iter().map(Into::into).collect(),
+    };
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+pub async fn read_notifications(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<ReadNotificationRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let updated = Notification::mark_read(&mut conn, employee.id, form.ids.as_deref())?;
+    let resp = ReadNotificationResponse { updated };
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+    use serde_json::{json, Value};
+
+    use crate::utils::{
+        constant::{
+            ACCOUNT_TYPE_APPLICANT, ACCOUNT_TYPE_APPROVER, ACCOUNT_TYPE_OPERATOR,
+            NOTIFICATION_KIND_APPROVAL_PENDING, NOTIFICATION_KIND_APPROVED,
+            NOTIFICATION_KIND_AVAILABLE,
+        },
+        testing::{self, create_employee, create_system},
+    };
+
+    fn kinds(body: &Value) -> Vec<i64> {
+        body["data"]["notifications"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x["kind"].as_i64().unwrap())
+            .collect()
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_ticket_notifications() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let (l1, l2) = (ts.approvals[0].id, ts.approvals[1].id);
+        let d1 = &ts.departments[0];
+        let d2 = &ts.departments[1];
+        let token = |conn: &mut _, account_type, approval_id, department_ids| {
+            let (_, account) = create_employee(
+                conn,
+                ts.system.id,
+                account_type,
+                approval_id,
+                department_ids,
+            );
+            account.generate_token().unwrap()
+        };
+        let applicant = token(&mut conn, ACCOUNT_TYPE_APPLICANT, None, vec![]);
+        let l1_token = token(&mut conn, ACCOUNT_TYPE_APPROVER, Some(l1), vec![]);
+        let l2_token = token(&mut conn, ACCOUNT_TYPE_APPROVER, Some(l2), vec![]);
+        let d1_operator = token(&mut conn, ACCOUNT_TYPE_OPERATOR, None, vec![d1.id]);
+        let d2_operator = token(&mut conn, ACCOUNT_TYPE_OPERATOR, None, vec![d2.id]);
+        let list = || test::TestRequest::get().uri("/notification?size=10&page=1");
+
+        // 金额 500，要 L1 和 L2 两级审批
+        let req = test::TestRequest::post().uri("/ticket").set_json(json!({
+            "title": "标题",
+            "address": "地址",
+            "reason": "理由",
+            "funds": [{ "reason": "材料", "amount": 500 }],
+            "departments": [d1.department_name],
+        }));
+        let (status, body) = testing::call(&pool, req, &applicant).await;
+        assert!(!testing::is_error(status, &body));
+        let ticket_id = body["ticket_id"].as_i64().unwrap();
+
+        let (_, body) = testing::call(&pool, list(), &l1_token).await;
+        assert_eq!(
+            kinds(&body),
+            vec![NOTIFICATION_KIND_APPROVAL_PENDING as i64]
+        );
+        let (_, body) = testing::call(&pool, list(), &l2_token).await;
+        assert!(kinds(&body).is_empty());
+
+        for t in [&l1_token, &l2_token] {
+            let req = test::TestRequest::post()
+                .uri("/ticket/approve")
+                .set_json(json!({ "ticket_id": ticket_id }));
+            let (status, body) = testing::call(&pool, req, t).await;
+            assert!(!testing::is_error(status, &body));
+        }
+        let (_, body) = testing::call(&pool, list(), &l2_token).await;
+        assert_eq!(
+            kinds(&body),
+            vec![NOTIFICATION_KIND_APPROVAL_PENDING as i64]
+        );
+        let (_, body) = testing::call(&pool, list(), &applicant).await;
+        assert_eq!(kinds(&body), vec![NOTIFICATION_KIND_APPROVED as i64]);
+        let (_, body) = testing::call(&pool, list(), &d2_operator).await;
+        assert!(kinds(&body).is_empty());
+        let (_, body) = testing::call(&pool, list(), &d1_operator).await;
+        assert_eq!(kinds(&body), vec![NOTIFICATION_KIND_AVAILABLE as i64]);
+        assert_eq!(body["data"]["unread"], 1);
+        let id = body["data"]["notifications"][0]["id"].clone();
+
+        // 不能把别人的通知标为已读
+        let read = |ids: Value| {
+            test::TestRequest::post()
+                .uri("/notification/read")
+                .set_json(json!({ "ids": ids }))
+        };
+        let (_, body) = testing::call(&pool, read(json!([id])), &d2_operator).await;
+        assert_eq!(body["data"]["updated"], 0);
+        let (_, body) = testing::call(&pool, read(json!([id])), &d1_operator).await;
+        assert_eq!(body["data"]["updated"], 1);
+        let (_, body) = testing::call(&pool, list(), &d1_operator).await;
+        assert_eq!(body["data"]["unread"], 0);
+        assert_eq!(body["data"]["total"], 1);
+        assert_eq!(body["data"]["notifications"][0]["is_read"], true);
+    }
+}
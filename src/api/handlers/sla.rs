@@ -0,0 +1,195 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::{
+    api::{
+        request::sla::{CreateSlaPolicyRequest, DeleteSlaPolicyRequest},
+        response::sla::{MGetSlaPolicyResponse, SlaPolicyResponse},
+    },
+    error::{new_forbidden_error, new_ok_error, AppError},
+    models::{
+        department::Department,
+        sla::{InsertSlaPolicy, SlaPolicy},
+    },
+    utils::{
+        auth::{get_current_system, is_system_admin},
+        constant::TicketState,
+        response::{new_ok_response, CommonResponse},
+    },
+    AppState,
+};
+
+pub async fn list_sla_policies(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let system = get_current_system(&req, &mut conn)?;
+    let policies = SlaPolicy::mget_by_system_id(&mut conn, system.id)?;
+    let mut ret = vec![];
+    for policy in policies.into_iter() {
+        ret.push(SlaPolicyResponse::try_from((&mut conn, policy))?);
+    }
+    let resp = MGetSlaPolicyResponse { policies: ret };
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+pub async fn create_sla_policy(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<CreateSlaPolicyRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    if !is_system_admin(&req, &mut conn)? {
+        return Err(new_forbidden_error("只有管理员可以配置 SLA"));
+    }
+    let system = get_current_system(&req, &mut conn)?;
+    // 终态的工单不需要时限
+    if !matches!(
+        form.state,
+        TicketState::Unapproved
+            | TicketState::Approving
+            | TicketState::Open
+            | TicketState::Assigned
+    ) {
+        return Err(new_ok_error(&format!(
+            "{}状态的工单不需要配置 SLA",
+            form.state.name()
+        )));
+    }
+    if form.warning_hours <= 0 || form.warning_hours > form.overdue_hours {
+        return Err(new_ok_error("预警时间要大于 0 且不能晚于超时时间"));
+    }
+    if let (Some(min), Some(max)) = (&form.min_amount, &form.max_amount) {
+        if min >= max {
+            return Err(new_ok_error("金额下限要小于上限"));
+        }
+    }
+    let department_id = match form.department_name.as_deref() {
+        Some(name) if !name.is_empty() => {
+            Some(Department::get_by_name(&mut conn, name, system.id)?.id)
+        }
+        _ => None,
+    };
+    let policy = SlaPolicy::create(
+        &mut conn,
+        InsertSlaPolicy {
+            system_id: system.id,
+            state: form.state,
+            department_id,
+            min_amount: form.min_amount.clone(),
+            max_amount: form.max_amount.clone(),
+            warning_hours: form.warning_hours,
+            overdue_hours: form.overdue_hours,
+        },
+    )?;
+    let resp = SlaPolicyResponse::try_from((&mut conn, policy))?;
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+pub async fn delete_sla_policy(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Query<DeleteSlaPolicyRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    if !is_system_admin(&req, &mut conn)? {
+        return Err(new_forbidden_error("只有管理员可以配置 SLA"));
+    }
+    let system = get_current_system(&req, &mut conn)?;
+    if SlaPolicy::delete(&mut conn, system.id, form.id)? == 0 {
+        return Err(new_ok_error("SLA 规则不存在"));
+    }
+    Ok(HttpResponse::Ok().json(new_ok_response("已删除")))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use chrono::Duration;
+    use diesel::prelude::*;
+    use serde_json::json;
+
+    use crate::{
+        schema::ticket_info,
+        utils::{
+            constant::{TicketState, ACCOUNT_TYPE_APPLICANT, ACCOUNT_TYPE_OPERATOR},
+            testing::{self, create_employee, create_system, create_ticket},
+        },
+    };
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_alarm_page_uses_sla_policy() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let (d1, d2) = (ts.departments[0].id, ts.departments[1].id);
+        let (applicant, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let (operator, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+
+        let policy = json!({
+            "state": 2,
+            "department_name": "D1",
+            "warning_hours": 1,
+            "overdue_hours": 2,
+        });
+        let req = test::TestRequest::post().uri("/sla").set_json(&policy);
+        let token = account.generate_token().unwrap();
+        let (status, _) = testing::call(&pool, req, &token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let req = test::TestRequest::post().uri("/sla").set_json(&policy);
+        let (status, body) = testing::call(&pool, req, &ts.admin_token).await;
+        assert!(!testing::is_error(status, &body));
+
+        let mut ticket = |departments, state, receiver_id, hours| {
+            let t = create_ticket(
+                &mut conn,
+                ts.system.id,
+                applicant.id,
+                departments,
+                state,
+                receiver_id,
+            );
+            diesel::update(ticket_info::table.find(t.id))
+                .set(
+                    ticket_info::created_time
+                        .eq(chrono::Utc::now().naive_utc() - Duration::hours(hours)),
+                )
+                .execute(&mut conn)
+                .unwrap();
+            t.id
+        };
+        // D1 的规则 2 小时超时，D2 用默认的 48/72 小时
+        let overdue = ticket(vec![d1], TicketState::Open, None, 3);
+        ticket(vec![d2], TicketState::Open, None, 3);
+        let warning = ticket(vec![d1], TicketState::Assigned, Some(operator.id), 50);
+
+        let page = |page| {
+            test::TestRequest::get().uri(&format!("/ticket/alarm/page?size=1&page={}", page))
+        };
+        let (_, body) = testing::call(&pool, page(1), &ts.admin_token).await;
+        assert_eq!(body["data"]["total"], 2);
+        let first = &body["data"]["tickets"][0];
+        assert_eq!(first["tid"], overdue);
+        assert_eq!(first["sla_level"], "overdue");
+        assert_eq!(first["remaining"], "-1");
+        let (_, body) = testing::call(&pool, page(2), &ts.admin_token).await;
+        assert_eq!(body["data"]["total"], 2);
+        let second = &body["data"]["tickets"][0];
+        assert_eq!(second["tid"], warning);
+        assert_eq!(second["sla_level"], "warning");
+        assert_eq!(second["remaining"], "21");
+    }
+}
@@ -1,26 +1,48 @@
 use actix_web::{web, HttpRequest, HttpResponse};
+use diesel::Connection;
 
 use crate::{
     api::{
-        request::system::{CreateSystemRequest, RegisterRequest},
-        response::system::{CreateEmployeeResponse, CreateSystemResponse},
+        request::system::{
+            CreateSystemRequest, LevelItem, RegisterRequest, UpdateCapacityRequest,
+            UpdateConfirmTimeoutRequest, UpdateDispatchRequest, UpdateLeadRequest,
+            UpdateOverrunThresholdRequest, UpdateReopenWindowRequest,
+        },
+        response::system::{
+            CapacityResponse, ConfirmTimeoutResponse, CreateEmployeeResponse, CreateSystemResponse,
+            DispatchResponse, OverrunThresholdResponse, ReopenWindowResponse,
+        },
     },
-    error::{new_ok_error, AppError},
+    error::{new_forbidden_error, new_ok_error, AppError},
     models::{
         account::Account,
         approval::{Approval, InsertApproval},
         department::{Department, EmployeeWithDepartments, InsertDepartment},
+        dispatch,
         employee::{Employee, InsertEmployee},
         system::System,
     },
     utils::{
         auth::{get_current_system, is_system_admin},
         constant::{SEX_FEMALE, SEX_MALE},
-        response::CommonResponse,
+        mailer::normalize_email,
+        money::{parse_currency, Amount},
+        response::{new_ok_response, CommonResponse},
     },
     AppState,
 };
 
+// 前端传的是字符串，格式不对直接报错
+fn parse_money_limit(level: &LevelItem) -> Result<Amount, AppError> {
+    match level.money_limit.parse::<Amount>() {
+        Ok(amount) if !amount.is_negative() => Ok(amount),
+        _ => Err(new_ok_error(&format!(
+            "审批层级{}的金额不合法：{}",
+            level.name, level.money_limit
+        ))),
+    }
+}
+
 pub async fn initialize_system(
     app_state: web::Data<AppState>,
     req: HttpRequest,
@@ -32,46 +54,65 @@ pub async fn initialize_system(
         if system.initialized != 0 {
             return Err(new_ok_error("系统已经被初始化"));
         }
-        if form.levels.len() <= 0 {
+        if form.levels.is_empty() {
             return Err(new_ok_error("至少要有一个审批层级"));
         }
-        let system = System::set_name(&mut conn, system.id, form.name.clone())?;
-        let mut departments = vec![];
-        for dep_item in form.departments.iter() {
-            let department = Department::create(
-                &mut conn,
-                InsertDepartment {
-                    department_name: &dep_item.name,
-                    system_id: system.id,
-                },
-            )?;
-            departments.push(department);
-        }
-        for level in form.levels.iter() {
-            Approval::create(
-                &mut conn,
-                InsertApproval {
-                    approval_name: &level.name,
-                    amount: level.money_limit.parse::<i32>().unwrap(),
-                    company: None,
-                    system_id: system.id,
-                },
-            )?;
-        }
+        let currency = match form.currency {
+            Some(ref currency) => parse_currency(currency)?,
+            None => system.currency,
+        };
+        let levels = form
+            .levels
+            .iter()
+            .map(|level| Ok((level, parse_money_limit(level)?)))
+            .collect::<Result<Vec<_>, AppError>>()?;
+        let mut special_levels = vec![];
         for special_level in form.special_levels.iter() {
             for level in special_level.special_level.iter() {
+                special_levels.push((special_level, level, parse_money_limit(level)?));
+            }
+        }
+        let (system, departments) = conn.transaction::<_, AppError, _>(|conn| {
+            System::set_name(conn, system.id, form.name.clone())?;
+            let system = System::set_currency(conn, system.id, &currency)?;
+            let mut departments = vec![];
+            for dep_item in form.departments.iter() {
+                let department = Department::create(
+                    conn,
+                    InsertDepartment {
+                        department_name: &dep_item.name,
+                        system_id: system.id,
+                    },
+                )?;
+                departments.push(department);
+            }
+            for (level, amount) in levels.into_iter() {
+                Approval::create(
+                    conn,
+                    InsertApproval {
+                        approval_name: &level.name,
+                        amount,
+                        company: None,
+                        system_id: system.id,
+                        currency: &system.currency,
+                    },
+                )?;
+            }
+            for (special_level, level, amount) in special_levels.into_iter() {
                 Approval::create(
-                    &mut conn,
+                    conn,
                     InsertApproval {
                         approval_name: &level.name,
-                        amount: level.money_limit.parse::<i32>().unwrap(),
+                        amount,
                         company: Some(&special_level.name),
                         system_id: system.id,
+                        currency: &system.currency,
                     },
                 )?;
             }
-        }
-        System::set_initialized(&mut conn, system.id, 1)?;
+            System::set_initialized(conn, system.id, 1)?;
+            Ok((system, departments))
+        })?;
         let resp = CreateSystemResponse::from((system, departments));
         Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
     } else {
@@ -93,7 +134,7 @@ pub async fn create_employee(
         return Err(app_error);
     }
     let system = get_current_system(&req, &mut conn)?;
-    let approval_id = if form.approval_name.len() > 0 {
+    let approval_id = if !form.approval_name.is_empty() {
         Approval::get_by_name(&mut conn, system.id, &form.approval_name)?.map(|x| x.id)
     } else {
         None
@@ -105,43 +146,324 @@ pub async fn create_employee(
             return Err(app_error);
         }
     };
-    let employee = Employee::create(
-        &mut conn,
-        InsertEmployee {
-            name: &form.name,
-            age: form.age.parse().unwrap(),
-            position: if form.position.len() > 0 {
-                Some(&form.position)
-            } else {
-                None
-            },
-            phone: &form.phone_number.trim(),
-            approval_id,
-            system_id: system.id,
-            sex,
-            company_name: if form.company.len() > 0 {
-                Some(form.company.as_str())
-            } else {
-                None
+    let email = normalize_email(form.email.as_deref())?;
+    if form.max_wip.is_some_and(|x| x <= 0) {
+        return Err(new_ok_error("同时处理的工单上限至少为1"));
+    }
+    let age = form
+        .age
+        .trim()
+        .parse::<i32>()
+        .ok()
+        .filter(|x| *x >= 0)
+        .ok_or_else(|| new_ok_error("年龄不合法"))?;
+    let (employee, account) = conn.transaction::<_, AppError, _>(|conn| {
+        let employee = Employee::create(
+            conn,
+            InsertEmployee {
+                name: &form.name,
+                age,
+                position: if !form.position.is_empty() {
+                    Some(&form.position)
+                } else {
+                    None
+                },
+                phone: form.phone_number.trim(),
+                approval_id,
+                system_id: system.id,
+                sex,
+                company_name: if !form.company.is_empty() {
+                    Some(form.company.as_str())
+                } else {
+                    None
+                },
+                email: email.as_deref(),
+                max_wip: form.max_wip,
             },
-        },
-    )?;
-    let (account, _) = Account::register(
-        &mut conn,
-        employee.id,
-        &form.account,
-        &form.password,
-        form.account_type,
-    )?;
-    for dep in form.departments.iter() {
-        let department = Department::get_by_name(&mut conn, dep, system.id)?;
-        log::info!(
-            "create, employee_id: {}, department_id: {}",
+        )?;
+        let (account, _) = Account::register(
+            conn,
             employee.id,
-            department.id
-        );
-        EmployeeWithDepartments::create(&mut conn, employee.id, department.id)?;
-    }
+            &form.account,
+            &form.password,
+            form.account_type,
+        )?;
+        for dep in form.departments.iter() {
+            let department = Department::get_by_name(conn, dep, system.id)?;
+            log::info!(
+                "create, employee_id: {}, department_id: {}",
+                employee.id,
+                department.id
+            );
+            EmployeeWithDepartments::create(conn, employee.id, department.id)?;
+        }
+        Ok((employee, account))
+    })?;
     let resp = CreateEmployeeResponse::from((employee, account));
     Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
 }
+
+// 调低到比手上的工单还少也可以，只是处理完之前不能再接
+pub async fn update_employee_capacity(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<UpdateCapacityRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    if !is_system_admin(&req, &mut conn)? {
+        return Err(new_forbidden_error("只有管理员可以设置工单上限"));
+    }
+    if form.max_wip <= 0 {
+        return Err(new_ok_error("同时处理的工单上限至少为1"));
+    }
+    let system = get_current_system(&req, &mut conn)?;
+    let (employee, wip) = conn.transaction::<_, AppError, _>(|conn| {
+        let employee = Employee::get_by_id_for_update(conn, form.employee_id)?;
+        if employee.system_id != system.id {
+            return Err(new_forbidden_error("系统ID不匹配"));
+        }
+        let employee = Employee::update_max_wip(conn, employee.id, form.max_wip)?;
+        let wip = Employee::count_wip(conn, employee.id)?;
+        Ok((employee, wip))
+    })?;
+    let resp = CapacityResponse::from((employee, wip));
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+// 部门负责人可以改派本部门的工单
+pub async fn update_department_lead(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<UpdateLeadRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    if !is_system_admin(&req, &mut conn)? {
+        return Err(new_forbidden_error("只有管理员可以设置部门负责人"));
+    }
+    let system = get_current_system(&req, &mut conn)?;
+    let department = Department::get_by_name(&mut conn, &form.department_name, system.id)?;
+    if EmployeeWithDepartments::set_lead(&mut conn, form.employee_id, department.id, form.is_lead)?
+        == 0
+    {
+        return Err(new_ok_error("该员工不在这个部门"));
+    }
+    Ok(HttpResponse::Ok().json(new_ok_response("设置成功")))
+}
+
+pub async fn get_dispatch_strategy(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    if !is_system_admin(&req, &mut conn)? {
+        return Err(new_forbidden_error("只有管理员可以查看派单方式"));
+    }
+    let system = get_current_system(&req, &mut conn)?;
+    let resp = DispatchResponse::from(system);
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+// 只影响之后审批通过或超时收回的工单，已经在待接取的不会补派
+pub async fn update_dispatch_strategy(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<UpdateDispatchRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    if !is_system_admin(&req, &mut conn)? {
+        return Err(new_forbidden_error("只有管理员可以设置派单方式"));
+    }
+    let strategy = dispatch::strategy_from_name(&form.strategy)
+        .ok_or_else(|| new_ok_error("不支持的派单方式"))?;
+    let system = get_current_system(&req, &mut conn)?;
+    let system = System::set_dispatch_strategy(&mut conn, system.id, strategy)?;
+    let resp = DispatchResponse::from(system);
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+pub async fn update_reopen_window(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<UpdateReopenWindowRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    if !is_system_admin(&req, &mut conn)? {
+        return Err(new_forbidden_error("只有管理员可以设置重新打开的期限"));
+    }
+    if form.hours < 0 {
+        return Err(new_ok_error("期限不能是负数"));
+    }
+    let system = get_current_system(&req, &mut conn)?;
+    let system = System::set_reopen_window_hours(&mut conn, system.id, form.hours)?;
+    let resp = ReopenWindowResponse {
+        hours: system.reopen_window_hours,
+    };
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+pub async fn update_confirm_timeout(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<UpdateConfirmTimeoutRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    if !is_system_admin(&req, &mut conn)? {
+        return Err(new_forbidden_error("只有管理员可以设置自动确认的期限"));
+    }
+    if form.hours <= 0 {
+        return Err(new_ok_error("期限必须大于0"));
+    }
+    let system = get_current_system(&req, &mut conn)?;
+    let system = System::set_confirm_timeout_hours(&mut conn, system.id, form.hours)?;
+    let resp = ConfirmTimeoutResponse {
+        hours: system.confirm_timeout_hours,
+    };
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+pub async fn update_overrun_threshold(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<UpdateOverrunThresholdRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    if !is_system_admin(&req, &mut conn)? {
+        return Err(new_forbidden_error("只有管理员可以设置超支审批的阈值"));
+    }
+    if form.percent < 0 {
+        return Err(new_ok_error("阈值不能是负数"));
+    }
+    let system = get_current_system(&req, &mut conn)?;
+    let system = System::set_overrun_threshold_percent(&mut conn, system.id, form.percent)?;
+    let resp = OverrunThresholdResponse {
+        percent: system.overrun_threshold_percent,
+    };
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+    use diesel::prelude::*;
+    use serde_json::json;
+
+    use crate::{
+        models::{approval::Approval, department::Department, system::System},
+        schema::{account_info, employee_info},
+        utils::{
+            constant::ACCOUNT_TYPE_OPERATOR,
+            money::Amount,
+            testing::{self, create_system, unique_name},
+        },
+    };
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_initialize_system_rollback() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, false);
+
+        // 审批层级名字超长，插 approval_info 时失败，此时部门已经插进去了
+        let req = test::TestRequest::post().uri("/system").set_json(json!({
+            "name": unique_name("new"),
+            "levels": [{ "key": 1, "name": "L".repeat(101), "money_limit": "100" }],
+            "departments": [{ "key": 1, "name": "D1" }],
+            "special_levels": [],
+        }));
+        let (status, body) = testing::call(&pool, req, &ts.admin_token).await;
+        assert!(testing::is_error(status, &body));
+
+        let system = System::get_by_id(&mut conn, ts.system.id).unwrap();
+        assert_eq!(system.initialized, 0);
+        assert_eq!(system.name, ts.system.name);
+        assert!(Department::mget_by_system(&mut conn, system.id)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_initialize_system_money() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, false);
+        let init = |money_limit: &str, currency: &str| {
+            test::TestRequest::post().uri("/system").set_json(json!({
+                "name": unique_name("new"),
+                "levels": [{ "key": 1, "name": "L1", "money_limit": "100" }],
+                "departments": [],
+                "special_levels": [{
+                    "key": 1,
+                    "name": "分公司",
+                    "special_level": [{ "key": 1, "name": "S1", "money_limit": money_limit }],
+                }],
+                "currency": currency,
+            }))
+        };
+
+        // 以前这里会 panic
+        for money_limit in ["", "一百", "1e3", "100.001", "-5"] {
+            let (status, body) =
+                testing::call(&pool, init(money_limit, "usd"), &ts.admin_token).await;
+            assert!(testing::is_error(status, &body), "{}", money_limit);
+        }
+        let (status, body) = testing::call(&pool, init("1500.5", "US$"), &ts.admin_token).await;
+        assert!(testing::is_error(status, &body));
+        let system = System::get_by_id(&mut conn, ts.system.id).unwrap();
+        assert_eq!(system.initialized, 0);
+        assert_eq!(system.currency, "CNY");
+
+        let (status, body) = testing::call(&pool, init("1500.5", "usd"), &ts.admin_token).await;
+        assert!(!testing::is_error(status, &body));
+        let system = System::get_by_id(&mut conn, ts.system.id).unwrap();
+        assert_eq!(system.currency, "USD");
+        let approval = Approval::get_by_name(&mut conn, system.id, "S1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(approval.amount, "1500.50".parse::<Amount>().unwrap());
+        assert_eq!(approval.currency, "USD");
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_create_employee_rollback() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+
+        // 部门不存在，此时员工和账号已经插进去了
+        let account = unique_name("account");
+        let name = unique_name("name");
+        let req = test::TestRequest::post()
+            .uri("/system/employee")
+            .set_json(json!({
+                "account": account,
+                "password": "password",
+                "name": name,
+                "age": "30",
+                "phone_number": "12345678901",
+                "sex": "male",
+                "account_type": ACCOUNT_TYPE_OPERATOR,
+                "position": "",
+                "company": "",
+                "departments": ["D1", "不存在的部门"],
+                "approval_name": "",
+            }));
+        let (status, body) = testing::call(&pool, req, &ts.admin_token).await;
+        assert!(testing::is_error(status, &body));
+
+        let accounts: i64 = account_info::table
+            .filter(account_info::account_name.eq(&account))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(accounts, 0);
+        let employees: i64 = employee_info::table
+            .filter(employee_info::name.eq(&name))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(employees, 0);
+    }
+}
@@ -1,32 +1,47 @@
-use std::vec;
-
 use actix_web::{web, HttpRequest, HttpResponse};
 use chrono::Utc;
+use diesel::{Connection, PgConnection};
 
 use crate::{
     api::{
         request::ticket::{
-            CreateAssistTicketRequest, CreateTicketRequest, FinishTicketRequest,
-            GetTicketByIDRequest, MGetTicketByPageRequest, TakeTicketRequest,
+            AssistActionRequest, CancelTicketRequest, ConfirmTicketRequest,
+            CreateAssistTicketRequest, CreateTicketRequest, DisputeTicketRequest,
+            FinishTicketRequest, GetTicketByIDRequest, MGetTicketByPageRequest,
+            ReassignTicketRequest, ReleaseTicketRequest, ReopenTicketRequest, TakeTicketRequest,
+            TicketFundRequest, UpdateTicketRequest,
         },
         response::ticket::{
-            AvailableTicketsResponse, CurrentTicketResponse, HistoryTicketsResponse,
-            MGetOverviewByPageResponse, PCTicketResponse,
+            AvailableAssistResponse, AvailableAssistsResponse, AvailableTicketsResponse,
+            CompletionReportsResponse, CurrentTicketResponse, CurrentTicketsResponse,
+            HistoryTicketsResponse, MGetOverviewByPageResponse, PCTicketResponse,
+            TicketTimelineResponse,
         },
     },
-    error::{new_ok_error, AppError},
+    error::{new_conflict_error, new_forbidden_error, new_ok_error, AppError},
     models::{
+        account::Account,
         assist::{Assist, AssistWithDepartments, AssistWithEmployees, InsertAssist},
+        attachment::{Attachment, AttachmentOwner},
         department::{Department, EmployeeWithDepartments},
+        dispatch,
         employee::Employee,
-        ticket::{Fund, InsertFund, InsertTicket, Ticket, TicketWithDepartments},
+        event::{InsertTicketEvent, TicketEvent},
+        expense::{self, Overrun},
+        notification::Notification,
+        report::{CompletionReport, InsertCompletionReport},
+        sla,
+        system::System,
+        ticket::{EditTicket, Fund, InsertFund, InsertTicket, Ticket, TicketWithDepartments},
     },
     utils::{
-        auth::{get_current_employee, get_current_system},
+        auth::{get_current_employee, get_current_system, is_system_admin},
         constant::{
-            EMPLOYEE_STATUS_AVAILABLE, EMPLOYEE_STATUS_UNAVAILABLE, TICKET_STATE_ASSIGNED,
-            TICKET_STATE_CLOSED, TICKET_STATE_OPEN,
+            TicketState, ACCOUNT_TYPE_OPERATOR, ASSIST_STATE_CANCELLED, ASSIST_STATE_FINISHED,
+            NOTIFICATION_KIND_REASSIGNED, TICKET_EVENT_ASSIST, TICKET_EVENT_CREATE,
+            TICKET_EVENT_REASSIGN, TICKET_EVENT_RELEASE,
         },
+        money::Amount,
         response::{new_ok_response, CommonResponse},
     },
     AppState,
@@ -39,8 +54,15 @@ pub async fn get_alarm_tickets_by_page(
 ) -> Result<HttpResponse, AppError> {
     let mut conn = app_state.conn()?;
     let system = get_current_system(&req, &mut conn)?;
-    let count = Ticket::get_alarm_count(&mut conn, system.id)?;
-    let tickets = Ticket::mget_alarm_by_page(&mut conn, system.id, form.size, form.page)?;
+    // 数量和分页用同一份结果，不会对不上
+    let alarms = sla::mget_alarm_tickets(&mut conn, system.id)?;
+    let count = alarms.len() as i64;
+    let tickets = alarms
+        .into_iter()
+        .map(|(ticket, _)| ticket)
+        .skip((form.page.max(1) - 1) as usize * form.size.max(0) as usize)
+        .take(form.size.max(0) as usize)
+        .collect();
     let resp = MGetOverviewByPageResponse::try_from((&mut conn, count, tickets))?;
     Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
 }
@@ -79,7 +101,7 @@ pub async fn get_history_tickets_by_page(
     let id = form
         .id
         .as_ref()
-        .filter(|x| x.len() > 0)
+        .filter(|x| !x.is_empty())
         .map(|x| x.parse::<i32>().unwrap());
     let employee = get_current_employee(&req, &mut conn)?;
     if let Some(approval_id) = employee.approval_id {
@@ -116,35 +138,127 @@ pub async fn create_ticket(
     let insert_ticket = InsertTicket {
         creator_id: employee.id,
         title: &form.title,
-        amount: 0,
+        amount: Amount::zero(),
         reason: &form.reason,
         address: &form.address,
-        image: form.image.as_ref().map(|x| x.as_str()),
         system_id: system.id,
         created_time: Utc::now().naive_utc(),
+        currency: &system.currency,
     };
-    let ticket = Ticket::create(&mut conn, insert_ticket)?;
-    let mut funds = vec![];
-    let mut sum = 0;
-    for f in form.funds.iter() {
-        let fund = Fund::create(
-            &mut conn,
-            InsertFund {
+    // 任何一步失败都要回滚，不能留下金额为 0 的孤儿工单
+    let ticket = conn.transaction::<_, AppError, _>(|conn| {
+        let ticket = Ticket::create(conn, insert_ticket)?;
+        let sum =
+            save_funds_and_departments(conn, ticket.id, system.id, &form.funds, &form.departments)?;
+        Ticket::update_amount(conn, ticket.id, sum)?;
+        Attachment::attach(
+            conn,
+            form.attachment_ids.as_deref().unwrap_or_default(),
+            employee.id,
+            AttachmentOwner {
+                ticket_id: Some(ticket.id),
+                ..Default::default()
+            },
+        )?;
+        Ticket::init_next_current_approval_id(conn, ticket.id, employee.company_name)?;
+        TicketEvent::create(
+            conn,
+            InsertTicketEvent {
                 ticket_id: ticket.id,
+                employee_id: Some(employee.id),
+                event_type: TICKET_EVENT_CREATE,
+                old_state: None,
+                new_state: ticket.state,
+                comment: None,
+                created_time: ticket.created_time,
+            },
+        )?;
+        Ok(ticket)
+    })?;
+    app_state.events.publish_ticket(&mut conn, ticket.id);
+    let resp = CurrentTicketResponse::try_from((&mut conn, ticket))?;
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+// 写入资金明细和需要的部门，返回总金额
+fn save_funds_and_departments(
+    conn: &mut PgConnection,
+    ticket_id: i32,
+    system_id: i32,
+    funds: &[TicketFundRequest],
+    departments: &[String],
+) -> Result<Amount, AppError> {
+    for f in funds.iter() {
+        Fund::create(
+            conn,
+            InsertFund {
+                ticket_id,
                 reason: &f.reason,
-                amount: f.amount,
+                amount: f.amount.clone(),
             },
         )?;
-        funds.push(fund);
-        sum += f.amount;
     }
-    for dep in form.departments.iter() {
-        let department = Department::get_by_name(&mut conn, dep, system.id)?;
-        let _ = TicketWithDepartments::create(&mut conn, ticket.id, department.id)?;
+    for dep in departments.iter() {
+        let department = Department::get_by_name(conn, dep, system_id)?;
+        TicketWithDepartments::create(conn, ticket_id, department.id)?;
     }
-    Ticket::update_amount(&mut conn, ticket.id, sum)?;
-    Ticket::init_next_current_approval_id(&mut conn, ticket.id, employee.company_name)?;
-    let resp = CurrentTicketResponse::from((&mut conn, ticket));
+    Fund::get_total_cost(conn, ticket_id)
+}
+
+// 创建人修改未审批或被退回的工单，审批从头开始
+pub async fn update_ticket(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<UpdateTicketRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let ticket = conn.transaction::<_, AppError, _>(|conn| {
+        let ticket = Ticket::get_by_id_for_update(conn, form.ticket_id)?;
+        if ticket.creator_id != employee.id {
+            return Err(new_forbidden_error("只有创建人可以修改工单"));
+        }
+        if !matches!(
+            ticket.state,
+            TicketState::Unapproved | TicketState::Returned
+        ) {
+            return Err(new_conflict_error(&format!(
+                "工单当前状态为{}，只有未审批或退回修改的工单可以修改",
+                ticket.state.name()
+            )));
+        }
+        Fund::delete_by_ticket_id(conn, ticket.id)?;
+        TicketWithDepartments::delete_by_ticket_id(conn, ticket.id)?;
+        let sum = save_funds_and_departments(
+            conn,
+            ticket.id,
+            ticket.system_id,
+            &form.funds,
+            &form.departments,
+        )?;
+        // 先重置审批级别，编辑事件才能通知到新的审批人
+        Ticket::update_amount(conn, ticket.id, sum)?;
+        Ticket::init_next_current_approval_id(conn, ticket.id, employee.company_name)?;
+        Ticket::edit(
+            conn,
+            ticket.id,
+            employee.id,
+            EditTicket {
+                title: &form.title,
+                reason: &form.reason,
+                address: &form.address,
+            },
+        )?;
+        Attachment::replace_by_ticket_id(
+            conn,
+            ticket.id,
+            form.attachment_ids.as_deref().unwrap_or_default(),
+            employee.id,
+        )?;
+        Ticket::get_by_id(conn, ticket.id)
+    })?;
+    app_state.events.publish_ticket(&mut conn, ticket.id);
+    let resp = CurrentTicketResponse::try_from((&mut conn, ticket))?;
     Ok(HttpResponse::Ok().json(resp))
 }
 
@@ -153,32 +267,61 @@ pub async fn create_assist(
     req: HttpRequest,
     form: web::Json<CreateAssistTicketRequest>,
 ) -> Result<HttpResponse, AppError> {
-    // 必须是一个接了主工单的人
+    // 必须是一个接了主工单的人，主工单还在处理中
     let mut conn = app_state.conn()?;
     let system = get_current_system(&req, &mut conn)?;
     let employee = get_current_employee(&req, &mut conn)?;
-    let ticket = Ticket::get_by_id(&mut conn, form.ticket_id)?;
-    if let Some(receiver_id) = ticket.receiver_id {
-        if receiver_id == employee.id {
-            let assist = Assist::create(
-                &mut conn,
-                InsertAssist {
-                    ticket_id: ticket.id,
-                    submitter_id: employee.id,
-                },
-            )?;
-            for r in form.requirements.iter() {
-                let department = Department::get_by_name(&mut conn, &r.department_name, system.id)?;
-                AssistWithDepartments::create(&mut conn, assist.id, department.id, r.total_num)?;
+    let department_ids = conn.transaction::<_, AppError, _>(|conn| {
+        let ticket = Ticket::get_by_id_for_update(conn, form.ticket_id)?;
+        match ticket.receiver_id {
+            None => return Err(new_ok_error("这个工单还没有接受人")),
+            Some(receiver_id) if receiver_id != employee.id => {
+                return Err(new_ok_error("你不是这个工单的接受人"))
             }
-            let resp = new_ok_response("提交协助工单成功");
-            Ok(HttpResponse::Ok().json(resp))
-        } else {
-            Err(new_ok_error("你不是这个工单的接受人"))
+            _ => {}
         }
-    } else {
-        Err(new_ok_error("这个工单还没有接受人"))
-    }
+        if ticket.state != TicketState::Assigned {
+            return Err(new_conflict_error(&format!(
+                "工单当前状态为{}，只有处理中的工单可以提交协助",
+                ticket.state.name()
+            )));
+        }
+        let assist = Assist::create(
+            conn,
+            InsertAssist {
+                ticket_id: ticket.id,
+                submitter_id: employee.id,
+            },
+        )?;
+        let mut department_ids = vec![];
+        for r in form.requirements.iter() {
+            let department = Department::get_by_name(conn, &r.department_name, system.id)?;
+            AssistWithDepartments::create(conn, assist.id, department.id, r.total_num)?;
+            department_ids.push(department.id);
+        }
+        Notification::on_assist_created(conn, &ticket, &department_ids, employee.id)?;
+        TicketEvent::create(
+            conn,
+            InsertTicketEvent {
+                ticket_id: ticket.id,
+                employee_id: Some(employee.id),
+                event_type: TICKET_EVENT_ASSIST,
+                old_state: Some(ticket.state),
+                new_state: ticket.state,
+                comment: Some(&format!("提交协助工单 {}", assist.id)),
+                created_time: Utc::now().naive_utc(),
+            },
+        )?;
+        Ok(department_ids)
+    })?;
+    // 被请求协助的部门也要能收到
+    app_state
+        .events
+        .publish_with(&mut conn, form.ticket_id, |update| {
+            update.department_ids.extend(department_ids)
+        });
+    let resp = new_ok_response("提交协助工单成功");
+    Ok(HttpResponse::Ok().json(resp))
 }
 
 pub async fn get_available_tickets(
@@ -190,34 +333,67 @@ pub async fn get_available_tickets(
     let department_ids =
         EmployeeWithDepartments::mget_department_id_by_employee_id(&mut conn, employee.id)?;
     let tickets = Ticket::mget_available_by_department_ids(&mut conn, department_ids)?;
-    let resp = AvailableTicketsResponse::from((&mut conn, tickets));
+    let resp = AvailableTicketsResponse::try_from((&mut conn, tickets))?;
     Ok(HttpResponse::Ok().json(resp))
 }
 
-pub async fn get_current_ticket(
+// 调用人所在部门还缺人的协助工单，自己已经接了的不算
+pub async fn get_available_assists(
     app_state: web::Data<AppState>,
     req: HttpRequest,
 ) -> Result<HttpResponse, AppError> {
     let mut conn = app_state.conn()?;
     let employee = get_current_employee(&req, &mut conn)?;
-    let ticket = Ticket::get_current_by_receiver(&mut conn, employee.id)?;
-    if let Some(ticket) = ticket {
-        let resp = CurrentTicketResponse::from((&mut conn, ticket));
-        Ok(HttpResponse::Ok().json(resp))
-    } else {
-        let assist_ids = AssistWithEmployees::mget_assist_id_by_involver(&mut conn, employee.id)?;
-        if assist_ids.len() > 1 {
-            return Err(new_ok_error("你接了多于1个协助工单"));
+    let department_ids =
+        EmployeeWithDepartments::mget_department_id_by_employee_id(&mut conn, employee.id)?;
+    let requirements =
+        AssistWithDepartments::mget_open_by_department_ids(&mut conn, &department_ids)?;
+    let mut grouped: Vec<(i32, Vec<AssistWithDepartments>)> = vec![];
+    for r in requirements.into_iter() {
+        match grouped.last_mut() {
+            Some((assist_id, rs)) if *assist_id == r.assist_id => rs.push(r),
+            _ => grouped.push((r.assist_id, vec![r])),
+        }
+    }
+    let mut assists = vec![];
+    for (assist_id, rs) in grouped.into_iter() {
+        if AssistWithEmployees::exists(&mut conn, assist_id, employee.id)? {
+            continue;
         }
-        if assist_ids.len() == 0 {
-            return Err(new_ok_error("你没有接任何主工单或协助工单"));
+        let assist = Assist::get_by_id(&mut conn, assist_id)?;
+        let ticket = Ticket::get_by_id(&mut conn, assist.ticket_id)?;
+        if ticket.state != TicketState::Assigned || ticket.system_id != employee.system_id {
+            continue;
         }
-        let assist_id = assist_ids[0];
+        assists.push(AvailableAssistResponse::try_from((
+            &mut conn, assist, ticket, rs,
+        ))?);
+    }
+    let resp = AvailableAssistsResponse { assists };
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+pub async fn get_current_ticket(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let mut tickets = vec![];
+    for ticket in Ticket::mget_current_by_receiver(&mut conn, employee.id)?.into_iter() {
+        tickets.push(CurrentTicketResponse::try_from((&mut conn, ticket))?);
+    }
+    let assist_ids =
+        AssistWithEmployees::mget_active_assist_id_by_involver(&mut conn, employee.id)?;
+    for assist_id in assist_ids.into_iter() {
         let assist = Assist::get_by_id(&mut conn, assist_id)?;
         let ticket = Ticket::get_by_id(&mut conn, assist.ticket_id)?;
-        let resp = CurrentTicketResponse::from((&mut conn, ticket, assist));
-        Ok(HttpResponse::Ok().json(resp))
+        tickets.push(CurrentTicketResponse::try_from((
+            &mut conn, ticket, assist,
+        ))?);
     }
+    let resp = CurrentTicketsResponse { tickets };
+    Ok(HttpResponse::Ok().json(resp))
 }
 
 pub async fn get_history_tickets(
@@ -244,7 +420,7 @@ pub async fn get_history_tickets(
         ans2.push(t);
     }
 
-    let resp = HistoryTicketsResponse::from((&mut conn, ans1, ans2, ans3));
+    let resp = HistoryTicketsResponse::try_from((&mut conn, ans1, ans2, ans3))?;
     Ok(HttpResponse::Ok().json(resp))
 }
 
@@ -258,59 +434,581 @@ pub async fn take_ticket(
     // let system = get_current_system(&req, &mut conn)?;
     match form.is_assist {
         Some(true) => {
-            let assist = Assist::get_by_id(&mut conn, form.tid)?;
-            let ticket = Ticket::get_by_id(&mut conn, assist.ticket_id)?;
-
-            if ticket.state == TICKET_STATE_ASSIGNED {
-                let ids = EmployeeWithDepartments::mget_department_id_by_employee_id(
-                    &mut conn,
-                    employee.id,
-                )?;
-                for id in ids.into_iter() {
-                    let department = Department::get_by_id(&mut conn, id)?;
-                    AssistWithDepartments::add_person(&mut conn, assist.id, department.id)?;
+            let ticket_id = conn.transaction::<_, AppError, _>(|conn| {
+                // 先锁主工单再看状态，避免检查之后主工单被完工或者放弃
+                let ticket_id = Assist::get_by_id(conn, form.tid)?.ticket_id;
+                let ticket = Ticket::get_by_id_for_update(conn, ticket_id)?;
+                if ticket.state != TicketState::Assigned {
+                    return Err(new_conflict_error("主工单还没有接受人或已经关闭"));
                 }
-                AssistWithEmployees::create(&mut conn, assist.id, employee.id)?;
-                Employee::update_state(&mut conn, employee.id, EMPLOYEE_STATUS_UNAVAILABLE)?;
-                let resp = new_ok_response("接取协助工单成功");
-                Ok(HttpResponse::Ok().json(resp))
-            } else {
-                Err(new_ok_error("主工单还没有接受人或已经关闭"))
-            }
+                let assist = Assist::get_by_id_for_update(conn, form.tid)?;
+                if !assist.is_active() {
+                    return Err(new_conflict_error("协助工单已经完成或撤销"));
+                }
+                if AssistWithEmployees::exists(conn, assist.id, employee.id)? {
+                    return Err(new_conflict_error("你已经接了这个协助工单"));
+                }
+                let ids =
+                    EmployeeWithDepartments::mget_department_id_by_employee_id(conn, employee.id)?;
+                // 只占协助工单需要的部门的名额
+                let requirements: Vec<_> =
+                    AssistWithDepartments::mget_by_assist_id(conn, assist.id)?
+                        .into_iter()
+                        .filter(|r| ids.contains(&r.department_id))
+                        .collect();
+                if requirements.is_empty() {
+                    return Err(new_ok_error("你所在的部门不在该协助工单的需求中"));
+                }
+                // 一个人只算一个名额，同时属于几个需求部门的占第一个还有空位的
+                let mut department_id = None;
+                for requirement in requirements.iter() {
+                    if AssistWithDepartments::add_person(
+                        conn,
+                        assist.id,
+                        requirement.department_id,
+                    )?
+                    .is_some()
+                    {
+                        department_id = Some(requirement.department_id);
+                        break;
+                    }
+                }
+                let department_id =
+                    department_id.ok_or_else(|| new_conflict_error("该部门协助人数已满"))?;
+                AssistWithEmployees::create(conn, assist.id, employee.id, department_id)?;
+                // 第一个接的人是负责人
+                if assist.receiver_id.is_none() {
+                    Assist::set_receiver(conn, assist.id, Some(employee.id))?;
+                }
+                Assist::refresh_state(conn, assist.id)?;
+                Employee::occupy(conn, employee.id)?;
+                TicketEvent::create(
+                    conn,
+                    InsertTicketEvent {
+                        ticket_id: ticket.id,
+                        employee_id: Some(employee.id),
+                        event_type: TICKET_EVENT_ASSIST,
+                        old_state: Some(ticket.state),
+                        new_state: ticket.state,
+                        comment: Some(&format!("接取协助工单 {}", assist.id)),
+                        created_time: Utc::now().naive_utc(),
+                    },
+                )?;
+                Ok(ticket.id)
+            })?;
+            app_state.events.publish_ticket(&mut conn, ticket_id);
+            let resp = new_ok_response("接取协助工单成功");
+            Ok(HttpResponse::Ok().json(resp))
         }
         _ => {
             let resp = new_ok_response("接取工单成功");
-            let ticket = Ticket::get_by_id(&mut conn, form.tid)?;
-            if ticket.state == TICKET_STATE_OPEN {
-                Ticket::set_receiver(&mut conn, form.tid, employee.id)?;
-                Employee::update_state(&mut conn, employee.id, EMPLOYEE_STATUS_UNAVAILABLE)?;
-                Ok(HttpResponse::Ok().json(resp))
-            } else {
-                Err(new_ok_error("工单没有经过完全审批或已经被其他人接受"))
-            }
+            conn.transaction::<_, AppError, _>(|conn| {
+                Ticket::set_receiver(conn, form.tid, employee.id)?;
+                Employee::occupy(conn, employee.id)?;
+                Ok(())
+            })?;
+            app_state.events.publish_ticket(&mut conn, form.tid);
+            Ok(HttpResponse::Ok().json(resp))
         }
     }
 }
 
-// 只针对主工单
+// 只针对主工单，接受人提交完工报告之后等创建人确认
 pub async fn finish_ticket(
     app_state: web::Data<AppState>,
     req: HttpRequest,
     form: web::Json<FinishTicketRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let notes = form.notes.trim();
+    if notes.is_empty() {
+        return Err(new_ok_error("请填写完工说明"));
+    }
+    if form.costs.iter().any(|x| x.reason.trim().is_empty()) {
+        return Err(new_ok_error("花费明细需要填写用途"));
+    }
+    let costs: Vec<(&str, Amount)> = form
+        .costs
+        .iter()
+        .map(|x| (x.reason.trim(), x.amount.clone()))
+        .collect();
+    conn.transaction::<_, AppError, _>(|conn| {
+        let ticket = Ticket::get_by_id_for_update(conn, form.ticket_id)?;
+        if ticket.receiver_id != Some(employee.id) {
+            return Err(new_forbidden_error("只有接受人可以完成工单"));
+        }
+        // 协助工单都结束了才能关主工单
+        if Assist::count_active_by_ticket_id(conn, form.ticket_id)? > 0 {
+            return Err(new_conflict_error("还有未完成的协助工单"));
+        }
+        let report = CompletionReport::create(
+            conn,
+            InsertCompletionReport {
+                ticket_id: ticket.id,
+                employee_id: employee.id,
+                notes,
+                created_time: Utc::now().naive_local(),
+            },
+            &costs,
+        )?;
+        Attachment::attach(
+            conn,
+            form.attachment_ids.as_deref().unwrap_or_default(),
+            employee.id,
+            AttachmentOwner {
+                ticket_id: Some(ticket.id),
+                report_id: Some(report.id),
+                ..Default::default()
+            },
+        )?;
+        expense::check_overrun(conn, ticket.id)?;
+        Ticket::finish(conn, ticket.id, employee.id)?;
+        Employee::refresh_state(conn, employee.id)?;
+        Ok(())
+    })?;
+    app_state.events.publish_ticket(&mut conn, form.ticket_id);
+    let resp = new_ok_response("已提交完工报告，等待创建人确认");
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+// 待确认的工单，只有创建人可以确认或者退回
+fn get_awaiting_ticket(
+    conn: &mut PgConnection,
+    ticket_id: i32,
+    employee_id: i32,
+) -> Result<Ticket, AppError> {
+    let ticket = Ticket::get_by_id_for_update(conn, ticket_id)?;
+    if ticket.creator_id != employee_id {
+        return Err(new_forbidden_error("只有创建人可以确认工单"));
+    }
+    if ticket.state != TicketState::AwaitingConfirmation {
+        return Err(new_conflict_error(&format!(
+            "工单当前状态为{}，不需要确认",
+            ticket.state.name()
+        )));
+    }
+    Ok(ticket)
+}
+
+pub async fn confirm_ticket(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<ConfirmTicketRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    conn.transaction::<_, AppError, _>(|conn| {
+        let ticket = get_awaiting_ticket(conn, form.ticket_id, employee.id)?;
+        if Overrun::get_pending_by_ticket_id(conn, ticket.id)?.is_some() {
+            return Err(new_conflict_error("超支还在审批中，审批完才能确认"));
+        }
+        if Assist::count_active_by_ticket_id(conn, ticket.id)? > 0 {
+            return Err(new_conflict_error("还有未完成的协助工单"));
+        }
+        Ticket::confirm(conn, ticket.id, Some(employee.id))?;
+        Ok(())
+    })?;
+    app_state.events.publish_ticket(&mut conn, form.ticket_id);
+    Ok(HttpResponse::Ok().json(new_ok_response("已确认完工")))
+}
+
+// 退回给原来的接受人继续处理，不占用新的名额
+pub async fn dispute_ticket(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<DisputeTicketRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let reason = get_reason(&form.reason)?;
+    conn.transaction::<_, AppError, _>(|conn| {
+        let ticket = get_awaiting_ticket(conn, form.ticket_id, employee.id)?;
+        Ticket::dispute(conn, ticket.id, employee.id, reason)?;
+        if let Some(receiver_id) = ticket.receiver_id {
+            Employee::refresh_state(conn, receiver_id)?;
+        }
+        Ok(())
+    })?;
+    app_state.events.publish_ticket(&mut conn, form.ticket_id);
+    Ok(HttpResponse::Ok().json(new_ok_response("已退回接受人")))
+}
+
+pub async fn get_ticket_reports(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Query<GetTicketByIDRequest>,
 ) -> Result<HttpResponse, AppError> {
     let mut conn = app_state.conn()?;
     let employee = get_current_employee(&req, &mut conn)?;
     let ticket = Ticket::get_by_id(&mut conn, form.ticket_id)?;
-    if ticket.state == TICKET_STATE_CLOSED {
-        Err(new_ok_error("工单已经完成"))
+    if employee.system_id == ticket.system_id {
+        let reports = CompletionReport::mget_by_ticket_id(&mut conn, ticket.id)?;
+        let resp = CompletionReportsResponse::try_from((&mut conn, reports))?;
+        Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
     } else {
-        Ticket::close(&mut conn, ticket.id)?;
-        Employee::update_state(&mut conn, employee.id, EMPLOYEE_STATUS_AVAILABLE)?;
-        let resp = new_ok_response("完成工单");
-        Ok(HttpResponse::Ok().json(resp))
+        Err(new_ok_error("系统ID不匹配"))
     }
 }
 
+// 创建人或系统管理员撤回还没人接的工单
+pub async fn cancel_ticket(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<CancelTicketRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let is_admin = is_system_admin(&req, &mut conn)?;
+    let reason = form
+        .reason
+        .as_deref()
+        .map(str::trim)
+        .filter(|x| !x.is_empty());
+    if reason.map_or(0, |x| x.chars().count()) > 500 {
+        return Err(new_ok_error("撤回原因不能超过500字"));
+    }
+    conn.transaction::<_, AppError, _>(|conn| {
+        let ticket = Ticket::get_by_id_for_update(conn, form.ticket_id)?;
+        if ticket.system_id != employee.system_id {
+            return Err(new_forbidden_error("系统ID不匹配"));
+        }
+        if ticket.creator_id != employee.id && !is_admin {
+            return Err(new_forbidden_error("只有创建人或管理员可以撤回工单"));
+        }
+        if !matches!(
+            ticket.state,
+            TicketState::Unapproved
+                | TicketState::Approving
+                | TicketState::Open
+                | TicketState::Returned
+        ) {
+            return Err(new_conflict_error(&format!(
+                "工单当前状态为{}，不能撤回",
+                ticket.state.name()
+            )));
+        }
+        Ticket::cancel(conn, ticket.id, employee.id, reason)?;
+        if let Some(receiver_id) = ticket.receiver_id {
+            Employee::refresh_state(conn, receiver_id)?;
+        }
+        Ok(())
+    })?;
+    app_state.events.publish_ticket(&mut conn, form.ticket_id);
+    let resp = new_ok_response("已撤回");
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+fn get_reason(reason: &str) -> Result<&str, AppError> {
+    let reason = reason.trim();
+    if reason.is_empty() {
+        return Err(new_ok_error("请填写原因"));
+    }
+    if reason.chars().count() > 500 {
+        return Err(new_ok_error("原因不能超过500字"));
+    }
+    Ok(reason)
+}
+
+// 工单所在部门的负责人或管理员换人处理，不指定人就放回待接取
+pub async fn reassign_ticket(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<ReassignTicketRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let is_admin = is_system_admin(&req, &mut conn)?;
+    let reason = get_reason(&form.reason)?;
+    conn.transaction::<_, AppError, _>(|conn| {
+        let ticket = Ticket::get_by_id_for_update(conn, form.ticket_id)?;
+        if ticket.system_id != employee.system_id {
+            return Err(new_forbidden_error("系统ID不匹配"));
+        }
+        let department_ids =
+            TicketWithDepartments::mget_department_id_by_ticket_ids(conn, &[ticket.id])?
+                .remove(&ticket.id)
+                .unwrap_or_default();
+        if !is_admin
+            && !EmployeeWithDepartments::is_lead_of_any(conn, employee.id, &department_ids)?
+        {
+            return Err(new_forbidden_error(
+                "只有工单所在部门的负责人或管理员可以改派",
+            ));
+        }
+        if ticket.state != TicketState::Assigned {
+            return Err(new_conflict_error(&format!(
+                "工单当前状态为{}，不能改派",
+                ticket.state.name()
+            )));
+        }
+        match form.receiver_id {
+            Some(receiver_id) if Some(receiver_id) == ticket.receiver_id => {
+                return Err(new_ok_error("已经是这个人在处理"));
+            }
+            Some(receiver_id) => {
+                let receiver = Employee::get_by_id(conn, receiver_id)?;
+                let account = Account::get_by_employee_id(conn, receiver_id)?;
+                let ids =
+                    EmployeeWithDepartments::mget_department_id_by_employee_id(conn, receiver_id)?;
+                if receiver.system_id != ticket.system_id
+                    || account.account_type != ACCOUNT_TYPE_OPERATOR
+                    || !ids.iter().any(|x| department_ids.contains(x))
+                {
+                    return Err(new_ok_error("只能改派给工单所在部门的运维人员"));
+                }
+                Ticket::reassign(conn, ticket.id, receiver_id, employee.id, reason)?;
+                Employee::occupy(conn, receiver_id)?;
+            }
+            None => {
+                Ticket::unassign(
+                    conn,
+                    ticket.id,
+                    Some(employee.id),
+                    TICKET_EVENT_REASSIGN,
+                    Some(reason),
+                )?;
+                dispatch::auto_dispatch(conn, ticket.id, ticket.receiver_id)?;
+            }
+        }
+        if let Some(old_receiver_id) = ticket.receiver_id {
+            Employee::refresh_state(conn, old_receiver_id)?;
+            let content = format!("工单《{}》已改派，原因：{}", ticket.title, reason);
+            Notification::mcreate(
+                conn,
+                &[old_receiver_id],
+                ticket.id,
+                NOTIFICATION_KIND_REASSIGNED,
+                &content,
+            )?;
+        }
+        Ok(())
+    })?;
+    app_state.events.publish_ticket(&mut conn, form.ticket_id);
+    Ok(HttpResponse::Ok().json(new_ok_response("已改派")))
+}
+
+// 接受人处理不了，放回待接取
+pub async fn release_ticket(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<ReleaseTicketRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let reason = get_reason(&form.reason)?;
+    conn.transaction::<_, AppError, _>(|conn| {
+        let ticket = Ticket::get_by_id_for_update(conn, form.ticket_id)?;
+        if ticket.receiver_id != Some(employee.id) {
+            return Err(new_forbidden_error("只有接受人可以放弃工单"));
+        }
+        Ticket::unassign(
+            conn,
+            ticket.id,
+            Some(employee.id),
+            TICKET_EVENT_RELEASE,
+            Some(reason),
+        )?;
+        dispatch::auto_dispatch(conn, ticket.id, Some(employee.id))?;
+        Employee::refresh_state(conn, employee.id)?;
+        Ok(())
+    })?;
+    app_state.events.publish_ticket(&mut conn, form.ticket_id);
+    Ok(HttpResponse::Ok().json(new_ok_response("已放弃")))
+}
+
+// 创建人发现没修好，在期限内可以重新打开，原来的人接不了就放回待接取
+pub async fn reopen_ticket(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<ReopenTicketRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let reason = get_reason(&form.reason)?;
+    conn.transaction::<_, AppError, _>(|conn| {
+        let ticket = Ticket::get_by_id_for_update(conn, form.ticket_id)?;
+        if ticket.creator_id != employee.id {
+            return Err(new_forbidden_error("只有创建人可以重新打开工单"));
+        }
+        if ticket.state != TicketState::Closed {
+            return Err(new_conflict_error(&format!(
+                "工单当前状态为{}，不能重新打开",
+                ticket.state.name()
+            )));
+        }
+        let system = System::get_by_id(conn, ticket.system_id)?;
+        let now = chrono::Utc::now().naive_local();
+        let in_window = ticket
+            .closed_time
+            .is_some_and(|x| now <= x + chrono::Duration::hours(system.reopen_window_hours as i64));
+        if system.reopen_window_hours == 0 || !in_window {
+            return Err(new_conflict_error(&format!(
+                "工单关闭超过{}小时，不能重新打开",
+                system.reopen_window_hours
+            )));
+        }
+        let keep_receiver = match ticket.receiver_id {
+            Some(receiver_id) => {
+                let receiver = Employee::get_by_id_for_update(conn, receiver_id)?;
+                Employee::count_wip(conn, receiver_id)? < receiver.max_wip as i64
+            }
+            None => false,
+        };
+        let ticket = Ticket::reopen(conn, ticket.id, employee.id, reason, keep_receiver)?;
+        match ticket.receiver_id {
+            Some(receiver_id) => {
+                Employee::occupy(conn, receiver_id)?;
+            }
+            None => {
+                dispatch::auto_dispatch(conn, ticket.id, None)?;
+            }
+        }
+        Ok(())
+    })?;
+    app_state.events.publish_ticket(&mut conn, form.ticket_id);
+    Ok(HttpResponse::Ok().json(new_ok_response("已重新打开")))
+}
+
+// 协助工单的事件记在主工单上
+fn create_assist_event(
+    conn: &mut PgConnection,
+    ticket_id: i32,
+    employee_id: i32,
+    comment: &str,
+) -> Result<(), AppError> {
+    let ticket = Ticket::get_by_id(conn, ticket_id)?;
+    TicketEvent::create(
+        conn,
+        InsertTicketEvent {
+            ticket_id,
+            employee_id: Some(employee_id),
+            event_type: TICKET_EVENT_ASSIST,
+            old_state: Some(ticket.state),
+            new_state: ticket.state,
+            comment: Some(comment),
+            created_time: Utc::now().naive_utc(),
+        },
+    )?;
+    Ok(())
+}
+
+fn get_active_assist(conn: &mut PgConnection, assist_id: i32) -> Result<Assist, AppError> {
+    let assist = Assist::get_by_id_for_update(conn, assist_id)?;
+    if !assist.is_active() {
+        return Err(new_conflict_error("协助工单已经完成或撤销"));
+    }
+    Ok(assist)
+}
+
+// 提交人或负责人确认协助完成
+pub async fn finish_assist(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<AssistActionRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let ticket_id = conn.transaction::<_, AppError, _>(|conn| {
+        let assist = get_active_assist(conn, form.assist_id)?;
+        if assist.submitter_id != employee.id && assist.receiver_id != Some(employee.id) {
+            return Err(new_forbidden_error("只有提交人或负责人可以完成协助工单"));
+        }
+        if assist.receiver_id.is_none() {
+            return Err(new_ok_error("还没有人接这个协助工单，不需要协助可以撤销"));
+        }
+        Assist::close(conn, assist.id, ASSIST_STATE_FINISHED)?;
+        create_assist_event(
+            conn,
+            assist.ticket_id,
+            employee.id,
+            &format!("完成协助工单 {}", assist.id),
+        )?;
+        Ok(assist.ticket_id)
+    })?;
+    app_state.events.publish_ticket(&mut conn, ticket_id);
+    Ok(HttpResponse::Ok().json(new_ok_response("协助工单已完成")))
+}
+
+// 提交人不再需要协助
+pub async fn cancel_assist(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<AssistActionRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let ticket_id = conn.transaction::<_, AppError, _>(|conn| {
+        let assist = get_active_assist(conn, form.assist_id)?;
+        if assist.submitter_id != employee.id {
+            return Err(new_forbidden_error("只有提交人可以撤销协助工单"));
+        }
+        Assist::close(conn, assist.id, ASSIST_STATE_CANCELLED)?;
+        create_assist_event(
+            conn,
+            assist.ticket_id,
+            employee.id,
+            &format!("撤销协助工单 {}", assist.id),
+        )?;
+        Ok(assist.ticket_id)
+    })?;
+    app_state.events.publish_ticket(&mut conn, ticket_id);
+    Ok(HttpResponse::Ok().json(new_ok_response("协助工单已撤销")))
+}
+
+// 参与人中途退出，空出来的名额可以再有人接
+pub async fn leave_assist(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<AssistActionRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let ticket_id = conn.transaction::<_, AppError, _>(|conn| {
+        let assist = get_active_assist(conn, form.assist_id)?;
+        let Some(participant) = AssistWithEmployees::delete(conn, assist.id, employee.id)? else {
+            return Err(new_ok_error("你没有接这个协助工单"));
+        };
+        // 接的时候占了哪个部门的名额，退出时就还哪个；以前接的每个所在部门都占了一个
+        match participant.department_id {
+            Some(department_id) => {
+                AssistWithDepartments::remove_person(conn, assist.id, department_id)?;
+            }
+            None => {
+                let ids =
+                    EmployeeWithDepartments::mget_department_id_by_employee_id(conn, employee.id)?;
+                for requirement in AssistWithDepartments::mget_by_assist_id(conn, assist.id)?.iter()
+                {
+                    if ids.contains(&requirement.department_id) {
+                        AssistWithDepartments::remove_person(
+                            conn,
+                            assist.id,
+                            requirement.department_id,
+                        )?;
+                    }
+                }
+            }
+        }
+        if assist.receiver_id == Some(employee.id) {
+            let next = AssistWithEmployees::mget_employee_id_by_assist_id(conn, assist.id)?
+                .first()
+                .copied();
+            Assist::set_receiver(conn, assist.id, next)?;
+        }
+        Assist::refresh_state(conn, assist.id)?;
+        Employee::refresh_state(conn, employee.id)?;
+        create_assist_event(
+            conn,
+            assist.ticket_id,
+            employee.id,
+            &format!("退出协助工单 {}", assist.id),
+        )?;
+        Ok(assist.ticket_id)
+    })?;
+    app_state.events.publish_ticket(&mut conn, ticket_id);
+    Ok(HttpResponse::Ok().json(new_ok_response("已退出协助工单")))
+}
+
 pub async fn get_ticket_by_id(
     app_state: web::Data<AppState>,
     req: HttpRequest,
@@ -326,3 +1024,1384 @@ pub async fn get_ticket_by_id(
         Err(new_ok_error("系统ID不匹配"))
     }
 }
+
+pub async fn get_ticket_timeline(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Query<GetTicketByIDRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(&req, &mut conn)?;
+    let ticket = Ticket::get_by_id(&mut conn, form.ticket_id)?;
+    if employee.system_id == ticket.system_id {
+        let events = TicketEvent::mget_by_ticket_id(&mut conn, ticket.id)?;
+        let resp = TicketTimelineResponse::try_from((&mut conn, events))?;
+        Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+    } else {
+        Err(new_ok_error("系统ID不匹配"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use diesel::prelude::*;
+    use serde_json::json;
+
+    use crate::{
+        models::{
+            assist::{Assist, AssistWithDepartments, AssistWithEmployees, InsertAssist},
+            attachment::{Attachment, AttachmentOwner},
+            employee::Employee,
+            report,
+            ticket::{Fund, Ticket, UpdateTicket},
+        },
+        schema::{
+            assist_department_info, assist_employee_info, assist_info, ticket_attachment,
+            ticket_event, ticket_info,
+        },
+        utils::{
+            constant::{
+                TicketState, ACCOUNT_TYPE_APPLICANT, ACCOUNT_TYPE_APPROVER, ACCOUNT_TYPE_OPERATOR,
+                ASSIST_STATE_CANCELLED, ASSIST_STATE_FINISHED, ASSIST_STATE_OPEN,
+                ASSIST_STATE_STAFFED, EMPLOYEE_STATUS_AVAILABLE, EMPLOYEE_STATUS_UNAVAILABLE,
+                TICKET_EVENT_CONFIRM, TICKET_EVENT_REASSIGN,
+            },
+            money::Amount,
+            testing::{self, create_attachment, create_employee, create_system, create_ticket},
+        },
+    };
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_create_ticket_rollback() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let (applicant, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let attachment = create_attachment(&mut conn, applicant.id, "漏水.png");
+
+        // 部门不存在，此时工单和资金明细已经插进去了
+        let req = test::TestRequest::post().uri("/ticket").set_json(json!({
+            "title": "标题",
+            "address": "地址",
+            "reason": "理由",
+            "funds": [{ "reason": "材料", "amount": 50 }],
+            "departments": ["D1", "不存在的部门"],
+            "attachment_ids": [attachment.id],
+        }));
+        let token = account.generate_token().unwrap();
+        let (status, body) = testing::call(&pool, req, &token).await;
+        assert!(testing::is_error(status, &body));
+
+        let tickets: i64 = ticket_info::table
+            .filter(ticket_info::creator_id.eq(applicant.id))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(tickets, 0);
+        let events: i64 = ticket_event::table
+            .filter(ticket_event::employee_id.eq(applicant.id))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(events, 0);
+        // 附件还能再用
+        let attachment_ticket_id: Option<i32> = ticket_attachment::table
+            .find(attachment.id)
+            .select(ticket_attachment::ticket_id)
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(attachment_ticket_id, None);
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_create_assist_rollback() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let d1 = ts.departments[0].id;
+        let (applicant, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let (operator, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![d1],
+            TicketState::Assigned,
+            Some(operator.id),
+        );
+
+        // 第二个部门不存在，此时协助工单已经插进去了
+        let req = test::TestRequest::post()
+            .uri("/ticket/assist")
+            .set_json(json!({
+                "ticket_id": ticket.id,
+                "requirements": [
+                    { "department_name": "D1", "total_num": 1 },
+                    { "department_name": "不存在的部门", "total_num": 1 },
+                ],
+            }));
+        let token = account.generate_token().unwrap();
+        let (status, body) = testing::call(&pool, req, &token).await;
+        assert!(testing::is_error(status, &body));
+
+        let assists: i64 = assist_info::table
+            .filter(assist_info::ticket_id.eq(ticket.id))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(assists, 0);
+        let events: i64 = ticket_event::table
+            .filter(ticket_event::ticket_id.eq(ticket.id))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(events, 0);
+
+        // 完工等确认和关闭了的工单不能再提交协助
+        for state in [TicketState::AwaitingConfirmation, TicketState::Closed] {
+            Ticket::update(
+                &mut conn,
+                ticket.id,
+                UpdateTicket {
+                    state: Some(state),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let req = test::TestRequest::post()
+                .uri("/ticket/assist")
+                .set_json(json!({
+                    "ticket_id": ticket.id,
+                    "requirements": [{ "department_name": "D1", "total_num": 1 }],
+                }));
+            let (status, _) = testing::call(&pool, req, &token).await;
+            assert_eq!(status, StatusCode::CONFLICT);
+        }
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_take_assist_rollback() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let d1 = ts.departments[0].id;
+        let (applicant, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let (operator, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        // 占了 D1 的名额之后才发现手上的工单已经到上限
+        let (helper, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![d1],
+            TicketState::Assigned,
+            Some(helper.id),
+        );
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![d1],
+            TicketState::Assigned,
+            Some(operator.id),
+        );
+        let assist = Assist::create(
+            &mut conn,
+            InsertAssist {
+                ticket_id: ticket.id,
+                submitter_id: operator.id,
+            },
+        )
+        .unwrap();
+        AssistWithDepartments::create(&mut conn, assist.id, d1, 2).unwrap();
+
+        let req = test::TestRequest::post()
+            .uri("/ticket/take")
+            .set_json(json!({ "tid": assist.id, "is_assist": true }));
+        let token = account.generate_token().unwrap();
+        let (status, _) = testing::call(&pool, req, &token).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let current_num: i32 = assist_department_info::table
+            .filter(assist_department_info::assist_id.eq(assist.id))
+            .filter(assist_department_info::department_id.eq(d1))
+            .select(assist_department_info::current_num)
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(current_num, 0);
+        let participants: i64 = assist_employee_info::table
+            .filter(assist_employee_info::assist_id.eq(assist.id))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(participants, 0);
+        let helper = Employee::get_by_id(&mut conn, helper.id).unwrap();
+        assert_eq!(helper.state, EMPLOYEE_STATUS_AVAILABLE);
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_take_ticket_already_taken() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let d1 = ts.departments[0].id;
+        let (applicant, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![d1],
+            TicketState::Open,
+            None,
+        );
+        let mut results = vec![];
+        for _ in 0..2 {
+            let (_, account) = create_employee(
+                &mut conn,
+                ts.system.id,
+                ACCOUNT_TYPE_OPERATOR,
+                None,
+                vec![d1],
+            );
+            let req = test::TestRequest::post()
+                .uri("/ticket/take")
+                .set_json(json!({ "tid": ticket.id, "is_assist": false }));
+            let token = account.generate_token().unwrap();
+            results.push(testing::call(&pool, req, &token).await);
+        }
+        assert!(!testing::is_error(results[0].0, &results[0].1));
+        assert_eq!(results[1].0, StatusCode::CONFLICT);
+
+        let events: i64 = ticket_event::table
+            .filter(ticket_event::ticket_id.eq(ticket.id))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(events, 1);
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_take_assist_full() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let d1 = ts.departments[0].id;
+        let (applicant, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let (operator, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![d1],
+            TicketState::Assigned,
+            Some(operator.id),
+        );
+        let assist = Assist::create(
+            &mut conn,
+            InsertAssist {
+                ticket_id: ticket.id,
+                submitter_id: operator.id,
+            },
+        )
+        .unwrap();
+        AssistWithDepartments::create(&mut conn, assist.id, d1, 1).unwrap();
+
+        let mut results = vec![];
+        for _ in 0..2 {
+            let (_, account) = create_employee(
+                &mut conn,
+                ts.system.id,
+                ACCOUNT_TYPE_OPERATOR,
+                None,
+                vec![d1],
+            );
+            let req = test::TestRequest::post()
+                .uri("/ticket/take")
+                .set_json(json!({ "tid": assist.id, "is_assist": true }));
+            let token = account.generate_token().unwrap();
+            results.push(testing::call(&pool, req, &token).await);
+        }
+        assert!(!testing::is_error(results[0].0, &results[0].1));
+        assert_eq!(results[1].0, StatusCode::CONFLICT);
+
+        let current_num: i32 = assist_department_info::table
+            .filter(assist_department_info::assist_id.eq(assist.id))
+            .select(assist_department_info::current_num)
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(current_num, 1);
+
+        // 同时属于两个需求部门的人只占一个名额，另一个部门的人还能接
+        let d2 = ts.departments[1].id;
+        let assist = Assist::create(
+            &mut conn,
+            InsertAssist {
+                ticket_id: ticket.id,
+                submitter_id: operator.id,
+            },
+        )
+        .unwrap();
+        AssistWithDepartments::create(&mut conn, assist.id, d1, 1).unwrap();
+        AssistWithDepartments::create(&mut conn, assist.id, d2, 1).unwrap();
+        let take = || {
+            test::TestRequest::post()
+                .uri("/ticket/take")
+                .set_json(json!({ "tid": assist.id, "is_assist": true }))
+        };
+        let current_nums = |conn: &mut PgConnection| -> Vec<i32> {
+            assist_department_info::table
+                .filter(assist_department_info::assist_id.eq(assist.id))
+                .order(assist_department_info::department_id.asc())
+                .select(assist_department_info::current_num)
+                .get_results(conn)
+                .unwrap()
+        };
+        let (both, both_account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1, d2],
+        );
+        let (status, body) =
+            testing::call(&pool, take(), &both_account.generate_token().unwrap()).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(current_nums(&mut conn), vec![1, 0]);
+        let state: i16 = assist_info::table
+            .find(assist.id)
+            .select(assist_info::state)
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(state, ASSIST_STATE_OPEN);
+        let (_, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d2],
+        );
+        let (status, body) = testing::call(&pool, take(), &account.generate_token().unwrap()).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(current_nums(&mut conn), vec![1, 1]);
+        // 退出时只还自己占的那个名额
+        let req = test::TestRequest::post()
+            .uri("/ticket/assist/leave")
+            .set_json(json!({ "assist_id": assist.id }));
+        let (status, body) =
+            testing::call(&pool, req, &both_account.generate_token().unwrap()).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(current_nums(&mut conn), vec![0, 1]);
+        assert!(!AssistWithEmployees::exists(&mut conn, assist.id, both.id).unwrap());
+
+        // 主工单不在处理中时不能接协助工单
+        Ticket::update(
+            &mut conn,
+            ticket.id,
+            UpdateTicket {
+                state: Some(TicketState::AwaitingConfirmation),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let (_, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        let (status, _) = testing::call(&pool, take(), &account.generate_token().unwrap()).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(current_nums(&mut conn), vec![0, 1]);
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_update_returned_ticket() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let (d1, l1) = (ts.departments[0].id, ts.approvals[0].id);
+        let (applicant, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let (_, approver_account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPROVER,
+            Some(l1),
+            vec![],
+        );
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![d1],
+            TicketState::Approving,
+            None,
+        );
+        Ticket::update_approval_id(&mut conn, ticket.id, Some(l1)).unwrap();
+        let old = create_attachment(&mut conn, applicant.id, "旧照片.png");
+        Attachment::attach(
+            &mut conn,
+            &[old.id],
+            applicant.id,
+            AttachmentOwner {
+                ticket_id: Some(ticket.id),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let new = create_attachment(&mut conn, applicant.id, "新照片.png");
+        let update_with = |attachment_ids: Vec<i32>| {
+            test::TestRequest::put().uri("/ticket").set_json(json!({
+                "ticket_id": ticket.id,
+                "title": "新标题",
+                "address": "新地址",
+                "reason": "新理由",
+                "funds": [{ "reason": "材料", "amount": 30 }, { "reason": "人工", "amount": 20 }],
+                "departments": ["D2"],
+                "attachment_ids": attachment_ids,
+            }))
+        };
+        let update = || update_with(vec![new.id]);
+        let token = account.generate_token().unwrap();
+
+        // 审批中不能改
+        let (status, _) = testing::call(&pool, update(), &token).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let req = test::TestRequest::post()
+            .uri("/ticket/return")
+            .set_json(json!({ "ticket_id": ticket.id, "comment": "把材料费拆开写" }));
+        let approver_token = approver_account.generate_token().unwrap();
+        let (status, body) = testing::call(&pool, req, &approver_token).await;
+        assert!(!testing::is_error(status, &body));
+        let returned = Ticket::get_by_id(&mut conn, ticket.id).unwrap();
+        assert_eq!(returned.state, TicketState::Returned);
+        assert_eq!(returned.approval_id, None);
+
+        // 别人不能改
+        let (_, other) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let other_token = other.generate_token().unwrap();
+        let (status, _) = testing::call(&pool, update(), &other_token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // 别人上传的附件不能用
+        let others = create_attachment(&mut conn, other.employee_id, "别人的.png");
+        let (status, body) = testing::call(&pool, update_with(vec![others.id]), &token).await;
+        assert!(testing::is_error(status, &body));
+
+        let (status, body) = testing::call(&pool, update(), &token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(body["departments"], json!(["D2"]));
+        let attachments = body["attachments"].as_array().unwrap();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0]["attachment_id"], new.id);
+        assert_eq!(attachments[0]["filename"], "新照片.png");
+        assert_eq!(attachments[0]["size"], 5);
+        let req = test::TestRequest::get().uri(&format!("/ticket?ticket_id={}", ticket.id));
+        let (status, body) = testing::call(&pool, req, &token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(body["data"]["attachments"][0]["attachment_id"], new.id);
+        assert_eq!(body["data"]["attachments"][0]["content_type"], "image/png");
+        let edited = Ticket::get_by_id(&mut conn, ticket.id).unwrap();
+        assert_eq!(edited.state, TicketState::Unapproved);
+        assert_eq!(edited.title, "新标题");
+        assert_eq!(edited.amount, Amount::from(50));
+        assert_eq!(edited.approval_id, Some(l1));
+        assert_eq!(
+            Fund::mget_by_ticket_id(&mut conn, ticket.id).unwrap().len(),
+            2
+        );
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_cancel_ticket() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let (applicant, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let (operator, _) =
+            create_employee(&mut conn, ts.system.id, ACCOUNT_TYPE_OPERATOR, None, vec![]);
+        let approving = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![],
+            TicketState::Approving,
+            None,
+        );
+        let open = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![],
+            TicketState::Open,
+            None,
+        );
+        let assigned = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![],
+            TicketState::Assigned,
+            Some(operator.id),
+        );
+        let cancel = |ticket_id: i32| {
+            test::TestRequest::post()
+                .uri("/ticket/cancel")
+                .set_json(json!({ "ticket_id": ticket_id, "reason": "不需要了" }))
+        };
+        let token = account.generate_token().unwrap();
+
+        let (_, other) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let other_token = other.generate_token().unwrap();
+        let (status, _) = testing::call(&pool, cancel(approving.id), &other_token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = testing::call(&pool, cancel(approving.id), &token).await;
+        assert!(!testing::is_error(status, &body));
+        let cancelled = Ticket::get_by_id(&mut conn, approving.id).unwrap();
+        assert_eq!(cancelled.state, TicketState::Cancelled);
+        let comment: Option<String> = ticket_event::table
+            .filter(ticket_event::ticket_id.eq(approving.id))
+            .select(ticket_event::comment)
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(comment.as_deref(), Some("不需要了"));
+
+        // 管理员也可以撤回
+        let (status, body) = testing::call(&pool, cancel(open.id), &ts.admin_token).await;
+        assert!(!testing::is_error(status, &body));
+        let cancelled = Ticket::get_by_id(&mut conn, open.id).unwrap();
+        assert_eq!(cancelled.state, TicketState::Cancelled);
+
+        // 已经有人接了就不能撤回
+        let (status, _) = testing::call(&pool, cancel(assigned.id), &token).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_assist_lifecycle() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let (d1, d2) = (ts.departments[0].id, ts.departments[1].id);
+        let (applicant, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let mut operators = vec![];
+        for department_id in [d1, d2, d2] {
+            let (employee, account) = create_employee(
+                &mut conn,
+                ts.system.id,
+                ACCOUNT_TYPE_OPERATOR,
+                None,
+                vec![department_id],
+            );
+            operators.push((employee, account.generate_token().unwrap()));
+        }
+        let [(receiver, receiver_token), (x, x_token), (y, y_token)] =
+            operators.try_into().unwrap();
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![d1],
+            TicketState::Assigned,
+            Some(receiver.id),
+        );
+        let req = test::TestRequest::post().uri("/ticket/assist").set_json(json!({
+            "ticket_id": ticket.id,
+            "requirements": [{ "department_name": ts.departments[1].department_name, "total_num": 1 }],
+        }));
+        let (status, body) = testing::call(&pool, req, &receiver_token).await;
+        assert!(!testing::is_error(status, &body));
+        let assist_id: i32 = assist_info::table
+            .filter(assist_info::ticket_id.eq(ticket.id))
+            .select(assist_info::id)
+            .first(&mut conn)
+            .unwrap();
+
+        let action = |uri: &str| {
+            test::TestRequest::post()
+                .uri(uri)
+                .set_json(json!({ "assist_id": assist_id, "tid": assist_id, "is_assist": true }))
+        };
+        let (status, body) = testing::call(&pool, action("/ticket/take"), &x_token).await;
+        assert!(!testing::is_error(status, &body));
+        let assist = Assist::get_by_id(&mut conn, assist_id).unwrap();
+        assert_eq!(assist.state, ASSIST_STATE_STAFFED);
+        assert_eq!(assist.receiver_id, Some(x.id));
+        let employee = Employee::get_by_id(&mut conn, x.id).unwrap();
+        assert_eq!(employee.state, EMPLOYEE_STATUS_UNAVAILABLE);
+
+        // 协助没结束不能关主工单
+        let req = test::TestRequest::post()
+            .uri("/ticket/finish")
+            .set_json(json!({ "ticket_id": ticket.id, "notes": "换了水管", "costs": [] }));
+        let (status, _) = testing::call(&pool, req, &receiver_token).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // x 退出，名额空出来给 y
+        let (status, body) = testing::call(&pool, action("/ticket/assist/leave"), &x_token).await;
+        assert!(!testing::is_error(status, &body));
+        let assist = Assist::get_by_id(&mut conn, assist_id).unwrap();
+        assert_eq!(assist.state, ASSIST_STATE_OPEN);
+        assert_eq!(assist.receiver_id, None);
+        let employee = Employee::get_by_id(&mut conn, x.id).unwrap();
+        assert_eq!(employee.state, EMPLOYEE_STATUS_AVAILABLE);
+        let (status, body) = testing::call(&pool, action("/ticket/take"), &y_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(
+            Assist::get_by_id(&mut conn, assist_id).unwrap().receiver_id,
+            Some(y.id)
+        );
+
+        let (status, _) = testing::call(&pool, action("/ticket/assist/finish"), &x_token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) =
+            testing::call(&pool, action("/ticket/assist/finish"), &receiver_token).await;
+        assert!(!testing::is_error(status, &body));
+        let assist = Assist::get_by_id(&mut conn, assist_id).unwrap();
+        assert_eq!(assist.state, ASSIST_STATE_FINISHED);
+        let employee = Employee::get_by_id(&mut conn, y.id).unwrap();
+        assert_eq!(employee.state, EMPLOYEE_STATUS_AVAILABLE);
+        let (status, _) = testing::call(&pool, action("/ticket/take"), &x_token).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) =
+            testing::call(&pool, action("/ticket/assist/cancel"), &receiver_token).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let req = test::TestRequest::post()
+            .uri("/ticket/finish")
+            .set_json(json!({ "ticket_id": ticket.id, "notes": "换了水管", "costs": [] }));
+        let (status, body) = testing::call(&pool, req, &receiver_token).await;
+        assert!(!testing::is_error(status, &body));
+        let ticket = Ticket::get_by_id(&mut conn, ticket.id).unwrap();
+        assert_eq!(ticket.state, TicketState::AwaitingConfirmation);
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_available_assists() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let (d1, d2) = (ts.departments[0].id, ts.departments[1].id);
+        let (applicant, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let (submitter, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        let (operator, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d2],
+        );
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![d1],
+            TicketState::Assigned,
+            Some(submitter.id),
+        );
+        // (部门需求, 已经接了的人数, 状态, 调用人是否已经接了)
+        let cases = [
+            (vec![(d2, 2), (d1, 1)], 1, ASSIST_STATE_OPEN, false), // 要出现，只列 D2
+            (vec![(d1, 1)], 0, ASSIST_STATE_OPEN, false),          // 不是调用人的部门
+            (vec![(d2, 1)], 1, ASSIST_STATE_STAFFED, false),       // 满了
+            (vec![(d2, 1)], 0, ASSIST_STATE_CANCELLED, false),     // 撤销了
+            (vec![(d2, 2)], 1, ASSIST_STATE_OPEN, true),           // 已经接了
+        ];
+        let mut assist_ids = vec![];
+        for (requirements, current_num, state, joined) in cases.into_iter() {
+            let assist = Assist::create(
+                &mut conn,
+                InsertAssist {
+                    ticket_id: ticket.id,
+                    submitter_id: submitter.id,
+                },
+            )
+            .unwrap();
+            for (department_id, total_num) in requirements.into_iter() {
+                AssistWithDepartments::create(&mut conn, assist.id, department_id, total_num)
+                    .unwrap();
+                if department_id == d2 {
+                    diesel::update(assist_department_info::table)
+                        .filter(
+                            assist_department_info::assist_id
+                                .eq(assist.id)
+                                .and(assist_department_info::department_id.eq(d2)),
+                        )
+                        .set(assist_department_info::current_num.eq(current_num))
+                        .execute(&mut conn)
+                        .unwrap();
+                }
+            }
+            diesel::update(assist_info::table.find(assist.id))
+                .set(assist_info::state.eq(state))
+                .execute(&mut conn)
+                .unwrap();
+            if joined {
+                AssistWithEmployees::create(&mut conn, assist.id, operator.id, d2).unwrap();
+            }
+            assist_ids.push(assist.id);
+        }
+
+        let req = test::TestRequest::get().uri("/ticket/assist/available");
+        let token = account.generate_token().unwrap();
+        let (status, body) = testing::call(&pool, req, &token).await;
+        assert!(!testing::is_error(status, &body));
+        let assists = body["data"]["assists"].as_array().unwrap();
+        assert_eq!(assists.len(), 1);
+        assert_eq!(assists[0]["assist_id"], assist_ids[0]);
+        assert_eq!(assists[0]["ticket_id"], ticket.id);
+        assert_eq!(assists[0]["submitter"], submitter.name);
+        let requirements = assists[0]["requirements"].as_array().unwrap();
+        assert_eq!(requirements.len(), 1);
+        assert_eq!(
+            requirements[0]["department_name"],
+            ts.departments[1].department_name
+        );
+        assert_eq!(requirements[0]["remaining"], 1);
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_operator_capacity() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let d1 = ts.departments[0].id;
+        let (applicant, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let (operator, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        let token = account.generate_token().unwrap();
+        let tickets: Vec<Ticket> = (0..3)
+            .map(|_| {
+                create_ticket(
+                    &mut conn,
+                    ts.system.id,
+                    applicant.id,
+                    vec![d1],
+                    TicketState::Open,
+                    None,
+                )
+            })
+            .collect();
+
+        let capacity = |max_wip: i32| {
+            test::TestRequest::put()
+                .uri("/system/employee/capacity")
+                .set_json(json!({ "employee_id": operator.id, "max_wip": max_wip }))
+        };
+        let (status, _) = testing::call(&pool, capacity(2), &token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = testing::call(&pool, capacity(2), &ts.admin_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(body["data"]["max_wip"], 2);
+
+        let take = |tid: i32| {
+            test::TestRequest::post()
+                .uri("/ticket/take")
+                .set_json(json!({ "tid": tid }))
+        };
+        let (status, body) = testing::call(&pool, take(tickets[0].id), &token).await;
+        assert!(!testing::is_error(status, &body));
+        let employee = Employee::get_by_id(&mut conn, operator.id).unwrap();
+        assert_eq!(employee.state, EMPLOYEE_STATUS_AVAILABLE);
+        let (status, body) = testing::call(&pool, take(tickets[1].id), &token).await;
+        assert!(!testing::is_error(status, &body));
+        let employee = Employee::get_by_id(&mut conn, operator.id).unwrap();
+        assert_eq!(employee.state, EMPLOYEE_STATUS_UNAVAILABLE);
+
+        // 到上限了，第三个接不了，工单还是待接
+        let (status, _) = testing::call(&pool, take(tickets[2].id), &token).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let ticket = Ticket::get_by_id(&mut conn, tickets[2].id).unwrap();
+        assert_eq!(ticket.state, TicketState::Open);
+        assert_eq!(ticket.receiver_id, None);
+
+        let req = test::TestRequest::get().uri("/ticket/current");
+        let (status, body) = testing::call(&pool, req, &token).await;
+        assert_eq!(status, StatusCode::OK);
+        let ids: Vec<i64> = body["tickets"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x["ticket_id"].as_i64().unwrap())
+            .collect();
+        assert_eq!(ids, vec![tickets[0].id as i64, tickets[1].id as i64]);
+
+        // 调低上限不影响手上的工单，只是不能再接
+        let (status, body) = testing::call(&pool, capacity(1), &ts.admin_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(body["data"]["wip"], 2);
+        let (status, body) = testing::call(&pool, capacity(2), &ts.admin_token).await;
+        assert!(!testing::is_error(status, &body));
+
+        let req = test::TestRequest::post()
+            .uri("/ticket/finish")
+            .set_json(json!({ "ticket_id": tickets[0].id, "notes": "修好了", "costs": [] }));
+        let (status, body) = testing::call(&pool, req, &token).await;
+        assert!(!testing::is_error(status, &body));
+        let employee = Employee::get_by_id(&mut conn, operator.id).unwrap();
+        assert_eq!(employee.state, EMPLOYEE_STATUS_AVAILABLE);
+        let (status, body) = testing::call(&pool, take(tickets[2].id), &token).await;
+        assert!(!testing::is_error(status, &body));
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_reassign_and_release() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let (d1, d2) = (ts.departments[0].id, ts.departments[1].id);
+        let (applicant, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let mut operators = vec![];
+        for department_id in [d1, d1, d1, d2] {
+            let (employee, account) = create_employee(
+                &mut conn,
+                ts.system.id,
+                ACCOUNT_TYPE_OPERATOR,
+                None,
+                vec![department_id],
+            );
+            operators.push((employee, account.generate_token().unwrap()));
+        }
+        let [(a, a_token), (b, b_token), (lead, lead_token), (other, _)] =
+            operators.try_into().unwrap();
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![d1],
+            TicketState::Assigned,
+            Some(a.id),
+        );
+        Employee::refresh_state(&mut conn, a.id).unwrap();
+
+        let reassign = |receiver_id: Option<i32>| {
+            test::TestRequest::post().uri("/ticket/reassign").set_json(
+                json!({ "ticket_id": ticket.id, "receiver_id": receiver_id, "reason": "休假" }),
+            )
+        };
+        let (status, _) = testing::call(&pool, reassign(Some(b.id)), &lead_token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let req = test::TestRequest::put()
+            .uri("/system/employee/lead")
+            .set_json(json!({ "employee_id": lead.id, "department_name": "D1", "is_lead": true }));
+        let (status, body) = testing::call(&pool, req, &ts.admin_token).await;
+        assert!(!testing::is_error(status, &body));
+
+        // 不能派给别的部门的人
+        let (status, body) = testing::call(&pool, reassign(Some(other.id)), &lead_token).await;
+        assert!(testing::is_error(status, &body));
+        let (status, body) = testing::call(&pool, reassign(Some(b.id)), &lead_token).await;
+        assert!(!testing::is_error(status, &body));
+        let current = Ticket::get_by_id(&mut conn, ticket.id).unwrap();
+        assert_eq!(current.state, TicketState::Assigned);
+        assert_eq!(current.receiver_id, Some(b.id));
+        let a = Employee::get_by_id(&mut conn, a.id).unwrap();
+        assert_eq!(a.state, EMPLOYEE_STATUS_AVAILABLE);
+        let b = Employee::get_by_id(&mut conn, b.id).unwrap();
+        assert_eq!(b.state, EMPLOYEE_STATUS_UNAVAILABLE);
+        let comment: Option<String> = ticket_event::table
+            .filter(ticket_event::ticket_id.eq(ticket.id))
+            .filter(ticket_event::event_type.eq(TICKET_EVENT_REASSIGN))
+            .select(ticket_event::comment)
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(comment.as_deref(), Some("休假"));
+
+        let release = |reason: &str| {
+            test::TestRequest::post()
+                .uri("/ticket/release")
+                .set_json(json!({ "ticket_id": ticket.id, "reason": reason }))
+        };
+        let (status, _) = testing::call(&pool, release("不会修"), &a_token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = testing::call(&pool, release("  "), &b_token).await;
+        assert!(testing::is_error(status, &body));
+        let (status, body) = testing::call(&pool, release("不会修"), &b_token).await;
+        assert!(!testing::is_error(status, &body));
+        let current = Ticket::get_by_id(&mut conn, ticket.id).unwrap();
+        assert_eq!(current.state, TicketState::Open);
+        assert_eq!(current.receiver_id, None);
+        let b = Employee::get_by_id(&mut conn, b.id).unwrap();
+        assert_eq!(b.state, EMPLOYEE_STATUS_AVAILABLE);
+
+        // 待接取的工单不需要改派
+        let (status, _) = testing::call(&pool, reassign(Some(a.id)), &lead_token).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_reopen_ticket() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let d1 = ts.departments[0].id;
+        let (applicant, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let applicant_token = account.generate_token().unwrap();
+        let (operator, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        let operator_token = account.generate_token().unwrap();
+        let mut tickets = vec![];
+        for _ in 0..2 {
+            tickets.push(create_ticket(
+                &mut conn,
+                ts.system.id,
+                applicant.id,
+                vec![d1],
+                TicketState::Assigned,
+                Some(operator.id),
+            ));
+        }
+        let finish = |ticket_id: i32| {
+            test::TestRequest::post()
+                .uri("/ticket/finish")
+                .set_json(json!({ "ticket_id": ticket_id, "notes": "修好了", "costs": [] }))
+        };
+        let confirm = |ticket_id: i32| {
+            test::TestRequest::post()
+                .uri("/ticket/confirm")
+                .set_json(json!({ "ticket_id": ticket_id }))
+        };
+        let reopen = |ticket_id: i32| {
+            test::TestRequest::post()
+                .uri("/ticket/reopen")
+                .set_json(json!({ "ticket_id": ticket_id, "reason": "还是漏水" }))
+        };
+        let (status, body) = testing::call(&pool, finish(tickets[0].id), &operator_token).await;
+        assert!(!testing::is_error(status, &body));
+        let (status, body) = testing::call(&pool, confirm(tickets[0].id), &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+
+        let (status, _) = testing::call(&pool, reopen(tickets[0].id), &operator_token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        // 原来的人手上还有一个，到上限了，放回待接取
+        let (status, body) = testing::call(&pool, reopen(tickets[0].id), &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+        let ticket = Ticket::get_by_id(&mut conn, tickets[0].id).unwrap();
+        assert_eq!(ticket.state, TicketState::Open);
+        assert_eq!(ticket.receiver_id, None);
+        assert_eq!(ticket.finished_time, None);
+        assert_eq!(ticket.closed_time, None);
+        assert_eq!(ticket.reopen_count, 1);
+
+        // 原来的人空了，直接回到他手上
+        let (status, body) = testing::call(&pool, finish(tickets[1].id), &operator_token).await;
+        assert!(!testing::is_error(status, &body));
+        let (status, body) = testing::call(&pool, confirm(tickets[1].id), &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+        let (status, body) = testing::call(&pool, reopen(tickets[1].id), &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+        let ticket = Ticket::get_by_id(&mut conn, tickets[1].id).unwrap();
+        assert_eq!(ticket.state, TicketState::Assigned);
+        assert_eq!(ticket.receiver_id, Some(operator.id));
+        let employee = Employee::get_by_id(&mut conn, operator.id).unwrap();
+        assert_eq!(employee.state, EMPLOYEE_STATUS_UNAVAILABLE);
+        let (status, _) = testing::call(&pool, reopen(tickets[1].id), &applicant_token).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let date = chrono::Local::now().format("%Y-%m-%d");
+        let req = test::TestRequest::get().uri(&format!("/figure/pie?date={}&t=daily", date));
+        let (status, body) = testing::call(&pool, req, &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(body["data"]["reopened"], 2);
+
+        // 管理员关掉重新打开
+        let (status, body) = testing::call(&pool, finish(tickets[1].id), &operator_token).await;
+        assert!(!testing::is_error(status, &body));
+        let (status, body) = testing::call(&pool, confirm(tickets[1].id), &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+        let req = test::TestRequest::put()
+            .uri("/system/reopen")
+            .set_json(json!({ "hours": 0 }));
+        let (status, body) = testing::call(&pool, req, &ts.admin_token).await;
+        assert!(!testing::is_error(status, &body));
+        let (status, _) = testing::call(&pool, reopen(tickets[1].id), &applicant_token).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            Ticket::get_by_id(&mut conn, tickets[1].id)
+                .unwrap()
+                .reopen_count,
+            1
+        );
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_completion_report() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let d1 = ts.departments[0].id;
+        let (applicant, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let applicant_token = account.generate_token().unwrap();
+        let (operator, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        let operator_token = account.generate_token().unwrap();
+        let (_, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        let other_token = account.generate_token().unwrap();
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![d1],
+            TicketState::Assigned,
+            Some(operator.id),
+        );
+        let photo = create_attachment(&mut conn, operator.id, "done.png");
+        let finish = |notes: &str, costs: serde_json::Value, attachment_ids: Vec<i32>| {
+            test::TestRequest::post()
+                .uri("/ticket/finish")
+                .set_json(json!({
+                    "ticket_id": ticket.id,
+                    "notes": notes,
+                    "costs": costs,
+                    "attachment_ids": attachment_ids,
+                }))
+        };
+        let action = |uri: &str| {
+            test::TestRequest::post()
+                .uri(uri)
+                .set_json(json!({ "ticket_id": ticket.id, "reason": "水管还在滴水" }))
+        };
+
+        let costs =
+            json!([{ "reason": "材料", "amount": 120 }, { "reason": "人工", "amount": 80 }]);
+        let (status, _) = testing::call(
+            &pool,
+            finish("修好了", costs.clone(), vec![photo.id]),
+            &other_token,
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = testing::call(
+            &pool,
+            finish("  ", costs.clone(), vec![photo.id]),
+            &operator_token,
+        )
+        .await;
+        assert!(testing::is_error(status, &body));
+        let (status, body) = testing::call(
+            &pool,
+            finish("换了水管", costs, vec![photo.id]),
+            &operator_token,
+        )
+        .await;
+        assert!(!testing::is_error(status, &body));
+        let current = Ticket::get_by_id(&mut conn, ticket.id).unwrap();
+        assert_eq!(current.state, TicketState::AwaitingConfirmation);
+        assert!(current.finished_time.is_some());
+        let employee = Employee::get_by_id(&mut conn, operator.id).unwrap();
+        assert_eq!(employee.state, EMPLOYEE_STATUS_AVAILABLE);
+
+        // 只有创建人能确认或者退回
+        let (status, _) = testing::call(&pool, action("/ticket/confirm"), &operator_token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) =
+            testing::call(&pool, action("/ticket/dispute"), &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+        let current = Ticket::get_by_id(&mut conn, ticket.id).unwrap();
+        assert_eq!(current.state, TicketState::Assigned);
+        assert_eq!(current.receiver_id, Some(operator.id));
+        assert_eq!(current.finished_time, None);
+        let employee = Employee::get_by_id(&mut conn, operator.id).unwrap();
+        assert_eq!(employee.state, EMPLOYEE_STATUS_UNAVAILABLE);
+        let (status, _) = testing::call(&pool, action("/ticket/confirm"), &applicant_token).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (status, body) = testing::call(
+            &pool,
+            finish("又紧了一遍接头", json!([]), vec![]),
+            &operator_token,
+        )
+        .await;
+        assert!(!testing::is_error(status, &body));
+        let req = test::TestRequest::get().uri(&format!("/ticket/report?ticket_id={}", ticket.id));
+        let (status, body) = testing::call(&pool, req, &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+        let reports = body["data"]["reports"].as_array().unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0]["notes"], "又紧了一遍接头");
+        assert_eq!(reports[0]["total_cost"], "0.00");
+        assert_eq!(reports[1]["total_cost"], "200.00");
+        assert_eq!(reports[0]["attachments"], json!([]));
+        assert_eq!(reports[1]["attachments"][0]["attachment_id"], photo.id);
+        assert_eq!(reports[1]["attachments"][0]["filename"], "done.png");
+        // 完工报告的附件不算在工单本身的附件里
+        let req = test::TestRequest::get().uri(&format!("/ticket?ticket_id={}", ticket.id));
+        let (status, body) = testing::call(&pool, req, &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(body["data"]["attachments"], json!([]));
+
+        let (status, body) =
+            testing::call(&pool, action("/ticket/confirm"), &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+        let current = Ticket::get_by_id(&mut conn, ticket.id).unwrap();
+        assert_eq!(current.state, TicketState::Closed);
+
+        // 超时没确认的自动关闭，事件里没有操作人
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![d1],
+            TicketState::Assigned,
+            Some(operator.id),
+        );
+        let req = test::TestRequest::post()
+            .uri("/ticket/finish")
+            .set_json(json!({ "ticket_id": ticket.id, "notes": "修好了", "costs": [] }));
+        let (status, body) = testing::call(&pool, req, &operator_token).await;
+        assert!(!testing::is_error(status, &body));
+        let closed = report::close_unconfirmed(&mut conn).unwrap();
+        assert!(!closed.contains(&ticket.id));
+        diesel::update(ticket_info::table.find(ticket.id))
+            .set(
+                ticket_info::finished_time
+                    .eq(chrono::Utc::now().naive_local() - chrono::Duration::hours(73)),
+            )
+            .execute(&mut conn)
+            .unwrap();
+        let closed = report::close_unconfirmed(&mut conn).unwrap();
+        assert!(closed.contains(&ticket.id));
+        let current = Ticket::get_by_id(&mut conn, ticket.id).unwrap();
+        assert_eq!(current.state, TicketState::Closed);
+        let event: (i16, Option<i32>) = ticket_event::table
+            .filter(ticket_event::ticket_id.eq(ticket.id))
+            .order(ticket_event::id.desc())
+            .select((ticket_event::event_type, ticket_event::employee_id))
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(event, (TICKET_EVENT_CONFIRM, None));
+
+        // 重新打开的期限从自动关闭时算，不是从提交完工报告时算
+        assert!(current.closed_time.unwrap() > current.finished_time.unwrap());
+        let req = test::TestRequest::post()
+            .uri("/ticket/reopen")
+            .set_json(json!({ "ticket_id": ticket.id, "reason": "还是漏水" }));
+        let (status, body) = testing::call(&pool, req, &applicant_token).await;
+        assert!(!testing::is_error(status, &body));
+        let current = Ticket::get_by_id(&mut conn, ticket.id).unwrap();
+        assert_eq!(current.state, TicketState::Assigned);
+        assert_eq!(current.closed_time, None);
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_confirm_with_active_assist() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let d1 = ts.departments[0].id;
+        let (applicant, account) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let (operator, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_OPERATOR,
+            None,
+            vec![d1],
+        );
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![d1],
+            TicketState::AwaitingConfirmation,
+            Some(operator.id),
+        );
+        diesel::update(ticket_info::table.find(ticket.id))
+            .set(
+                ticket_info::finished_time
+                    .eq(chrono::Utc::now().naive_local() - chrono::Duration::hours(73)),
+            )
+            .execute(&mut conn)
+            .unwrap();
+        let assist = Assist::create(
+            &mut conn,
+            InsertAssist {
+                ticket_id: ticket.id,
+                submitter_id: operator.id,
+            },
+        )
+        .unwrap();
+
+        // 协助工单没结束，手动确认和自动关闭都不行
+        let req = test::TestRequest::post()
+            .uri("/ticket/confirm")
+            .set_json(json!({ "ticket_id": ticket.id }));
+        let token = account.generate_token().unwrap();
+        let (status, _) = testing::call(&pool, req, &token).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let closed = report::close_unconfirmed(&mut conn).unwrap();
+        assert!(!closed.contains(&ticket.id));
+        let current = Ticket::get_by_id(&mut conn, ticket.id).unwrap();
+        assert_eq!(current.state, TicketState::AwaitingConfirmation);
+
+        diesel::update(assist_info::table.find(assist.id))
+            .set(assist_info::state.eq(ASSIST_STATE_FINISHED))
+            .execute(&mut conn)
+            .unwrap();
+        let closed = report::close_unconfirmed(&mut conn).unwrap();
+        assert!(closed.contains(&ticket.id));
+    }
+}
@@ -1,63 +1,197 @@
-use std::io::Write;
+use std::{io::Write, path::Path};
 
 use actix_multipart::Multipart;
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
 use base64::Engine;
+use chrono::Utc;
 use futures::{StreamExt, TryStreamExt};
+use sha2::{Digest, Sha256};
 
 use crate::{
-    api::{request::upload::UploadFileV2Request, response::upload::UploadFileV2Response},
+    api::{request::upload::UploadFileV2Request, response::upload::AttachmentResponse},
     error::{new_ok_error, AppError},
-    utils::{constant::IMAGE_URL_PREFIX, response::CommonResponse},
+    models::attachment::{Attachment, InsertAttachment},
+    utils::{
+        auth::get_current_employee,
+        constant::{ATTACHMENT_MAX_BYTES, ATTACHMENT_MAX_FILENAME_CHARS, IMAGE_URL_PREFIX},
+        response::CommonResponse,
+    },
+    AppState,
 };
 
-pub async fn save_file(mut payload: Multipart) -> Result<HttpResponse, AppError> {
-    if let Ok(Some(mut field)) = payload.try_next().await {
-        let content_type = field.content_disposition();
-        let filename = content_type.get_filename().unwrap();
-        let file_path = format!("static/{}", filename);
-        let resp = UploadFileV2Response {
-            url: format!("{}/{}", IMAGE_URL_PREFIX, file_path),
-        };
+// 只留最后一段，不让文件名带路径
+fn clean_filename(name: &str) -> Result<String, AppError> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(new_ok_error("文件名不能为空"));
+    }
+    if name.chars().count() > ATTACHMENT_MAX_FILENAME_CHARS {
+        return Err(new_ok_error("文件名太长"));
+    }
+    Ok(name.to_string())
+}
 
-        // File::create is blocking operation, use threadpool
-        let mut f = web::block(|| std::fs::File::create(file_path))
-            .await
-            .unwrap()
-            .unwrap();
-
-        // Field in turn is stream of *Bytes* object
-        while let Some(chunk) = field.next().await {
-            let data = chunk.unwrap();
-            // filesystem operations are blocking, we have to use threadpool
-            f = web::block(move || f.write_all(&data).map(|_| f))
-                .await
-                .unwrap()
-                .unwrap();
+// 按内容的 sha256 存，同名文件不会互相覆盖，扩展名只留字母数字
+fn stored_path(checksum: &str, filename: &str) -> String {
+    let ext = Path::new(filename)
+        .extension()
+        .and_then(|x| x.to_str())
+        .filter(|x| !x.is_empty() && x.len() <= 10 && x.bytes().all(|b| b.is_ascii_alphanumeric()))
+        .map(|x| format!(".{}", x.to_ascii_lowercase()))
+        .unwrap_or_default();
+    format!("static/{}{}", checksum, ext)
+}
+
+// 写文件并记下附件信息，还没挂到工单上
+async fn save_attachment(
+    app_state: &AppState,
+    req: &HttpRequest,
+    filename: &str,
+    content_type: Option<String>,
+    content: Vec<u8>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    let employee = get_current_employee(req, &mut conn)?;
+    let filename = clean_filename(filename)?;
+    let content_type = content_type.unwrap_or_else(|| {
+        mime_guess::from_path(&filename)
+            .first_or_octet_stream()
+            .to_string()
+    });
+    let checksum = hex::encode(Sha256::digest(&content));
+    let file_path = stored_path(&checksum, &filename);
+    let size = content.len() as i64;
+
+    // 文件系统操作会阻塞，放到线程池里
+    let path = file_path.clone();
+    web::block(move || std::fs::File::create(path)?.write_all(&content)).await??;
+
+    let attachment = Attachment::create(
+        &mut conn,
+        InsertAttachment {
+            uploader_id: employee.id,
+            url: &format!("{}/{}", IMAGE_URL_PREFIX, file_path),
+            filename: &filename,
+            content_type: &content_type,
+            size,
+            checksum: &checksum,
+            created_time: Utc::now().naive_local(),
+        },
+    )?;
+    let resp = AttachmentResponse::from(attachment);
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+pub async fn save_file(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    mut payload: Multipart,
+) -> Result<HttpResponse, AppError> {
+    let upload_error = |_| new_ok_error("上传文件失败");
+    let mut field = match payload.try_next().await.map_err(upload_error)? {
+        Some(field) => field,
+        None => return Err(new_ok_error("上传文件失败")),
+    };
+    let filename = field
+        .content_disposition()
+        .get_filename()
+        .unwrap_or_default()
+        .to_string();
+    let content_type = field.content_type().map(|x| x.to_string());
+    let mut content = vec![];
+    while let Some(chunk) = field.next().await {
+        let data = chunk.map_err(upload_error)?;
+        if content.len() + data.len() > ATTACHMENT_MAX_BYTES {
+            return Err(new_ok_error("文件太大"));
         }
-        Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
-    } else {
-        Err(new_ok_error("上传图片失败"))
+        content.extend_from_slice(&data);
     }
+    save_attachment(&app_state, &req, &filename, content_type, content).await
 }
 
-pub async fn save_file_v2(form: web::Json<UploadFileV2Request>) -> Result<HttpResponse, AppError> {
-    let file_path = format!("static/{}", form.name);
+pub async fn save_file_v2(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<UploadFileV2Request>,
+) -> Result<HttpResponse, AppError> {
     let content = base64::engine::general_purpose::STANDARD
         .decode(&form.file)
-        .unwrap();
-
-    let mut f = web::block(|| std::fs::File::create(file_path))
-        .await
-        .unwrap()
-        .unwrap();
-
-    web::block(move || f.write_all(&content).map(|_| f))
-        .await
-        .unwrap()
-        .unwrap();
-    let resp = UploadFileV2Response {
-        url: format!("{}/static/{}", IMAGE_URL_PREFIX, form.name),
-    };
-    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+        .map_err(|_| new_ok_error("文件内容不是合法的 base64"))?;
+    if content.len() > ATTACHMENT_MAX_BYTES {
+        return Err(new_ok_error("文件太大"));
+    }
+    save_attachment(&app_state, &req, &form.name, None, content).await
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test::TestRequest};
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::testing;
+
+    #[test]
+    fn test_clean_filename() {
+        assert_eq!(clean_filename("报修.png").unwrap(), "报修.png");
+        assert_eq!(clean_filename("../../etc/passwd").unwrap(), "passwd");
+        assert_eq!(clean_filename("C:\\a\\b.JPG ").unwrap(), "b.JPG");
+        assert!(clean_filename("dir/").is_err());
+        assert!(clean_filename("..").is_err());
+        assert!(clean_filename(&"a".repeat(256)).is_err());
+        assert_eq!(stored_path("ab", "x.JPG"), "static/ab.jpg");
+        assert_eq!(stored_path("ab", "x"), "static/ab");
+        assert_eq!(stored_path("ab", "x.p/ng"), "static/ab");
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_upload_attachment() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = testing::create_system(&mut conn, true);
+        let upload = |name: &str, content: &[u8]| {
+            TestRequest::post().uri("/upload/v2").set_json(json!({
+                "name": name,
+                "file": base64::engine::general_purpose::STANDARD.encode(content),
+            }))
+        };
+
+        let (status, body) =
+            testing::call(&pool, upload("../现场.png", b"hello"), &ts.admin_token).await;
+        assert!(!testing::is_error(status, &body));
+        let data = &body["data"];
+        let checksum = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert_eq!(data["filename"], "现场.png");
+        assert_eq!(data["content_type"], "image/png");
+        assert_eq!(data["size"], 5);
+        assert_eq!(data["checksum"], checksum);
+        assert!(data["url"]
+            .as_str()
+            .unwrap()
+            .ends_with(&format!("/static/{}.png", checksum)));
+        let path = format!("static/{}.png", checksum);
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+        std::fs::remove_file(path).unwrap();
+
+        // 超过 actix 默认 32KB 的 JSON 也能传，超过附件上限的由接口自己拒绝
+        let content = vec![7u8; 100 * 1024];
+        let (status, body) =
+            testing::call(&pool, upload("大图.jpg", &content), &ts.admin_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert_eq!(body["data"]["size"], 100 * 1024);
+        let path = format!("static/{}.jpg", body["data"]["checksum"].as_str().unwrap());
+        std::fs::remove_file(path).unwrap();
+        let content = vec![7u8; ATTACHMENT_MAX_BYTES + 1];
+        let (status, body) =
+            testing::call(&pool, upload("太大.jpg", &content), &ts.admin_token).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["error"], "文件太大");
+
+        let req = TestRequest::post()
+            .uri("/upload/v2")
+            .set_json(json!({ "name": "a.txt", "file": "不是base64" }));
+        let (status, body) = testing::call(&pool, req, &ts.admin_token).await;
+        assert!(testing::is_error(status, &body));
+    }
 }
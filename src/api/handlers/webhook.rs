@@ -0,0 +1,259 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::{
+    api::{
+        request::webhook::{
+            CreateWebhookRequest, DeleteWebhookRequest, MGetWebhookDeliveryRequest,
+        },
+        response::webhook::{MGetWebhookDeliveryResponse, MGetWebhookResponse, WebhookResponse},
+    },
+    error::{new_forbidden_error, new_ok_error, AppError},
+    models::webhook::{event_name, InsertWebhook, Webhook, WebhookDelivery},
+    utils::{
+        auth::{get_current_system, is_system_admin},
+        response::{new_ok_response, CommonResponse},
+    },
+    AppConn, AppState,
+};
+
+fn check_admin(req: &HttpRequest, conn: &mut AppConn) -> Result<(), AppError> {
+    if !is_system_admin(req, conn)? {
+        return Err(new_forbidden_error("只有管理员可以配置 Webhook"));
+    }
+    Ok(())
+}
+
+pub async fn list_webhooks(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    check_admin(&req, &mut conn)?;
+    let system = get_current_system(&req, &mut conn)?;
+    let webhooks = Webhook::mget_by_system_id(&mut conn, system.id)?;
+    let resp = MGetWebhookResponse {
+        webhooks: webhooks.into_iter().map(Into::into).collect(),
+    };
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+pub async fn create_webhook(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Json<CreateWebhookRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    check_admin(&req, &mut conn)?;
+    let system = get_current_system(&req, &mut conn)?;
+    let url = form.url.trim();
+    if !(url.starts_with("http://") || url.starts_with("https://")) || url.len() > 500 {
+        return Err(new_ok_error(
+            "URL 必须是 http 或 https 地址，且不超过500个字符",
+        ));
+    }
+    if form.secret.len() < 16 || form.secret.len() > 255 {
+        return Err(new_ok_error("secret 长度要在16到255之间"));
+    }
+    let mut event_types = form.event_types.clone().unwrap_or_default();
+    if let Some(x) = event_types.iter().find(|x| event_name(**x).is_none()) {
+        return Err(new_ok_error(&format!("不支持订阅事件类型 {}", x)));
+    }
+    event_types.sort_unstable();
+    event_types.dedup();
+    let webhook = Webhook::create(
+        &mut conn,
+        InsertWebhook {
+            system_id: system.id,
+            url,
+            secret: &form.secret,
+            event_types,
+            created_time: chrono::Utc::now().naive_utc(),
+        },
+    )?;
+    let resp = WebhookResponse::from(webhook);
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+pub async fn delete_webhook(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Query<DeleteWebhookRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = app_state.conn()?;
+    check_admin(&req, &mut conn)?;
+    let system = get_current_system(&req, &mut conn)?;
+    if Webhook::delete(&mut conn, system.id, form.id)? == 0 {
+        return Err(new_ok_error("Webhook 不存在"));
+    }
+    Ok(HttpResponse::Ok().json(new_ok_response("已删除")))
+}
+
+// 投递记录，最新的在前
+pub async fn get_webhook_deliveries_by_page(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Query<MGetWebhookDeliveryRequest>,
+) -> Result<HttpResponse, AppError> {
+    if form.size <= 0 || form.page <= 0 {
+        return Err(new_ok_error("分页参数不合法"));
+    }
+    let mut conn = app_state.conn()?;
+    check_admin(&req, &mut conn)?;
+    let system = get_current_system(&req, &mut conn)?;
+    let webhook = Webhook::get_by_id(&mut conn, form.webhook_id)?;
+    if webhook.system_id != system.id {
+        return Err(new_ok_error("Webhook 不存在"));
+    }
+    let total = WebhookDelivery::get_count_by_webhook_id(&mut conn, webhook.id)?;
+    let deliveries =
+        WebhookDelivery::mget_by_webhook_id(&mut conn, webhook.id, form.size, form.page)?;
+    let resp = MGetWebhookDeliveryResponse {
+        total,
+        deliveries: deliveries.into_iter().map(Into::into).collect(),
+    };
+    Ok(HttpResponse::Ok().json(CommonResponse::from(resp)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::mpsc,
+        thread,
+    };
+
+    use actix_web::test;
+    use diesel::prelude::*;
+    use serde_json::json;
+
+    use crate::{
+        models::webhook::{self, sign},
+        schema::webhook_delivery,
+        utils::{
+            constant::{
+                ACCOUNT_TYPE_APPLICANT, TICKET_EVENT_CREATE, WEBHOOK_DELIVERY_PENDING,
+                WEBHOOK_DELIVERY_SUCCEEDED,
+            },
+            testing::{self, create_employee, create_system},
+        },
+    };
+
+    // 本地起一个接收方，按顺序回 statuses 里的状态码，收到的请求头和请求体发回来
+    fn start_receiver(statuses: Vec<u16>) -> (String, mpsc::Receiver<(Vec<String>, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut headers = vec![];
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    headers.push(line.trim().to_string());
+                }
+                let len: usize = headers
+                    .iter()
+                    .find_map(|x| {
+                        x.to_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|x| x.trim().parse().unwrap())
+                    })
+                    .unwrap_or(0);
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+                let resp = format!("HTTP/1.1 {} X\r\nContent-Length: 2\r\n\r\nok", status);
+                reader.get_mut().write_all(resp.as_bytes()).unwrap();
+                tx.send((headers, String::from_utf8(body).unwrap()))
+                    .unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    #[actix_web::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_webhook_delivery_retry() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let (_, applicant) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let applicant = applicant.generate_token().unwrap();
+        let (url, rx) = start_receiver(vec![500, 200]);
+        let secret = "0123456789abcdef";
+
+        // 只有管理员能配置
+        let create = json!({ "url": url, "secret": secret, "event_types": [TICKET_EVENT_CREATE] });
+        let req = test::TestRequest::post().uri("/webhook").set_json(&create);
+        let (status, _) = testing::call(&pool, req, &applicant).await;
+        assert_eq!(status, actix_web::http::StatusCode::FORBIDDEN);
+        let req = test::TestRequest::post().uri("/webhook").set_json(&create);
+        let (status, body) = testing::call(&pool, req, &ts.admin_token).await;
+        assert!(!testing::is_error(status, &body));
+        assert!(body["data"]["secret"].is_null());
+        let webhook_id = body["data"]["id"].as_i64().unwrap();
+
+        let req = test::TestRequest::post().uri("/ticket").set_json(json!({
+            "title": "标题",
+            "address": "地址",
+            "reason": "理由",
+            "funds": [{ "reason": "材料", "amount": 50 }],
+            "departments": [ts.departments[0].department_name],
+        }));
+        let (status, body) = testing::call(&pool, req, &applicant).await;
+        assert!(!testing::is_error(status, &body));
+        let ticket_id = body["ticket_id"].as_i64().unwrap();
+
+        let log = || {
+            test::TestRequest::get().uri(&format!(
+                "/webhook/delivery?webhook_id={}&size=10&page=1",
+                webhook_id
+            ))
+        };
+        // 第一次接收方返回 500，留着等重试
+        webhook::deliver_due(&mut conn).unwrap();
+        let (_, body) = testing::call(&pool, log(), &ts.admin_token).await;
+        assert_eq!(body["data"]["total"], 1);
+        let delivery = &body["data"]["deliveries"][0];
+        assert_eq!(delivery["state"], WEBHOOK_DELIVERY_PENDING);
+        assert_eq!(delivery["attempts"], 1);
+        assert_eq!(delivery["last_status"], 500);
+        let delivery_id = delivery["id"].as_i64().unwrap() as i32;
+        let (_, first) = rx.recv().unwrap();
+
+        // 还没到重试时间
+        assert_eq!(webhook::deliver_due(&mut conn).unwrap(), 0);
+        diesel::update(webhook_delivery::table.find(delivery_id))
+            .set(webhook_delivery::next_attempt_time.eq(chrono::Utc::now().naive_utc()))
+            .execute(&mut conn)
+            .unwrap();
+        assert_eq!(webhook::deliver_due(&mut conn).unwrap(), 1);
+        let (headers, body) = rx.recv().unwrap();
+        assert_eq!(first, body);
+        let signature = headers
+            .iter()
+            .find_map(|x| x.strip_prefix("X-Webhook-Signature: "))
+            .unwrap();
+        assert_eq!(signature, sign(secret, &body));
+        let payload: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(payload["event"], "create");
+        assert_eq!(payload["ticket"]["id"], ticket_id);
+
+        let (_, body) = testing::call(&pool, log(), &ts.admin_token).await;
+        let delivery = &body["data"]["deliveries"][0];
+        assert_eq!(delivery["state"], WEBHOOK_DELIVERY_SUCCEEDED);
+        assert_eq!(delivery["attempts"], 2);
+        assert!(delivery["delivered_time"].is_string());
+    }
+}
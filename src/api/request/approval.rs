@@ -8,4 +8,5 @@ pub struct MGetApprovalLevelByCompanyRequest {
 #[derive(Debug, Clone, Deserialize)]
 pub struct ApproveRejectTicketRequest {
     pub ticket_id: i32,
+    pub comment: Option<String>, // 审批意见，驳回时写驳回理由
 }
@@ -6,6 +6,12 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateEmailRequest {
+    pub email: Option<String>, // 为空表示清除
+    pub email_opt_out: bool,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct RegisterAdminRequest {
     pub account: String,
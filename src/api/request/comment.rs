@@ -0,0 +1,8 @@
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateCommentRequest {
+    pub ticket_id: i32,
+    pub content: String, // 用 @名字 提到别人
+    pub attachment_ids: Option<Vec<i32>>,
+}
@@ -0,0 +1,17 @@
+use serde::Deserialize;
+
+use crate::utils::money::Amount;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AddExpenseRequest {
+    pub ticket_id: i32,
+    pub reason: String,
+    pub amount: Amount,
+    pub receipt: Option<String>, // 票据的地址，先调上传接口
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OverrunActionRequest {
+    pub overrun_id: i32,
+    pub comment: Option<String>, // 审批意见
+}
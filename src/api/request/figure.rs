@@ -6,11 +6,6 @@ pub struct GetPieChartDataRequest {
     pub date: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct GetChartDataRequest {
-    pub t: String,
-}
-
 #[derive(Debug, Clone, Deserialize)]
 pub struct GetTableRequest {
     pub date: String,
@@ -1,6 +1,11 @@
 pub mod approval;
 pub mod auth;
+pub mod comment;
+pub mod expense;
 pub mod figure;
+pub mod notification;
+pub mod sla;
 pub mod system;
 pub mod ticket;
 pub mod upload;
+pub mod webhook;
@@ -0,0 +1,14 @@
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MGetNotificationByPageRequest {
+    pub size: i32, // # of items per page
+    pub page: i32, // # of current page
+
+    pub unread_only: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReadNotificationRequest {
+    pub ids: Option<Vec<i32>>, // 为空表示全部已读
+}
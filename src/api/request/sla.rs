@@ -0,0 +1,18 @@
+use serde::Deserialize;
+
+use crate::utils::{constant::TicketState, money::Amount};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateSlaPolicyRequest {
+    pub state: TicketState,
+    pub department_name: Option<String>, // 为空表示所有部门
+    pub min_amount: Option<Amount>,
+    pub max_amount: Option<Amount>,
+    pub warning_hours: i32,
+    pub overdue_hours: i32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeleteSlaPolicyRequest {
+    pub id: i32,
+}
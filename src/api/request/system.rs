@@ -6,8 +6,9 @@ pub struct CreateSystemRequest {
     pub levels: Vec<LevelItem>,
     pub departments: Vec<DepItem>,
     pub special_levels: Vec<SpecialLevelItem>,
-    // pub departments: Vec<String>,
-    // pub approvals: Vec<ApprovalRequest>,
+    pub currency: Option<String>, // 币种代码，不填为人民币
+                                  // pub departments: Vec<String>,
+                                  // pub approvals: Vec<ApprovalRequest>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -43,4 +44,39 @@ pub struct RegisterRequest {
     pub company: String,
     pub departments: Vec<String>,
     pub approval_name: String,
+    pub email: Option<String>,
+    pub max_wip: Option<i32>, // 同时处理的工单上限，不填为1
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateCapacityRequest {
+    pub employee_id: i32,
+    pub max_wip: i32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateLeadRequest {
+    pub employee_id: i32,
+    pub department_name: String,
+    pub is_lead: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateDispatchRequest {
+    pub strategy: String, // manual, round_robin, least_loaded, longest_idle
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateReopenWindowRequest {
+    pub hours: i32, // 0 表示不能重新打开
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateConfirmTimeoutRequest {
+    pub hours: i32, // 完工后多久不确认就自动关闭
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateOverrunThresholdRequest {
+    pub percent: i32, // 实际花费超出预算多少百分比要重新审批，0 表示超一分钱都要
 }
@@ -1,5 +1,7 @@
 use serde::Deserialize;
 
+use crate::utils::money::Amount;
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct MGetTicketByPageRequest {
     pub size: i32, // # of items per page
@@ -16,13 +18,25 @@ pub struct CreateTicketRequest {
     pub reason: String,
     pub funds: Vec<TicketFundRequest>,
     pub departments: Vec<String>,
-    pub image: Option<String>,
+    pub attachment_ids: Option<Vec<i32>>, // 先上传拿到的附件 id
+}
+
+// 整张工单重新提交，资金明细和部门都以这次为准
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateTicketRequest {
+    pub ticket_id: i32,
+    pub title: String,
+    pub address: String,
+    pub reason: String,
+    pub funds: Vec<TicketFundRequest>,
+    pub departments: Vec<String>,
+    pub attachment_ids: Option<Vec<i32>>, // 不在列表里的附件会被去掉
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct TicketFundRequest {
     pub reason: String,
-    pub amount: i32,
+    pub amount: Amount, // 字符串或数字，最多两位小数
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -46,6 +60,50 @@ pub struct TakeTicketRequest {
 #[derive(Debug, Clone, Deserialize)]
 pub struct FinishTicketRequest {
     pub ticket_id: i32,
+    pub notes: String,                    // 做了哪些工作
+    pub costs: Vec<TicketFundRequest>,    // 实际花费
+    pub attachment_ids: Option<Vec<i32>>, // 完工照片等附件
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConfirmTicketRequest {
+    pub ticket_id: i32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DisputeTicketRequest {
+    pub ticket_id: i32,
+    pub reason: String, // 哪里没做好
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssistActionRequest {
+    pub assist_id: i32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CancelTicketRequest {
+    pub ticket_id: i32,
+    pub reason: Option<String>, // 撤回原因
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReassignTicketRequest {
+    pub ticket_id: i32,
+    pub receiver_id: Option<i32>, // 为空表示放回待接取
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReleaseTicketRequest {
+    pub ticket_id: i32,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReopenTicketRequest {
+    pub ticket_id: i32,
+    pub reason: String, // 为什么没修好
 }
 
 #[derive(Debug, Clone, Deserialize)]
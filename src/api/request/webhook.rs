@@ -0,0 +1,20 @@
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    pub secret: String,
+    pub event_types: Option<Vec<i16>>, // 为空表示全部
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeleteWebhookRequest {
+    pub id: i32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MGetWebhookDeliveryRequest {
+    pub webhook_id: i32,
+    pub size: i32, // # of items per page
+    pub page: i32, // # of current page
+}
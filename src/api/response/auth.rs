@@ -23,6 +23,12 @@ impl From<(Account, String, Option<String>)> for AccountResponse {
     }
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct EmailSettingsResponse {
+    pub email: Option<String>,
+    pub email_opt_out: bool,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct RegisterAdminResponse {
     pub system_id: i32,
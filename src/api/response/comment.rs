@@ -0,0 +1,79 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use crate::{
+    api::response::upload::AttachmentResponse,
+    error::AppError,
+    models::{attachment::Attachment, comment::Comment, employee::Employee},
+    utils::date_format,
+    AppConn,
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommentsResponse {
+    pub comments: Vec<CommentResponse>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommentResponse {
+    pub comment_id: i32,
+    pub employee_id: i32,
+    pub employee_name: String,
+    pub content: String,
+    pub mentions: Vec<MentionResponse>,
+    pub attachments: Vec<AttachmentResponse>,
+    #[serde(with = "date_format")]
+    pub created_time: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MentionResponse {
+    pub employee_id: i32,
+    pub name: String,
+}
+
+impl TryFrom<(&mut AppConn, Comment, String)> for CommentResponse {
+    type Error = AppError;
+
+    fn try_from(
+        (conn, comment, employee_name): (&mut AppConn, Comment, String),
+    ) -> Result<Self, Self::Error> {
+        let mut mentions = vec![];
+        for id in comment.mentioned_ids.into_iter() {
+            let employee = Employee::get_by_id(conn, id)?;
+            mentions.push(MentionResponse {
+                employee_id: id,
+                name: employee.name,
+            });
+        }
+        Ok(Self {
+            comment_id: comment.id,
+            employee_id: comment.employee_id,
+            employee_name,
+            content: comment.content,
+            mentions,
+            attachments: AttachmentResponse::mget(Attachment::mget_by_comment_id(
+                conn, comment.id,
+            )?),
+            created_time: comment.created_time,
+        })
+    }
+}
+
+impl TryFrom<(&mut AppConn, Vec<(Comment, String)>)> for CommentsResponse {
+    type Error = AppError;
+
+    fn try_from(
+        (conn, comments): (&mut AppConn, Vec<(Comment, String)>),
+    ) -> Result<Self, Self::Error> {
+        let mut ret = vec![];
+        for (comment, employee_name) in comments.into_iter() {
+            ret.push(CommentResponse::try_from((
+                &mut *conn,
+                comment,
+                employee_name,
+            ))?);
+        }
+        Ok(Self { comments: ret })
+    }
+}
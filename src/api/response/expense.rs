@@ -0,0 +1,171 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use crate::{
+    error::AppError,
+    models::{
+        approval::Approval,
+        employee::Employee,
+        expense::{self, Expense, Overrun},
+        system::System,
+        ticket::Ticket,
+    },
+    utils::{date_format, money::Amount},
+    AppConn,
+};
+
+// 实际花费和预算的对比
+#[derive(Debug, Clone, Serialize)]
+pub struct ExpenseSummaryResponse {
+    pub ticket_id: i32,
+    pub currency: String,
+    pub approved_amount: Amount,  // 工单审批通过的金额
+    pub approved_overrun: Amount, // 批准的超支
+    pub budget: Amount,
+    pub actual: Amount,
+    pub variance: Amount,              // 实际减预算，超支为正
+    pub variance_percent: Option<f64>, // 预算为 0 时没有
+    pub threshold_percent: i32,
+    pub expenses: Vec<ExpenseResponse>,
+    pub overruns: Vec<OverrunResponse>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExpenseResponse {
+    pub expense_id: i32,
+    pub employee_id: i32,
+    pub employee_name: String,
+    pub report_id: Option<i32>,
+    pub reason: String,
+    pub amount: Amount,
+    pub receipt: Option<String>,
+    #[serde(with = "date_format")]
+    pub created_time: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OverrunResponse {
+    pub overrun_id: i32,
+    pub amount: Amount,
+    pub state: i16,
+    pub approval_name: Option<String>, // 审批中时轮到的级别
+    pub comment: Option<String>,
+    #[serde(with = "date_format")]
+    pub created_time: NaiveDateTime,
+    pub finished_time: Option<NaiveDateTime>,
+}
+
+fn variance_percent(budget: &Amount, variance: &Amount) -> Option<f64> {
+    variance.percent_of(budget)
+}
+
+impl TryFrom<(&mut AppConn, Ticket)> for ExpenseSummaryResponse {
+    type Error = AppError;
+
+    fn try_from((conn, ticket): (&mut AppConn, Ticket)) -> Result<Self, Self::Error> {
+        let system = System::get_by_id(conn, ticket.system_id)?;
+        let budget = expense::get_budget(conn, &ticket)?;
+        let mut expenses = vec![];
+        for x in Expense::mget_by_ticket_id(conn, ticket.id)?.into_iter() {
+            let employee = Employee::get_by_id(conn, x.employee_id)?;
+            expenses.push(ExpenseResponse {
+                expense_id: x.id,
+                employee_id: x.employee_id,
+                employee_name: employee.name,
+                report_id: x.report_id,
+                reason: x.reason,
+                amount: x.amount,
+                receipt: x.receipt,
+                created_time: x.created_time,
+            });
+        }
+        let mut overruns = vec![];
+        for x in Overrun::mget_by_ticket_id(conn, ticket.id)?.into_iter() {
+            let approval_name = match x.approval_id {
+                Some(id) => Some(Approval::get_by_id(conn, id)?.approval_name),
+                None => None,
+            };
+            overruns.push(OverrunResponse {
+                overrun_id: x.id,
+                amount: x.amount,
+                state: x.state,
+                approval_name,
+                comment: x.comment,
+                created_time: x.created_time,
+                finished_time: x.finished_time,
+            });
+        }
+        let actual: Amount = expenses.iter().map(|x| &x.amount).sum();
+        let variance = actual.clone() - budget.clone();
+        Ok(Self {
+            ticket_id: ticket.id,
+            currency: ticket.currency,
+            approved_overrun: budget.clone() - ticket.amount.clone(),
+            approved_amount: ticket.amount,
+            variance_percent: variance_percent(&budget, &variance),
+            budget,
+            actual,
+            variance,
+            threshold_percent: system.overrun_threshold_percent,
+            expenses,
+            overruns,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingOverrunsResponse {
+    pub overruns: Vec<PendingOverrunResponse>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingOverrunResponse {
+    pub overrun_id: i32,
+    pub ticket_id: i32,
+    pub title: String,
+    pub currency: String,
+    pub amount: Amount, // 这次要批的超支
+    pub budget: Amount,
+    pub actual: Amount,
+    #[serde(with = "date_format")]
+    pub created_time: NaiveDateTime,
+}
+
+impl TryFrom<(&mut AppConn, Vec<(Overrun, Ticket)>)> for PendingOverrunsResponse {
+    type Error = AppError;
+
+    fn try_from(
+        (conn, overruns): (&mut AppConn, Vec<(Overrun, Ticket)>),
+    ) -> Result<Self, Self::Error> {
+        let mut ret = vec![];
+        for (overrun, ticket) in overruns.into_iter() {
+            ret.push(PendingOverrunResponse {
+                overrun_id: overrun.id,
+                ticket_id: ticket.id,
+                budget: expense::get_budget(conn, &ticket)?,
+                actual: Expense::get_total_by_ticket_id(conn, ticket.id)?,
+                title: ticket.title,
+                currency: ticket.currency,
+                amount: overrun.amount,
+                created_time: overrun.created_time,
+            });
+        }
+        Ok(Self { overruns: ret })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variance_percent() {
+        let percent = |budget, variance: &str| {
+            variance_percent(&Amount::from(budget), &variance.parse().unwrap())
+        };
+        assert_eq!(percent(0, "100"), None);
+        assert_eq!(percent(1000, "125"), Some(12.5));
+        assert_eq!(percent(300, "-100"), Some(-33.33));
+        assert_eq!(percent(1000, "0.05"), Some(0.01));
+    }
+}
@@ -6,8 +6,12 @@ pub struct GetPieChartDataResponse {
     pub approving: i32,
     pub available: i32,
     pub received: i32,
+    pub awaiting_confirmation: i32,
     pub closed: i32,
     pub rejected: i32,
+    pub returned: i32,
+    pub cancelled: i32,
+    pub reopened: i32, // 被重新打开过的
 }
 
 // #[derive(Debug, Clone, Serialize)]
@@ -19,6 +23,7 @@ pub struct BarChartState {
     pub period: Option<String>,
     pub open: i32,
     pub closed: i32,
+    pub reopened: i32,
 }
 
 pub type GetTableResponse = Vec<TableState>;
@@ -28,4 +33,5 @@ pub struct TableState {
     pub range: String,
     pub open: i32,
     pub closed: i32,
+    pub reopened: i32,
 }
@@ -0,0 +1,39 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use crate::models::notification::Notification;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MGetNotificationByPageResponse {
+    pub total: i64,
+    pub unread: i64,
+    pub notifications: Vec<NotificationResponse>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NotificationResponse {
+    pub id: i32,
+    pub ticket_id: i32,
+    pub kind: i16,
+    pub content: String,
+    pub is_read: bool,
+    pub created_time: NaiveDateTime,
+}
+
+impl From<Notification> for NotificationResponse {
+    fn from(n: Notification) -> Self {
+        Self {
+            id: n.id,
+            ticket_id: n.ticket_id,
+            kind: n.kind,
+            content: n.content,
+            is_read: n.is_read,
+            created_time: n.created_time,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReadNotificationResponse {
+    pub updated: usize,
+}
@@ -0,0 +1,44 @@
+use serde::Serialize;
+
+use crate::{
+    error::AppError,
+    models::{department::Department, sla::SlaPolicy},
+    utils::{constant::TicketState, money::Amount},
+    AppConn,
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MGetSlaPolicyResponse {
+    pub policies: Vec<SlaPolicyResponse>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SlaPolicyResponse {
+    pub id: i32,
+    pub state: TicketState,
+    pub department_name: Option<String>,
+    pub min_amount: Option<Amount>,
+    pub max_amount: Option<Amount>,
+    pub warning_hours: i32,
+    pub overdue_hours: i32,
+}
+
+impl TryFrom<(&mut AppConn, SlaPolicy)> for SlaPolicyResponse {
+    type Error = AppError;
+
+    fn try_from((conn, policy): (&mut AppConn, SlaPolicy)) -> Result<Self, Self::Error> {
+        let department_name = match policy.department_id {
+            Some(id) => Some(Department::get_by_id(conn, id)?.department_name),
+            None => None,
+        };
+        Ok(Self {
+            id: policy.id,
+            state: policy.state,
+            department_name,
+            min_amount: policy.min_amount,
+            max_amount: policy.max_amount,
+            warning_hours: policy.warning_hours,
+            overdue_hours: policy.overdue_hours,
+        })
+    }
+}
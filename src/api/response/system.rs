@@ -1,6 +1,8 @@
 use serde::Serialize;
 
-use crate::models::{account::Account, department::Department, employee::Employee, system::System};
+use crate::models::{
+    account::Account, department::Department, dispatch, employee::Employee, system::System,
+};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct CreateSystemResponse {
@@ -44,6 +46,53 @@ pub struct CreateEmployeeResponse {
     pub account_type: i16,
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct DispatchResponse {
+    pub strategy: String,
+}
+
+impl From<System> for DispatchResponse {
+    fn from(system: System) -> Self {
+        Self {
+            strategy: dispatch::strategy_name(system.dispatch_strategy).to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReopenWindowResponse {
+    pub hours: i32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfirmTimeoutResponse {
+    pub hours: i32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OverrunThresholdResponse {
+    pub percent: i32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CapacityResponse {
+    pub employee_id: i32,
+    pub max_wip: i32,
+    pub wip: i64, // 正在处理的工单数
+    pub state: i16,
+}
+
+impl From<(Employee, i64)> for CapacityResponse {
+    fn from((employee, wip): (Employee, i64)) -> Self {
+        Self {
+            employee_id: employee.id,
+            max_wip: employee.max_wip,
+            wip,
+            state: employee.state,
+        }
+    }
+}
+
 impl From<(Employee, Account)> for CreateEmployeeResponse {
     fn from((employee, account): (Employee, Account)) -> Self {
         Self {
@@ -2,14 +2,21 @@ use chrono::NaiveDateTime;
 use serde::Serialize;
 
 use crate::{
+    api::response::upload::AttachmentResponse,
     error::AppError,
     models::{
         approval::ApprovalWithTicket,
-        assist::Assist,
+        assist::{Assist, AssistWithDepartments},
+        attachment::Attachment,
+        department::Department,
         employee::Employee,
+        event::TicketEvent,
+        expense::Expense,
+        report::CompletionReport,
+        sla::{self, SlaEvaluation, SlaLevel},
         ticket::{Fund, Ticket, TicketWithDepartments},
     },
-    utils::date_format,
+    utils::{constant::TicketState, date_format, money::Amount},
     AppConn,
 };
 
@@ -26,10 +33,13 @@ impl TryFrom<(&mut AppConn, i64, Vec<Ticket>)> for MGetOverviewByPageResponse {
         (conn, total, tickets): (&mut AppConn, i64, Vec<Ticket>),
     ) -> Result<Self, Self::Error> {
         let mut ts = vec![];
-        for ticket in tickets.into_iter() {
+        let evaluations = sla::mevaluate(conn, &tickets)?;
+        for (ticket, evaluation) in tickets.into_iter().zip(evaluations) {
             let employee = Employee::get_by_id(conn, ticket.creator_id)?;
             let funds = Fund::mget_by_ticket_id(conn, ticket.id)?;
-            ts.push(TicketOverviewResponse::from((ticket, employee, funds)));
+            ts.push(TicketOverviewResponse::from((
+                ticket, employee, funds, evaluation,
+            )));
         }
         Ok(Self { total, tickets: ts })
     }
@@ -41,38 +51,40 @@ pub struct TicketOverviewResponse {
     pub title: String,
     pub name: String,
     pub phone: String,
-    pub money: i32,
+    pub money: Amount,
+    pub currency: String,
     #[serde(with = "date_format")]
     pub submitted_time: NaiveDateTime,
     pub reason: String,
     pub address: String,
-    pub state: i16,
+    pub state: TicketState,
     pub funds: Vec<Fund>,
-    // TODO: 超时还没显示出来，2天超时
+    // 离 SLA 超时还剩几个小时，超时了是负数，终态的工单为空
     pub remaining: Option<String>,
+    pub sla_level: Option<SlaLevel>,
+    pub reopen_count: i32,
 }
 
-impl From<(Ticket, Employee, Vec<Fund>)> for TicketOverviewResponse {
-    fn from((ticket, employee, funds): (Ticket, Employee, Vec<Fund>)) -> Self {
-        let delta = chrono::Utc::now().naive_local() - ticket.created_time;
-        let hours = delta.num_hours();
-        let remaining = if hours <= 48 {
-            None
-        } else {
-            Some((hours - 72).to_string())
-        };
+impl From<(Ticket, Employee, Vec<Fund>, Option<SlaEvaluation>)> for TicketOverviewResponse {
+    fn from(
+        (ticket, employee, funds, evaluation): (Ticket, Employee, Vec<Fund>, Option<SlaEvaluation>),
+    ) -> Self {
+        let now = chrono::Utc::now().naive_utc();
         Self {
             tid: ticket.id,
             title: ticket.title,
             phone: employee.phone.trim().to_string(),
             name: employee.name,
             money: ticket.amount,
+            currency: ticket.currency,
             submitted_time: ticket.created_time,
             reason: ticket.reason,
             address: ticket.address,
             state: ticket.state,
             funds,
-            remaining,
+            remaining: evaluation.map(|x| x.remaining_hours(now).to_string()),
+            sla_level: evaluation.map(|x| x.level),
+            reopen_count: ticket.reopen_count,
         }
     }
 }
@@ -82,16 +94,81 @@ pub struct AvailableTicketsResponse {
     pub tickets: Vec<CurrentTicketResponse>,
 }
 
-impl From<(&mut AppConn, Vec<Ticket>)> for AvailableTicketsResponse {
-    fn from((conn, tickets): (&mut AppConn, Vec<Ticket>)) -> Self {
+impl TryFrom<(&mut AppConn, Vec<Ticket>)> for AvailableTicketsResponse {
+    type Error = AppError;
+
+    fn try_from((conn, tickets): (&mut AppConn, Vec<Ticket>)) -> Result<Self, Self::Error> {
         let mut new_tickets = vec![];
         for ticket in tickets.into_iter() {
-            let new_ticket = CurrentTicketResponse::from((&mut *conn, ticket));
+            let new_ticket = CurrentTicketResponse::try_from((&mut *conn, ticket))?;
             new_tickets.push(new_ticket);
         }
-        Self {
+        Ok(Self {
             tickets: new_tickets,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AvailableAssistsResponse {
+    pub assists: Vec<AvailableAssistResponse>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AvailableAssistResponse {
+    pub assist_id: i32,
+    pub ticket_id: i32,
+    pub title: String,
+    pub reason: String,
+    pub address: String,
+    pub submitter: String,
+    pub phone_number: String,
+    pub email: Option<String>,
+    pub requirements: Vec<AssistRequirementResponse>, // 只有调用人所在部门的
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AssistRequirementResponse {
+    pub department_name: String,
+    pub total_num: i32,
+    pub current_num: i32,
+    pub remaining: i32,
+}
+
+impl TryFrom<(&mut AppConn, Assist, Ticket, Vec<AssistWithDepartments>)>
+    for AvailableAssistResponse
+{
+    type Error = AppError;
+
+    fn try_from(
+        (conn, assist, ticket, requirements): (
+            &mut AppConn,
+            Assist,
+            Ticket,
+            Vec<AssistWithDepartments>,
+        ),
+    ) -> Result<Self, Self::Error> {
+        let submitter = Employee::get_by_id(conn, assist.submitter_id)?;
+        let mut rs = vec![];
+        for r in requirements.into_iter() {
+            rs.push(AssistRequirementResponse {
+                department_name: Department::get_by_id(conn, r.department_id)?.department_name,
+                total_num: r.total_num,
+                current_num: r.current_num,
+                remaining: r.total_num - r.current_num,
+            });
         }
+        Ok(Self {
+            assist_id: assist.id,
+            ticket_id: ticket.id,
+            title: ticket.title,
+            reason: ticket.reason,
+            address: ticket.address,
+            submitter: submitter.name,
+            phone_number: submitter.phone.trim().to_string(),
+            email: submitter.email,
+            requirements: rs,
+        })
     }
 }
 
@@ -106,53 +183,66 @@ pub struct CurrentTicketResponse {
     pub participants: Vec<String>,
     pub reason: String,
     pub departments: Vec<String>,
-    pub state: i16,
+    pub state: TicketState,
     pub manager_id: Option<i32>,
-    pub image: Option<String>,
+    pub attachments: Vec<AttachmentResponse>,
+    pub approvals: Vec<ApprovalTrailResponse>,
 }
 
-impl From<(&mut AppConn, Ticket)> for CurrentTicketResponse {
-    fn from((conn, ticket): (&mut AppConn, Ticket)) -> Self {
-        let submitter = Employee::get_by_id(conn, ticket.creator_id).unwrap();
-        let departments =
-            TicketWithDepartments::mget_department_by_ticket_id(conn, ticket.id).unwrap();
-        Self {
+// 手上所有的主工单和协助工单，没有就是空列表
+#[derive(Debug, Clone, Serialize)]
+pub struct CurrentTicketsResponse {
+    pub tickets: Vec<CurrentTicketResponse>,
+}
+
+impl TryFrom<(&mut AppConn, Ticket)> for CurrentTicketResponse {
+    type Error = AppError;
+
+    fn try_from((conn, ticket): (&mut AppConn, Ticket)) -> Result<Self, Self::Error> {
+        let submitter = Employee::get_by_id(conn, ticket.creator_id)?;
+        let departments = TicketWithDepartments::mget_department_by_ticket_id(conn, ticket.id)?;
+        Ok(Self {
             ticket_id: ticket.id,
             title: ticket.title,
             submitter: submitter.name,
             phone_nubmer: submitter.phone.trim().to_string(),
             submitter_ass: None,
             phone_number_ass: None,
-            participants: Ticket::mget_participant(conn, ticket.id, false).unwrap(),
+            participants: Ticket::mget_participant(conn, ticket.id, false)?,
             reason: ticket.reason,
             departments,
             state: ticket.state,
             manager_id: None,
-            image: ticket.image,
-        }
+            approvals: ApprovalTrailResponse::mget(conn, ticket.id)?,
+            attachments: AttachmentResponse::mget(Attachment::mget_by_ticket_id(conn, ticket.id)?),
+        })
     }
 }
 
-impl From<(&mut AppConn, Ticket, Assist)> for CurrentTicketResponse {
-    fn from((conn, ticket, assist): (&mut AppConn, Ticket, Assist)) -> Self {
-        let submitter = Employee::get_by_id(conn, ticket.creator_id).unwrap();
-        let assist_submitter = Employee::get_by_id(conn, assist.submitter_id).unwrap();
-        let departments =
-            TicketWithDepartments::mget_department_by_ticket_id(conn, ticket.id).unwrap();
-        Self {
+impl TryFrom<(&mut AppConn, Ticket, Assist)> for CurrentTicketResponse {
+    type Error = AppError;
+
+    fn try_from(
+        (conn, ticket, assist): (&mut AppConn, Ticket, Assist),
+    ) -> Result<Self, Self::Error> {
+        let submitter = Employee::get_by_id(conn, ticket.creator_id)?;
+        let assist_submitter = Employee::get_by_id(conn, assist.submitter_id)?;
+        let departments = TicketWithDepartments::mget_department_by_ticket_id(conn, ticket.id)?;
+        Ok(Self {
             ticket_id: ticket.id,
             title: ticket.title,
             submitter: submitter.name,
             phone_nubmer: submitter.phone.trim().to_string(),
             submitter_ass: Some(assist_submitter.name),
             phone_number_ass: Some(assist_submitter.phone.trim().to_string()),
-            participants: Ticket::mget_participant(conn, ticket.id, true).unwrap(),
+            participants: Ticket::mget_participant(conn, ticket.id, true)?,
             reason: ticket.reason,
             departments,
             state: ticket.state,
             manager_id: Some(assist.submitter_id),
-            image: ticket.image,
-        }
+            approvals: ApprovalTrailResponse::mget(conn, ticket.id)?,
+            attachments: AttachmentResponse::mget(Attachment::mget_by_ticket_id(conn, ticket.id)?),
+        })
     }
 }
 
@@ -188,7 +278,7 @@ pub struct HistoryTicketResponse {
     pub phone_number: String,
     pub reason: String,
     pub departments: Vec<String>,
-    pub state: i16,
+    pub state: TicketState,
     #[serde(with = "date_format")]
     pub submitted_time: NaiveDateTime,
     pub submitter_ass: Option<String>,
@@ -198,20 +288,27 @@ pub struct HistoryTicketResponse {
     pub approval_info: Vec<String>,
 }
 
-impl From<(&mut AppConn, Vec<Ticket>, Vec<Ticket>, Vec<Assist>)> for HistoryTicketsResponse {
-    fn from(
+// 列表里只放一张图，取工单上最早的附件
+fn first_attachment_url(conn: &mut AppConn, ticket_id: i32) -> Result<Option<String>, AppError> {
+    let attachments = Attachment::mget_by_ticket_id(conn, ticket_id)?;
+    Ok(attachments.into_iter().next().map(|x| x.url))
+}
+
+impl TryFrom<(&mut AppConn, Vec<Ticket>, Vec<Ticket>, Vec<Assist>)> for HistoryTicketsResponse {
+    type Error = AppError;
+
+    fn try_from(
         (conn, main_tickets, ass_main_tickets, ass_tickets): (
             &mut AppConn,
             Vec<Ticket>,
             Vec<Ticket>,
             Vec<Assist>,
         ),
-    ) -> Self {
+    ) -> Result<Self, Self::Error> {
         let mut ret = vec![];
         for t in main_tickets.into_iter() {
-            let employee = Employee::get_by_id(conn, t.creator_id).unwrap();
-            let departments =
-                TicketWithDepartments::mget_department_by_ticket_id(conn, t.id).unwrap();
+            let employee = Employee::get_by_id(conn, t.creator_id)?;
+            let departments = TicketWithDepartments::mget_department_by_ticket_id(conn, t.id)?;
             ret.push(HistoryTicketResponse {
                 ticket_id: t.id,
                 title: t.title,
@@ -223,16 +320,15 @@ impl From<(&mut AppConn, Vec<Ticket>, Vec<Ticket>, Vec<Assist>)> for HistoryTick
                 submitted_time: t.created_time,
                 submitter_ass: None,
                 phone_number_ass: None,
-                image_path: t.image,
-                participants: Ticket::mget_participant(conn, t.id, false).unwrap(),
-                approval_info: ApprovalWithTicket::get_approver_list(conn, t.id).unwrap(),
+                image_path: first_attachment_url(conn, t.id)?,
+                participants: Ticket::mget_participant(conn, t.id, false)?,
+                approval_info: ApprovalWithTicket::get_approver_list(conn, t.id)?,
             });
         }
-        for (t, ass) in ass_main_tickets.into_iter().zip(ass_tickets.into_iter()) {
-            let creator = Employee::get_by_id(conn, t.creator_id).unwrap();
-            let submitter = Employee::get_by_id(conn, ass.submitter_id).unwrap();
-            let departments =
-                TicketWithDepartments::mget_department_by_ticket_id(conn, t.id).unwrap();
+        for (t, ass) in ass_main_tickets.into_iter().zip(ass_tickets) {
+            let creator = Employee::get_by_id(conn, t.creator_id)?;
+            let submitter = Employee::get_by_id(conn, ass.submitter_id)?;
+            let departments = TicketWithDepartments::mget_department_by_ticket_id(conn, t.id)?;
             ret.push(HistoryTicketResponse {
                 ticket_id: t.id,
                 title: t.title,
@@ -244,12 +340,12 @@ impl From<(&mut AppConn, Vec<Ticket>, Vec<Ticket>, Vec<Assist>)> for HistoryTick
                 submitted_time: t.created_time,
                 submitter_ass: Some(submitter.name),
                 phone_number_ass: Some(submitter.phone.trim().to_string()),
-                image_path: t.image,
-                participants: Ticket::mget_participant(conn, t.id, true).unwrap(),
-                approval_info: ApprovalWithTicket::get_approver_list(conn, t.id).unwrap(),
+                image_path: first_attachment_url(conn, t.id)?,
+                participants: Ticket::mget_participant(conn, t.id, true)?,
+                approval_info: ApprovalWithTicket::get_approver_list(conn, t.id)?,
             });
         }
-        Self { tickets: ret }
+        Ok(Self { tickets: ret })
     }
 }
 
@@ -260,15 +356,17 @@ pub struct PCTicketResponse {
     pub ticket_id: i32,
     pub submitter: String,
     pub phone_number: String,
-    pub money: i32,
+    pub money: Amount,
+    pub currency: String,
     pub reason: String,
-    pub state: i16,
+    pub state: TicketState,
     pub address: String,
     #[serde(with = "date_format")]
     pub submitted_time: NaiveDateTime,
     pub departments: String,
     pub detail_money: String,
-    pub image_path: Option<String>,
+    pub attachments: Vec<AttachmentResponse>,
+    pub approvals: Vec<ApprovalTrailResponse>,
 }
 
 impl TryFrom<(&mut AppConn, Ticket)> for PCTicketResponse {
@@ -278,14 +376,21 @@ impl TryFrom<(&mut AppConn, Ticket)> for PCTicketResponse {
         let submitter = Employee::get_by_id(conn, t.creator_id)?;
         let departments = TicketWithDepartments::mget_department_by_ticket_id(conn, t.id)?;
         let funds = Fund::mget_by_ticket_id(conn, t.id)?;
+        let approvals = ApprovalTrailResponse::mget(conn, t.id)?;
+        let attachments = Attachment::mget_by_ticket_id(conn, t.id)?;
 
         Ok(Self {
             title: t.title,
-            check_name: "黄姥爷 -> 黄姥爷 -> 黄姥爷".into(), // FIXME:
+            check_name: approvals
+                .iter()
+                .map(|x| x.approver.as_str())
+                .collect::<Vec<&str>>()
+                .join(" -> "),
             ticket_id: t.id,
             submitter: submitter.name,
             phone_number: submitter.phone.trim().to_string(),
             money: t.amount,
+            currency: t.currency,
             reason: t.reason,
             state: t.state,
             address: t.address,
@@ -296,7 +401,135 @@ impl TryFrom<(&mut AppConn, Ticket)> for PCTicketResponse {
                 .map(|x| format!("{}: {}", x.reason, x.amount))
                 .collect::<Vec<String>>()
                 .join(";"),
-            image_path: t.image,
+            attachments: AttachmentResponse::mget(attachments),
+            approvals,
         })
     }
 }
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApprovalTrailResponse {
+    pub approver: String,
+    pub approval_name: String,
+    pub result: i16,
+    pub comment: Option<String>,
+    #[serde(with = "date_format")]
+    pub approved_time: NaiveDateTime,
+}
+
+impl ApprovalTrailResponse {
+    pub fn mget(conn: &mut AppConn, ticket_id: i32) -> Result<Vec<Self>, AppError> {
+        let trail = ApprovalWithTicket::mget_trail(conn, ticket_id)?;
+        Ok(trail
+            .into_iter()
+            .map(|(approved, approver, approval_name)| Self {
+                approver,
+                approval_name,
+                result: approved.result,
+                comment: approved.comment,
+                approved_time: approved.created_time,
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TicketTimelineResponse {
+    pub events: Vec<TicketEventResponse>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TicketEventResponse {
+    pub event_id: i32,
+    pub event_type: i16,
+    pub employee_id: Option<i32>,
+    pub employee_name: Option<String>,
+    pub old_state: Option<TicketState>,
+    pub new_state: TicketState,
+    pub comment: Option<String>,
+    #[serde(with = "date_format")]
+    pub created_time: NaiveDateTime,
+}
+
+impl TryFrom<(&mut AppConn, Vec<TicketEvent>)> for TicketTimelineResponse {
+    type Error = AppError;
+
+    fn try_from((conn, events): (&mut AppConn, Vec<TicketEvent>)) -> Result<Self, Self::Error> {
+        let mut ret = vec![];
+        for event in events.into_iter() {
+            let employee_name = match event.employee_id {
+                Some(id) => Some(Employee::get_by_id(conn, id)?.name),
+                None => None,
+            };
+            ret.push(TicketEventResponse {
+                event_id: event.id,
+                event_type: event.event_type,
+                employee_id: event.employee_id,
+                employee_name,
+                old_state: event.old_state,
+                new_state: event.new_state,
+                comment: event.comment,
+                created_time: event.created_time,
+            });
+        }
+        Ok(Self { events: ret })
+    }
+}
+
+// 最新的完工报告在前
+#[derive(Debug, Clone, Serialize)]
+pub struct CompletionReportsResponse {
+    pub reports: Vec<CompletionReportResponse>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompletionReportResponse {
+    pub report_id: i32,
+    pub employee_id: i32,
+    pub employee_name: String,
+    pub notes: String,
+    pub attachments: Vec<AttachmentResponse>,
+    pub costs: Vec<ReportCostResponse>,
+    pub total_cost: Amount,
+    #[serde(with = "date_format")]
+    pub created_time: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportCostResponse {
+    pub reason: String,
+    pub amount: Amount,
+}
+
+impl TryFrom<(&mut AppConn, Vec<CompletionReport>)> for CompletionReportsResponse {
+    type Error = AppError;
+
+    fn try_from(
+        (conn, reports): (&mut AppConn, Vec<CompletionReport>),
+    ) -> Result<Self, Self::Error> {
+        let mut ret = vec![];
+        for report in reports.into_iter() {
+            let employee = Employee::get_by_id(conn, report.employee_id)?;
+            let costs: Vec<ReportCostResponse> = Expense::mget_by_report_id(conn, report.id)?
+                .into_iter()
+                .map(|x| ReportCostResponse {
+                    reason: x.reason,
+                    amount: x.amount,
+                })
+                .collect();
+            ret.push(CompletionReportResponse {
+                report_id: report.id,
+                employee_id: report.employee_id,
+                employee_name: employee.name,
+                notes: report.notes,
+                attachments: AttachmentResponse::mget(Attachment::mget_by_report_id(
+                    conn, report.id,
+                )?),
+                total_cost: costs.iter().map(|x| &x.amount).sum(),
+                costs,
+                created_time: report.created_time,
+            });
+        }
+        Ok(Self { reports: ret })
+    }
+}
@@ -1,6 +1,39 @@
+use chrono::NaiveDateTime;
 use serde::Serialize;
 
+use crate::{models::attachment::Attachment, utils::date_format};
+
+// 上传之后返回 attachment_id，建工单、评论、完工报告时带上
 #[derive(Debug, Clone, Serialize)]
-pub struct UploadFileV2Response {
+pub struct AttachmentResponse {
+    pub attachment_id: i32,
     pub url: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: Option<i64>,
+    pub checksum: Option<String>,
+    pub uploader_id: Option<i32>,
+    #[serde(with = "date_format")]
+    pub created_time: NaiveDateTime,
+}
+
+impl From<Attachment> for AttachmentResponse {
+    fn from(attachment: Attachment) -> Self {
+        Self {
+            attachment_id: attachment.id,
+            url: attachment.url,
+            filename: attachment.filename,
+            content_type: attachment.content_type,
+            size: attachment.size,
+            checksum: attachment.checksum,
+            uploader_id: attachment.uploader_id,
+            created_time: attachment.created_time,
+        }
+    }
+}
+
+impl AttachmentResponse {
+    pub fn mget(attachments: Vec<Attachment>) -> Vec<Self> {
+        attachments.into_iter().map(Self::from).collect()
+    }
 }
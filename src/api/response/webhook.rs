@@ -0,0 +1,68 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use crate::models::webhook::{Webhook, WebhookDelivery};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MGetWebhookResponse {
+    pub webhooks: Vec<WebhookResponse>,
+}
+
+// 不回传 secret
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookResponse {
+    pub id: i32,
+    pub url: String,
+    pub event_types: Vec<i16>,
+    pub created_time: NaiveDateTime,
+}
+
+impl From<Webhook> for WebhookResponse {
+    fn from(w: Webhook) -> Self {
+        Self {
+            id: w.id,
+            url: w.url,
+            event_types: w.event_types,
+            created_time: w.created_time,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MGetWebhookDeliveryResponse {
+    pub total: i64,
+    pub deliveries: Vec<WebhookDeliveryResponse>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookDeliveryResponse {
+    pub id: i32,
+    pub ticket_id: i32,
+    pub event_type: i16,
+    pub payload: String,
+    pub state: i16,
+    pub attempts: i32,
+    pub next_attempt_time: NaiveDateTime,
+    pub last_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_time: NaiveDateTime,
+    pub delivered_time: Option<NaiveDateTime>,
+}
+
+impl From<WebhookDelivery> for WebhookDeliveryResponse {
+    fn from(d: WebhookDelivery) -> Self {
+        Self {
+            id: d.id,
+            ticket_id: d.ticket_id,
+            event_type: d.event_type,
+            payload: d.payload,
+            state: d.state,
+            attempts: d.attempts,
+            next_attempt_time: d.next_attempt_time,
+            last_status: d.last_status,
+            last_error: d.last_error,
+            created_time: d.created_time,
+            delivered_time: d.delivered_time,
+        }
+    }
+}
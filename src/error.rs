@@ -20,6 +20,9 @@ pub enum AppError {
     #[error("Not Found: {0}")]
     NotFound(ErrMessage), // 404
 
+    #[error("Conflict: {0}")]
+    Conflict(ErrMessage), // 409
+
     #[error("Unprocessable Entity: {0}")]
     UnprocessableEntity(ErrMessage), // 422
 
@@ -44,6 +47,19 @@ pub fn new_ok_error(error: &str) -> AppError {
     })
 }
 
+pub fn new_forbidden_error(error: &str) -> AppError {
+    AppError::Forbidden(ErrMessage {
+        error: error.into(),
+    })
+}
+
+// 并发下被别人抢先一步，比如工单已经被接取
+pub fn new_conflict_error(error: &str) -> AppError {
+    AppError::Conflict(ErrMessage {
+        error: error.into(),
+    })
+}
+
 impl actix_web::error::ResponseError for AppError {
     fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
         match self {
@@ -52,6 +68,7 @@ impl actix_web::error::ResponseError for AppError {
             }
             AppError::Forbidden(val) => HttpResponse::Forbidden().json(CommonResponse::from(val)),
             AppError::NotFound(val) => HttpResponse::NotFound().json(CommonResponse::from(val)),
+            AppError::Conflict(val) => HttpResponse::Conflict().json(CommonResponse::from(val)),
             AppError::UnprocessableEntity(val) => {
                 HttpResponse::UnprocessableEntity().json(CommonResponse::from(val))
             }
@@ -68,6 +85,7 @@ impl actix_web::error::ResponseError for AppError {
             AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
             AppError::Forbidden(_) => StatusCode::FORBIDDEN,
             AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
             AppError::UnprocessableEntity(_) => StatusCode::UNPROCESSABLE_ENTITY,
             AppError::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
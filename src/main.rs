@@ -6,7 +6,7 @@ use diesel::{
     PgConnection,
 };
 use error::AppError;
-use utils::auth::Authorization;
+use utils::{auth::Authorization, broadcast::Broadcaster};
 
 pub mod error;
 pub mod router;
@@ -23,6 +23,7 @@ pub type AppConn = PooledConnection<Manager>;
 #[derive(Clone)]
 pub struct AppState {
     pub pool: Pool,
+    pub events: Broadcaster,
 }
 
 impl AppState {
@@ -40,7 +41,11 @@ async fn main() -> std::io::Result<()> {
     let pool = r2d2::Pool::builder()
         .build(Manager::new(database_url))
         .expect("failed to build pool");
-    let app_state = AppState { pool };
+    let app_state = AppState {
+        pool,
+        events: Broadcaster::default(),
+    };
+    utils::scheduler::start(app_state.clone());
 
     HttpServer::new(move || {
         let cors = Cors::default()
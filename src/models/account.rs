@@ -97,6 +97,16 @@ impl Account {
         Ok(account)
     }
 
+    pub fn get_by_employee_id(
+        conn: &mut PgConnection,
+        employee_id: i32,
+    ) -> Result<Account, AppError> {
+        let account = account_info::table
+            .filter(account_info::employee_id.eq(employee_id))
+            .first(conn)?;
+        Ok(account)
+    }
+
     pub fn find_by_name(conn: &mut PgConnection, account_name: &str) -> Result<Account, AppError> {
         let account: Account = account_info::table
             .filter(account_info::account_name.eq(account_name))
@@ -4,7 +4,8 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     error::AppError,
-    schema::{approval_info, approved_info},
+    schema::{approval_info, approved_info, employee_info},
+    utils::money::{Amount, Money},
 };
 
 use super::employee::Employee;
@@ -13,19 +14,21 @@ use super::employee::Employee;
 #[diesel(table_name = approval_info)]
 pub struct Approval {
     pub approval_name: String, // 这个审批级别的名字
-    pub amount: i32,           // 小于这个数的，我能批
+    pub amount: Amount,        // 小于这个数的，我能批
     pub company: Option<String>,
     pub system_id: i32,
     pub id: i32,
+    pub currency: String,
 }
 
 #[derive(Insertable)]
 #[diesel(table_name = approval_info)]
 pub struct InsertApproval<'a> {
     pub approval_name: &'a str,
-    pub amount: i32,
+    pub amount: Amount,
     pub company: Option<&'a str>,
     pub system_id: i32,
+    pub currency: &'a str,
 }
 
 impl Approval {
@@ -44,10 +47,15 @@ impl Approval {
         Ok(approval)
     }
 
+    // 这一级能批的额度
+    pub fn limit(&self) -> Money {
+        Money::new(self.amount.clone(), &self.currency)
+    }
+
     pub fn get_highest_by_amount(
         conn: &mut PgConnection,
         system_id: i32,
-        amount: i32,
+        amount: Amount,
     ) -> Result<Self, AppError> {
         let approval = FilterDsl::filter(
             approval_info::table,
@@ -91,7 +99,7 @@ impl Approval {
                     .and(approval_info::company.eq(company_name)),
             )
             .get_results::<Approval>(conn)?;
-            if approvals.len() == 0 {
+            if approvals.is_empty() {
                 let approvals = FilterDsl::filter(
                     approval_info::table,
                     approval_info::system_id
@@ -118,10 +126,13 @@ impl Approval {
 
     pub fn get_next_by_company(
         conn: &mut PgConnection,
+        system_id: i32,
         company_name: Option<String>,
-        cur_money_limit: i32,
+        cur_money_limit: Amount,
     ) -> Result<Option<Approval>, AppError> {
-        let mut query = approval_info::table.into_boxed();
+        let mut query =
+            FilterDsl::filter(approval_info::table, approval_info::system_id.eq(system_id))
+                .into_boxed();
         if let Some(company_name) = company_name {
             query = FilterDsl::filter(query, approval_info::company.eq(company_name));
         }
@@ -144,15 +155,17 @@ pub struct ApprovalWithTicket {
     pub employee_id: i32,
     pub created_time: NaiveDateTime,
     pub result: i16,
+    pub comment: Option<String>, // 审批意见
 }
 
 #[derive(Insertable)]
 #[diesel(table_name = approved_info)]
-pub struct InsertApprovalWithTicket {
+pub struct InsertApprovalWithTicket<'a> {
     pub ticket_id: i32,
     pub approval_id: i32,
     pub employee_id: i32,
     pub result: i16,
+    pub comment: Option<&'a str>,
 }
 
 impl ApprovalWithTicket {
@@ -162,6 +175,7 @@ impl ApprovalWithTicket {
         approval_id: i32,
         employee_id: i32,
         result: i16,
+        comment: Option<&str>,
     ) -> Result<Self, AppError> {
         let a = diesel::insert_into(approved_info::table)
             .values(InsertApprovalWithTicket {
@@ -169,6 +183,7 @@ impl ApprovalWithTicket {
                 approval_id,
                 employee_id,
                 result,
+                comment,
             })
             .get_result(conn)?;
         Ok(a)
@@ -190,4 +205,25 @@ impl ApprovalWithTicket {
         }
         Ok(ret)
     }
+
+    // 审批记录，带上审批人名字和审批级别名字，按时间先后
+    pub fn mget_trail(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+    ) -> Result<Vec<(Self, String, String)>, AppError> {
+        let trail = FilterDsl::filter(
+            approved_info::table
+                .inner_join(employee_info::table)
+                .inner_join(approval_info::table),
+            approved_info::ticket_id.eq(ticket_id),
+        )
+        .select((
+            Self::as_select(),
+            employee_info::name,
+            approval_info::approval_name,
+        ))
+        .order((approved_info::created_time.asc(), approved_info::id.asc()))
+        .get_results(conn)?;
+        Ok(trail)
+    }
 }
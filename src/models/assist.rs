@@ -5,17 +5,20 @@ use diesel::{prelude::*, query_dsl::methods::FilterDsl};
 use crate::{
     error::AppError,
     schema::{assist_department_info, assist_info},
-    utils::constant::{TICKET_STATE_CLOSED, TICKET_STATE_OPEN},
+    utils::constant::{
+        ASSIST_DEPARTMENT_FULL, ASSIST_DEPARTMENT_OPEN, ASSIST_STATE_CANCELLED,
+        ASSIST_STATE_FINISHED, ASSIST_STATE_OPEN, ASSIST_STATE_STAFFED,
+    },
 };
 
 #[derive(Debug, Clone, Identifiable, Selectable, Queryable)]
 #[diesel(table_name = assist_info)]
 pub struct Assist {
     pub ticket_id: i32,    // 原工单
-    pub state: i16,        // 0: 没人接，1：有人接，2：关闭了
+    pub state: i16,        // ASSIST_STATE_*
     pub submitter_id: i32, // 接原工单，提交协助工单的人
     pub id: i32,
-    pub receiver_id: Option<i32>, // 接协助工单的人
+    pub receiver_id: Option<i32>, // 负责人，第一个接协助工单的人
 }
 
 #[derive(Insertable)]
@@ -41,6 +44,31 @@ impl Assist {
         Ok(assist)
     }
 
+    // 加入、退出、完成、撤销都先锁住协助工单，避免互相穿插
+    pub fn get_by_id_for_update(conn: &mut PgConnection, id: i32) -> Result<Self, AppError> {
+        let assist = assist_info::table.find(id).for_update().get_result(conn)?;
+        Ok(assist)
+    }
+
+    pub fn is_active(&self) -> bool {
+        matches!(self.state, ASSIST_STATE_OPEN | ASSIST_STATE_STAFFED)
+    }
+
+    pub fn count_active_by_ticket_id(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+    ) -> Result<i64, AppError> {
+        let count = FilterDsl::filter(
+            assist_info::table,
+            assist_info::ticket_id
+                .eq(ticket_id)
+                .and(assist_info::state.eq_any([ASSIST_STATE_OPEN, ASSIST_STATE_STAFFED])),
+        )
+        .count()
+        .get_result(conn)?;
+        Ok(count)
+    }
+
     pub fn mget_current_by_receiver(
         conn: &mut PgConnection,
         receiver_id: i32,
@@ -49,12 +77,56 @@ impl Assist {
             assist_info::table,
             assist_info::receiver_id
                 .eq(receiver_id)
-                .and(assist_info::state.eq(TICKET_STATE_OPEN)),
+                .and(assist_info::state.eq_any([ASSIST_STATE_OPEN, ASSIST_STATE_STAFFED])),
         )
         .get_results(conn)?;
         Ok(assists)
     }
 
+    pub fn set_receiver(
+        conn: &mut PgConnection,
+        id: i32,
+        receiver_id: Option<i32>,
+    ) -> Result<Self, AppError> {
+        let assist = diesel::update(assist_info::table.find(id))
+            .set(assist_info::receiver_id.eq(receiver_id))
+            .get_result(conn)?;
+        Ok(assist)
+    }
+
+    // 各部门都满了就是人齐了，有人退出又回到招人
+    pub fn refresh_state(conn: &mut PgConnection, id: i32) -> Result<Self, AppError> {
+        let full = AssistWithDepartments::mget_by_assist_id(conn, id)?
+            .iter()
+            .all(|x| x.current_num >= x.total_num);
+        let state = if full {
+            ASSIST_STATE_STAFFED
+        } else {
+            ASSIST_STATE_OPEN
+        };
+        let assist = diesel::update(FilterDsl::filter(
+            assist_info::table,
+            assist_info::id
+                .eq(id)
+                .and(assist_info::state.eq_any([ASSIST_STATE_OPEN, ASSIST_STATE_STAFFED])),
+        ))
+        .set(assist_info::state.eq(state))
+        .get_result(conn)?;
+        Ok(assist)
+    }
+
+    // 完成或撤销，参与的人都放出来
+    pub fn close(conn: &mut PgConnection, id: i32, state: i16) -> Result<Vec<i32>, AppError> {
+        diesel::update(assist_info::table.find(id))
+            .set(assist_info::state.eq(state))
+            .execute(conn)?;
+        let participants = AssistWithEmployees::mget_employee_id_by_assist_id(conn, id)?;
+        for employee_id in participants.iter() {
+            Employee::refresh_state(conn, *employee_id)?;
+        }
+        Ok(participants)
+    }
+
     pub fn mget_history_by_receiver(
         conn: &mut PgConnection,
         receiver: i32,
@@ -62,7 +134,9 @@ impl Assist {
         use crate::schema::assist_info::dsl::*;
         let assists = diesel::QueryDsl::filter(
             assist_info,
-            state.eq(TICKET_STATE_CLOSED).and(receiver_id.eq(receiver)),
+            state
+                .eq_any([ASSIST_STATE_FINISHED, ASSIST_STATE_CANCELLED])
+                .and(receiver_id.eq(receiver)),
         )
         .get_results::<Assist>(conn)?;
         Ok(assists)
@@ -106,12 +180,24 @@ impl AssistWithDepartments {
                 department_id,
                 total_num,
                 current_num: 0,
-                state: TICKET_STATE_OPEN,
+                state: ASSIST_DEPARTMENT_OPEN,
             })
             .get_result::<Self>(conn)?;
         Ok(a)
     }
 
+    pub fn mget_by_assist_id(
+        conn: &mut PgConnection,
+        assist_id: i32,
+    ) -> Result<Vec<Self>, AppError> {
+        let a = diesel::QueryDsl::filter(
+            assist_department_info::table,
+            assist_department_info::assist_id.eq(assist_id),
+        )
+        .get_results::<Self>(conn)?;
+        Ok(a)
+    }
+
     pub fn mget_assist_id_by_department(
         conn: &mut PgConnection,
         department_id: i32,
@@ -125,18 +211,75 @@ impl AssistWithDepartments {
         Ok(ids)
     }
 
+    // 还在招人、这些部门还缺人的需求，按协助工单排好
+    pub fn mget_open_by_department_ids(
+        conn: &mut PgConnection,
+        department_ids: &[i32],
+    ) -> Result<Vec<Self>, AppError> {
+        let a = FilterDsl::filter(
+            assist_department_info::table.inner_join(assist_info::table),
+            assist_department_info::department_id
+                .eq_any(department_ids)
+                .and(assist_department_info::current_num.lt(assist_department_info::total_num))
+                .and(assist_info::state.eq(ASSIST_STATE_OPEN)),
+        )
+        .select(Self::as_select())
+        .order((
+            assist_department_info::assist_id.asc(),
+            assist_department_info::department_id.asc(),
+        ))
+        .get_results(conn)?;
+        Ok(a)
+    }
+
+    // 人数没满才加一，满了就不更新并返回 None，两个人同时加入最后一个名额时只有一个能成功
     pub fn add_person(
         conn: &mut PgConnection,
         assist_id: i32,
         department_id: i32,
-    ) -> Result<Self, AppError> {
-        let a = diesel::update(assist_department_info::table)
+    ) -> Result<Option<Self>, AppError> {
+        let a: Option<Self> = diesel::update(assist_department_info::table)
             .filter(
                 assist_department_info::assist_id
                     .eq(assist_id)
-                    .and(assist_department_info::department_id.eq(department_id)),
+                    .and(assist_department_info::department_id.eq(department_id))
+                    .and(assist_department_info::current_num.lt(assist_department_info::total_num)),
             )
             .set(assist_department_info::current_num.eq(assist_department_info::current_num + 1))
+            .get_result(conn)
+            .optional()?;
+        let Some(a) = a else {
+            return Ok(None);
+        };
+        if a.current_num >= a.total_num {
+            return Self::update_state(conn, a.id, ASSIST_DEPARTMENT_FULL).map(Some);
+        }
+        Ok(Some(a))
+    }
+
+    pub fn remove_person(
+        conn: &mut PgConnection,
+        assist_id: i32,
+        department_id: i32,
+    ) -> Result<(), AppError> {
+        diesel::update(assist_department_info::table)
+            .filter(
+                assist_department_info::assist_id
+                    .eq(assist_id)
+                    .and(assist_department_info::department_id.eq(department_id))
+                    .and(assist_department_info::current_num.gt(0)),
+            )
+            .set((
+                assist_department_info::current_num.eq(assist_department_info::current_num - 1),
+                assist_department_info::state.eq(ASSIST_DEPARTMENT_OPEN),
+            ))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    fn update_state(conn: &mut PgConnection, id: i32, state: i16) -> Result<Self, AppError> {
+        let a = diesel::update(assist_department_info::table.find(id))
+            .set(assist_department_info::state.eq(state))
             .get_result(conn)?;
         Ok(a)
     }
@@ -151,6 +294,7 @@ pub struct AssistWithEmployees {
     pub id: i32,
     pub assist_id: i32,
     pub employee_id: i32,
+    pub department_id: Option<i32>, // 占了哪个部门的名额
 }
 
 #[derive(Debug, Clone, Insertable)]
@@ -158,6 +302,7 @@ pub struct AssistWithEmployees {
 pub struct InsertAssistWithEmployees {
     pub assist_id: i32,
     pub employee_id: i32,
+    pub department_id: Option<i32>,
 }
 
 impl AssistWithEmployees {
@@ -165,16 +310,81 @@ impl AssistWithEmployees {
         conn: &mut PgConnection,
         assist_id: i32,
         employee_id: i32,
+        department_id: i32,
     ) -> Result<Self, AppError> {
         let a: Self = diesel::insert_into(assist_employee_info::table)
             .values(InsertAssistWithEmployees {
                 assist_id,
                 employee_id,
+                department_id: Some(department_id),
             })
             .get_result(conn)?;
         Ok(a)
     }
 
+    pub fn exists(
+        conn: &mut PgConnection,
+        assist_id: i32,
+        employee_id: i32,
+    ) -> Result<bool, AppError> {
+        let count: i64 = FilterDsl::filter(
+            assist_employee_info::table,
+            assist_employee_info::assist_id
+                .eq(assist_id)
+                .and(assist_employee_info::employee_id.eq(employee_id)),
+        )
+        .count()
+        .get_result(conn)?;
+        Ok(count > 0)
+    }
+
+    // 返回删掉的那条，没接过返回 None
+    pub fn delete(
+        conn: &mut PgConnection,
+        assist_id: i32,
+        employee_id: i32,
+    ) -> Result<Option<Self>, AppError> {
+        let a = diesel::delete(FilterDsl::filter(
+            assist_employee_info::table,
+            assist_employee_info::assist_id
+                .eq(assist_id)
+                .and(assist_employee_info::employee_id.eq(employee_id)),
+        ))
+        .get_result(conn)
+        .optional()?;
+        Ok(a)
+    }
+
+    pub fn mget_employee_id_by_assist_id(
+        conn: &mut PgConnection,
+        assist_id: i32,
+    ) -> Result<Vec<i32>, AppError> {
+        let a: Vec<i32> = FilterDsl::filter(
+            assist_employee_info::table,
+            assist_employee_info::assist_id.eq(assist_id),
+        )
+        .order(assist_employee_info::id.asc())
+        .select(assist_employee_info::employee_id)
+        .get_results(conn)?;
+        Ok(a)
+    }
+
+    // 还没完成或撤销的
+    pub fn mget_active_assist_id_by_involver(
+        conn: &mut PgConnection,
+        involver_id: i32,
+    ) -> Result<Vec<i32>, AppError> {
+        let a: Vec<i32> = FilterDsl::filter(
+            assist_employee_info::table.inner_join(assist_info::table),
+            assist_employee_info::employee_id
+                .eq(involver_id)
+                .and(assist_info::state.eq_any([ASSIST_STATE_OPEN, ASSIST_STATE_STAFFED])),
+        )
+        .select(assist_employee_info::assist_id)
+        .get_results(conn)?;
+        Ok(a)
+    }
+
     pub fn mget_assist_id_by_involver(
         conn: &mut PgConnection,
         involver_id: i32,
@@ -0,0 +1,156 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{new_ok_error, AppError},
+    schema::ticket_attachment,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = ticket_attachment)]
+pub struct Attachment {
+    pub id: i32,
+    pub ticket_id: Option<i32>, // 为空表示上传了还没用
+    pub comment_id: Option<i32>,
+    pub report_id: Option<i32>,
+    pub uploader_id: Option<i32>,
+    pub url: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: Option<i64>,        // 旧图片迁移过来的没有
+    pub checksum: Option<String>, // sha256
+    pub created_time: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = ticket_attachment)]
+pub struct InsertAttachment<'a> {
+    pub uploader_id: i32,
+    pub url: &'a str,
+    pub filename: &'a str,
+    pub content_type: &'a str,
+    pub size: i64,
+    pub checksum: &'a str,
+    pub created_time: NaiveDateTime,
+}
+
+// 附件挂在哪里：只填 ticket_id 是工单本身的附件，再填 comment_id 或 report_id 是评论或完工报告的
+#[derive(AsChangeset, Default)]
+#[diesel(table_name = ticket_attachment)]
+pub struct AttachmentOwner {
+    pub ticket_id: Option<i32>,
+    pub comment_id: Option<i32>,
+    pub report_id: Option<i32>,
+}
+
+impl Attachment {
+    pub fn create(
+        conn: &mut PgConnection,
+        insert_attachment: InsertAttachment,
+    ) -> Result<Attachment, AppError> {
+        let attachment = diesel::insert_into(ticket_attachment::table)
+            .values(insert_attachment)
+            .get_result(conn)?;
+        Ok(attachment)
+    }
+
+    // 只能用自己上传、还没挂到别处的附件
+    pub fn attach(
+        conn: &mut PgConnection,
+        ids: &[i32],
+        uploader_id: i32,
+        owner: AttachmentOwner,
+    ) -> Result<(), AppError> {
+        let mut ids = ids.to_vec();
+        ids.sort_unstable();
+        ids.dedup();
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let count = diesel::update(ticket_attachment::table)
+            .filter(
+                ticket_attachment::id
+                    .eq_any(&ids)
+                    .and(ticket_attachment::ticket_id.is_null())
+                    .and(ticket_attachment::uploader_id.eq(uploader_id)),
+            )
+            .set(owner)
+            .execute(conn)?;
+        if count != ids.len() {
+            return Err(new_ok_error("附件不存在、不是你上传的或者已经用过了"));
+        }
+        Ok(())
+    }
+
+    // 修改工单时附件以这次提交的为准，不在列表里的删掉
+    pub fn replace_by_ticket_id(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        ids: &[i32],
+        uploader_id: i32,
+    ) -> Result<(), AppError> {
+        diesel::delete(ticket_attachment::table)
+            .filter(
+                ticket_attachment::ticket_id
+                    .eq(ticket_id)
+                    .and(ticket_attachment::comment_id.is_null())
+                    .and(ticket_attachment::report_id.is_null())
+                    .and(ticket_attachment::id.ne_all(ids)),
+            )
+            .execute(conn)?;
+        let kept: Vec<i32> = Attachment::mget_by_ticket_id(conn, ticket_id)?
+            .into_iter()
+            .map(|x| x.id)
+            .collect();
+        let new_ids: Vec<i32> = ids.iter().copied().filter(|x| !kept.contains(x)).collect();
+        Attachment::attach(
+            conn,
+            &new_ids,
+            uploader_id,
+            AttachmentOwner {
+                ticket_id: Some(ticket_id),
+                ..Default::default()
+            },
+        )
+    }
+
+    // 工单本身的附件，不含评论和完工报告里的
+    pub fn mget_by_ticket_id(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+    ) -> Result<Vec<Attachment>, AppError> {
+        let attachments = ticket_attachment::table
+            .filter(
+                ticket_attachment::ticket_id
+                    .eq(ticket_id)
+                    .and(ticket_attachment::comment_id.is_null())
+                    .and(ticket_attachment::report_id.is_null()),
+            )
+            .order(ticket_attachment::id.asc())
+            .get_results(conn)?;
+        Ok(attachments)
+    }
+
+    pub fn mget_by_comment_id(
+        conn: &mut PgConnection,
+        comment_id: i32,
+    ) -> Result<Vec<Attachment>, AppError> {
+        let attachments = ticket_attachment::table
+            .filter(ticket_attachment::comment_id.eq(comment_id))
+            .order(ticket_attachment::id.asc())
+            .get_results(conn)?;
+        Ok(attachments)
+    }
+
+    pub fn mget_by_report_id(
+        conn: &mut PgConnection,
+        report_id: i32,
+    ) -> Result<Vec<Attachment>, AppError> {
+        let attachments = ticket_attachment::table
+            .filter(ticket_attachment::report_id.eq(report_id))
+            .order(ticket_attachment::id.asc())
+            .get_results(conn)?;
+        Ok(attachments)
+    }
+}
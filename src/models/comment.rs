@@ -0,0 +1,177 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    schema::{approved_info, assist_employee_info, assist_info, employee_info, ticket_comment},
+};
+
+use super::{
+    account::Account, employee::Employee, notification::Notification, system::System,
+    ticket::Ticket,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = ticket_comment)]
+pub struct Comment {
+    pub id: i32,
+    pub ticket_id: i32,
+    pub employee_id: i32,
+    pub content: String,
+    pub mentioned_ids: Vec<i32>, // 被 @ 并且发了通知的人
+    pub created_time: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = ticket_comment)]
+pub struct InsertComment<'a> {
+    pub ticket_id: i32,
+    pub employee_id: i32,
+    pub content: &'a str,
+    pub mentioned_ids: Vec<i32>,
+    pub created_time: NaiveDateTime,
+}
+
+impl Comment {
+    pub fn create(
+        conn: &mut PgConnection,
+        ticket: &Ticket,
+        author: &Employee,
+        insert_comment: InsertComment,
+    ) -> Result<Comment, AppError> {
+        let comment: Comment = diesel::insert_into(ticket_comment::table)
+            .values(insert_comment)
+            .get_result(conn)?;
+        Notification::on_mentioned(conn, ticket, author, &comment)?;
+        Ok(comment)
+    }
+
+    // 按时间先后，带上评论人的名字
+    pub fn mget_by_ticket_id(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+    ) -> Result<Vec<(Comment, String)>, AppError> {
+        let comments = ticket_comment::table
+            .inner_join(employee_info::table)
+            .filter(ticket_comment::ticket_id.eq(ticket_id))
+            .select((Comment::as_select(), employee_info::name))
+            .order(ticket_comment::id.asc())
+            .get_results(conn)?;
+        Ok(comments)
+    }
+}
+
+// 能看评论的人：创建人、审批过的人、接受人、协助工单的参与人，还有系统管理员
+pub fn mget_viewer_ids(conn: &mut PgConnection, ticket: &Ticket) -> Result<Vec<i32>, AppError> {
+    let mut ids = vec![ticket.creator_id];
+    ids.extend(ticket.receiver_id);
+    let approvers: Vec<i32> = approved_info::table
+        .filter(approved_info::ticket_id.eq(ticket.id))
+        .select(approved_info::employee_id)
+        .get_results(conn)?;
+    ids.extend(approvers);
+    let assistants: Vec<i32> = assist_employee_info::table
+        .inner_join(assist_info::table)
+        .filter(assist_info::ticket_id.eq(ticket.id))
+        .select(assist_employee_info::employee_id)
+        .get_results(conn)?;
+    ids.extend(assistants);
+    let system = System::get_by_id(conn, ticket.system_id)?;
+    if let Some(account_id) = system.admin_account_id {
+        ids.push(Account::find(conn, account_id)?.employee_id);
+    }
+    ids.sort_unstable();
+    ids.dedup();
+    Ok(ids)
+}
+
+// 把 @ 到的名字换成员工 id，只留能看到这条评论的人，不通知自己
+pub fn resolve_mentions(
+    conn: &mut PgConnection,
+    system_id: i32,
+    content: &str,
+    viewer_ids: &[i32],
+    author_id: i32,
+) -> Result<Vec<i32>, AppError> {
+    if !content.contains('@') {
+        return Ok(vec![]);
+    }
+    let viewers: Vec<(i32, String)> = employee_info::table
+        .filter(
+            employee_info::system_id
+                .eq(system_id)
+                .and(employee_info::id.eq_any(viewer_ids))
+                .and(employee_info::id.ne(author_id)),
+        )
+        .select((employee_info::id, employee_info::name))
+        .get_results(conn)?;
+    Ok(parse_mentions(content, &viewers))
+}
+
+// @ 后面按能看到工单的人的名字找，取最长的那个，中文名后面不用空格隔开。
+// @ 前面紧挨着英文字母数字的不算，避免把邮箱当成 @；名字后面紧挨着英文字母数字的也不算
+fn parse_mentions(content: &str, viewers: &[(i32, String)]) -> Vec<i32> {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    let mut ids = vec![];
+    let mut prev: Option<char> = None;
+    for (i, c) in content.char_indices() {
+        if c == '@' && !prev.is_some_and(|x| x.is_ascii_alphanumeric() || x == '_') {
+            let rest = &content[i + 1..];
+            let name = viewers
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .filter(|name| {
+                    !name.is_empty()
+                        && rest.starts_with(name)
+                        && !rest[name.len()..].starts_with(is_name_char)
+                })
+                .max_by_key(|name| name.len());
+            if let Some(name) = name {
+                // 重名的都算
+                ids.extend(viewers.iter().filter(|x| x.1 == name).map(|x| x.0));
+            }
+        }
+        prev = Some(c);
+    }
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mentions() {
+        let viewers: Vec<(i32, String)> = [
+            (1, "张三"),
+            (2, "李四"),
+            (3, "王五"),
+            (4, "张三丰"),
+            (5, "zhang_san"),
+            (6, "ops-1"),
+            (7, "zhang"),
+        ]
+        .into_iter()
+        .map(|(id, name)| (id, name.to_string()))
+        .collect();
+        let parse = |content: &str| parse_mentions(content, &viewers);
+        assert_eq!(parse("@张三 你看下，@李四，还有@王五。"), vec![1, 2, 3]);
+        // 中文名后面直接跟着正文
+        assert_eq!(parse("@张三请看一下"), vec![1]);
+        assert_eq!(parse("@李四@王五帮忙"), vec![2, 3]);
+        // 取最长的名字
+        assert_eq!(parse("@张三丰来了"), vec![4]);
+        assert_eq!(parse("@zhang_san\n@zhang_san"), vec![5]);
+        assert_eq!(parse("@zhang 看下"), vec![7]);
+        assert!(parse("@zhangsan").is_empty());
+        assert!(parse("发到 a@example.com").is_empty());
+        assert!(parse("@ 空的 @@ @赵六").is_empty());
+        assert_eq!(parse("(@ops-1)"), vec![6]);
+        // 重名的都通知
+        let viewers = vec![(1, "张三".to_string()), (8, "张三".to_string())];
+        assert_eq!(parse_mentions("@张三", &viewers), vec![1, 8]);
+    }
+}
@@ -77,6 +77,7 @@ impl Department {
 pub struct EmployeeWithDepartments {
     pub employee_id: i32,
     pub department_id: i32,
+    pub is_lead: bool, // 部门负责人
 }
 
 #[derive(Insertable)]
@@ -101,6 +102,51 @@ impl EmployeeWithDepartments {
         Ok(a)
     }
 
+    pub fn mget_employee_id_by_department_ids(
+        conn: &mut PgConnection,
+        department_ids: &[i32],
+    ) -> Result<Vec<i32>, AppError> {
+        let a = FilterDsl::filter(
+            employee_operation_info::table,
+            employee_operation_info::department_id.eq_any(department_ids),
+        )
+        .select(employee_operation_info::employee_id)
+        .distinct()
+        .get_results::<i32>(conn)?;
+        Ok(a)
+    }
+
+    // 返回改了几行，0 表示这个人不在这个部门
+    pub fn set_lead(
+        conn: &mut PgConnection,
+        employee_id: i32,
+        department_id: i32,
+        is_lead: bool,
+    ) -> Result<usize, AppError> {
+        let n = diesel::update(employee_operation_info::table.find((employee_id, department_id)))
+            .set(employee_operation_info::is_lead.eq(is_lead))
+            .execute(conn)?;
+        Ok(n)
+    }
+
+    // 是不是其中某个部门的负责人
+    pub fn is_lead_of_any(
+        conn: &mut PgConnection,
+        employee_id: i32,
+        department_ids: &[i32],
+    ) -> Result<bool, AppError> {
+        let count: i64 = FilterDsl::filter(
+            employee_operation_info::table,
+            employee_operation_info::employee_id
+                .eq(employee_id)
+                .and(employee_operation_info::department_id.eq_any(department_ids))
+                .and(employee_operation_info::is_lead.eq(true)),
+        )
+        .count()
+        .get_result(conn)?;
+        Ok(count > 0)
+    }
+
     pub fn mget_department_id_by_employee_id(
         conn: &mut PgConnection,
         employee_id: i32,
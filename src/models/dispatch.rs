@@ -0,0 +1,190 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::{
+    error::AppError,
+    schema::{account_info, employee_info},
+    utils::constant::{
+        TicketState, ACCOUNT_TYPE_OPERATOR, DISPATCH_STRATEGY_LEAST_LOADED,
+        DISPATCH_STRATEGY_LONGEST_IDLE, DISPATCH_STRATEGY_MANUAL, DISPATCH_STRATEGY_ROUND_ROBIN,
+        EMPLOYEE_STATUS_AVAILABLE,
+    },
+};
+
+use super::{
+    department::EmployeeWithDepartments,
+    employee::Employee,
+    system::System,
+    ticket::{Ticket, TicketWithDepartments},
+};
+
+const STRATEGIES: [(i16, &str); 4] = [
+    (DISPATCH_STRATEGY_MANUAL, "manual"),
+    (DISPATCH_STRATEGY_ROUND_ROBIN, "round_robin"),
+    (DISPATCH_STRATEGY_LEAST_LOADED, "least_loaded"),
+    (DISPATCH_STRATEGY_LONGEST_IDLE, "longest_idle"),
+];
+
+pub fn strategy_name(strategy: i16) -> &'static str {
+    STRATEGIES
+        .iter()
+        .find(|(x, _)| *x == strategy)
+        .map_or("manual", |(_, name)| name)
+}
+
+pub fn strategy_from_name(name: &str) -> Option<i16> {
+    STRATEGIES
+        .iter()
+        .find(|(_, x)| *x == name)
+        .map(|(strategy, _)| *strategy)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Candidate {
+    employee_id: i32,
+    wip: i64,
+    last_assigned_time: Option<NaiveDateTime>,
+}
+
+// 按策略排好先后，前面的人接不了再往后找
+fn order(strategy: i16, cursor: Option<i32>, mut candidates: Vec<Candidate>) -> Vec<i32> {
+    match strategy {
+        DISPATCH_STRATEGY_LEAST_LOADED => candidates.sort_by_key(|x| (x.wip, x.employee_id)),
+        // 从没接过单的排最前
+        DISPATCH_STRATEGY_LONGEST_IDLE => {
+            candidates.sort_by_key(|x| (x.last_assigned_time, x.employee_id))
+        }
+        // 从上一次派给的人后面接着轮
+        _ => {
+            candidates.sort_by_key(|x| x.employee_id);
+            let start = cursor.map_or(0, |cursor| {
+                candidates
+                    .iter()
+                    .position(|x| x.employee_id > cursor)
+                    .unwrap_or(0)
+            });
+            candidates.rotate_left(start);
+        }
+    }
+    candidates.into_iter().map(|x| x.employee_id).collect()
+}
+
+// 工单部门里空闲的运维，exclude 是刚被收回工单的人
+fn mget_candidates(
+    conn: &mut PgConnection,
+    ticket: &Ticket,
+    exclude: Option<i32>,
+) -> Result<Vec<Candidate>, AppError> {
+    let department_ids =
+        TicketWithDepartments::mget_department_id_by_ticket_ids(conn, &[ticket.id])?
+            .remove(&ticket.id)
+            .unwrap_or_default();
+    let ids: Vec<i32> =
+        EmployeeWithDepartments::mget_employee_id_by_department_ids(conn, &department_ids)?
+            .into_iter()
+            .filter(|x| Some(*x) != exclude)
+            .collect();
+    let employees: Vec<Employee> = employee_info::table
+        .inner_join(account_info::table)
+        .filter(
+            employee_info::id
+                .eq_any(ids)
+                .and(employee_info::system_id.eq(ticket.system_id))
+                .and(employee_info::state.eq(EMPLOYEE_STATUS_AVAILABLE))
+                .and(account_info::account_type.eq(ACCOUNT_TYPE_OPERATOR)),
+        )
+        .select(Employee::as_select())
+        .get_results(conn)?;
+    let mut candidates = vec![];
+    for employee in employees.into_iter() {
+        candidates.push(Candidate {
+            employee_id: employee.id,
+            wip: Employee::count_wip(conn, employee.id)?,
+            last_assigned_time: employee.last_assigned_time,
+        });
+    }
+    Ok(candidates)
+}
+
+// 工单变成待接取之后调用，在同一个事务里。系统没开自动派单或者没人能接就留在待接取
+pub fn auto_dispatch(
+    conn: &mut PgConnection,
+    ticket_id: i32,
+    exclude: Option<i32>,
+) -> Result<Option<i32>, AppError> {
+    let ticket = Ticket::get_by_id(conn, ticket_id)?;
+    if ticket.state != TicketState::Open || ticket.receiver_id.is_some() {
+        return Ok(None);
+    }
+    // 锁住系统，同时派单的不会都派给同一个人
+    let system = System::get_by_id_for_update(conn, ticket.system_id)?;
+    if system.dispatch_strategy == DISPATCH_STRATEGY_MANUAL {
+        return Ok(None);
+    }
+    let candidates = mget_candidates(conn, &ticket, exclude)?;
+    for employee_id in order(system.dispatch_strategy, system.dispatch_cursor, candidates) {
+        // 排序之后可能有人接了别的单
+        let employee = Employee::get_by_id_for_update(conn, employee_id)?;
+        if Employee::count_wip(conn, employee_id)? >= employee.max_wip as i64 {
+            continue;
+        }
+        Ticket::dispatch(conn, ticket.id, employee_id)?;
+        Employee::occupy(conn, employee_id)?;
+        if system.dispatch_strategy == DISPATCH_STRATEGY_ROUND_ROBIN {
+            System::set_dispatch_cursor(conn, system.id, employee_id)?;
+        }
+        return Ok(Some(employee_id));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn candidate(employee_id: i32, wip: i64, day: Option<u32>) -> Candidate {
+        Candidate {
+            employee_id,
+            wip,
+            last_assigned_time: day.map(|d| {
+                NaiveDate::from_ymd_opt(2026, 10, d)
+                    .unwrap()
+                    .and_hms_opt(0, 0, 0)
+                    .unwrap()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_order() {
+        let candidates = vec![
+            candidate(3, 0, Some(2)),
+            candidate(1, 2, Some(1)),
+            candidate(5, 1, None),
+        ];
+        let round_robin = |cursor| order(DISPATCH_STRATEGY_ROUND_ROBIN, cursor, candidates.clone());
+        assert_eq!(round_robin(None), vec![1, 3, 5]);
+        assert_eq!(round_robin(Some(1)), vec![3, 5, 1]);
+        assert_eq!(round_robin(Some(4)), vec![5, 1, 3]);
+        assert_eq!(round_robin(Some(5)), vec![1, 3, 5]);
+        assert_eq!(
+            order(DISPATCH_STRATEGY_LEAST_LOADED, None, candidates.clone()),
+            vec![3, 5, 1]
+        );
+        assert_eq!(
+            order(DISPATCH_STRATEGY_LONGEST_IDLE, None, candidates),
+            vec![5, 1, 3]
+        );
+    }
+
+    #[test]
+    fn test_strategy_name() {
+        for (strategy, name) in STRATEGIES {
+            assert_eq!(strategy_from_name(name), Some(strategy));
+            assert_eq!(strategy_name(strategy), name);
+        }
+        assert_eq!(strategy_from_name("random"), None);
+    }
+}
@@ -1,7 +1,15 @@
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::AppError, schema::employee_info};
+use crate::{
+    error::{new_conflict_error, AppError},
+    schema::{assist_employee_info, assist_info, employee_info, ticket_info},
+    utils::constant::{
+        TicketState, ASSIST_STATE_OPEN, ASSIST_STATE_STAFFED, EMPLOYEE_STATUS_AVAILABLE,
+        EMPLOYEE_STATUS_UNAVAILABLE,
+    },
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Selectable, Identifiable, Queryable)]
 #[diesel(table_name = employee_info)]
@@ -16,6 +24,10 @@ pub struct Employee {
     pub system_id: i32,
     pub sex: i16,
     pub company_name: Option<String>,
+    pub email: Option<String>,
+    pub email_opt_out: bool, // 不接收邮件通知
+    pub max_wip: i32,        // 同时处理的工单上限
+    pub last_assigned_time: Option<NaiveDateTime>,
 }
 
 #[derive(Insertable)]
@@ -29,6 +41,8 @@ pub struct InsertEmployee<'a> {
     pub system_id: i32,
     pub sex: i16,
     pub company_name: Option<&'a str>,
+    pub email: Option<&'a str>,
+    pub max_wip: Option<i32>, // 不填用数据库默认值
 }
 
 impl Employee {
@@ -47,6 +61,38 @@ impl Employee {
         Ok(employee)
     }
 
+    // 某个审批级别的所有审批人
+    pub fn mget_id_by_approval_id(
+        conn: &mut PgConnection,
+        system_id: i32,
+        approval_id: i32,
+    ) -> Result<Vec<i32>, AppError> {
+        let ids = employee_info::table
+            .filter(
+                employee_info::system_id
+                    .eq(system_id)
+                    .and(employee_info::approval_id.eq(approval_id)),
+            )
+            .select(employee_info::id)
+            .get_results(conn)?;
+        Ok(ids)
+    }
+
+    pub fn update_email(
+        conn: &mut PgConnection,
+        id: i32,
+        email: Option<&str>,
+        email_opt_out: bool,
+    ) -> Result<Employee, AppError> {
+        let employee = diesel::update(employee_info::table.filter(employee_info::id.eq(id)))
+            .set((
+                employee_info::email.eq(email),
+                employee_info::email_opt_out.eq(email_opt_out),
+            ))
+            .get_result(conn)?;
+        Ok(employee)
+    }
+
     pub fn update_state(
         conn: &mut PgConnection,
         id: i32,
@@ -57,4 +103,72 @@ impl Employee {
             .get_result(conn)?;
         Ok(employee)
     }
+
+    // 接单前锁住，避免同时接单超过上限
+    pub fn get_by_id_for_update(conn: &mut PgConnection, id: i32) -> Result<Employee, AppError> {
+        let employee: Employee = employee_info::table.find(id).for_update().first(conn)?;
+        Ok(employee)
+    }
+
+    // 正在处理的主工单和协助工单数
+    pub fn count_wip(conn: &mut PgConnection, id: i32) -> Result<i64, AppError> {
+        let tickets: i64 = ticket_info::table
+            .filter(
+                ticket_info::receiver_id
+                    .eq(id)
+                    .and(ticket_info::state.eq(TicketState::Assigned)),
+            )
+            .count()
+            .get_result(conn)?;
+        let assists: i64 = assist_employee_info::table
+            .inner_join(assist_info::table)
+            .filter(
+                assist_employee_info::employee_id
+                    .eq(id)
+                    .and(assist_info::state.eq_any([ASSIST_STATE_OPEN, ASSIST_STATE_STAFFED])),
+            )
+            .count()
+            .get_result(conn)?;
+        Ok(tickets + assists)
+    }
+
+    // 忙不忙按手上实际的工单数算，接单、完成、退出之后都要调一下
+    pub fn refresh_state(conn: &mut PgConnection, id: i32) -> Result<Employee, AppError> {
+        let employee = Self::get_by_id(conn, id)?;
+        let state = if Self::count_wip(conn, id)? < employee.max_wip as i64 {
+            EMPLOYEE_STATUS_AVAILABLE
+        } else {
+            EMPLOYEE_STATUS_UNAVAILABLE
+        };
+        if state == employee.state {
+            return Ok(employee);
+        }
+        Self::update_state(conn, id, state)
+    }
+
+    pub fn update_max_wip(
+        conn: &mut PgConnection,
+        id: i32,
+        max_wip: i32,
+    ) -> Result<Employee, AppError> {
+        diesel::update(employee_info::table.filter(employee_info::id.eq(id)))
+            .set(employee_info::max_wip.eq(max_wip))
+            .execute(conn)?;
+        Self::refresh_state(conn, id)
+    }
+
+    // 接单或被派单之后调用，先占工单再锁人，和完成、超时改派的加锁顺序一致
+    pub fn occupy(conn: &mut PgConnection, id: i32) -> Result<Employee, AppError> {
+        let employee = Self::get_by_id_for_update(conn, id)?;
+        if Self::count_wip(conn, id)? > employee.max_wip as i64 {
+            return Err(new_conflict_error(&format!(
+                "同时处理的工单已达到上限（{}个）",
+                employee.max_wip
+            )));
+        }
+        diesel::update(employee_info::table.filter(employee_info::id.eq(id)))
+            .set(employee_info::last_assigned_time.eq(chrono::Utc::now().naive_utc()))
+            .execute(conn)?;
+        Self::refresh_state(conn, id)
+    }
 }
@@ -0,0 +1,146 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::NaiveDateTime;
+use diesel::{prelude::*, query_dsl::methods::FilterDsl};
+use serde::{Deserialize, Serialize};
+
+use super::{notification::Notification, webhook::WebhookDelivery};
+use crate::{
+    error::AppError,
+    schema::{ticket_event, ticket_info},
+    utils::constant::TicketState,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = ticket_event)]
+pub struct TicketEvent {
+    pub id: i32,
+    pub ticket_id: i32,
+    pub employee_id: Option<i32>, // 为空表示系统自动操作
+    pub event_type: i16,
+    pub old_state: Option<TicketState>,
+    pub new_state: TicketState,
+    pub comment: Option<String>,
+    pub created_time: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = ticket_event)]
+pub struct InsertTicketEvent<'a> {
+    pub ticket_id: i32,
+    pub employee_id: Option<i32>,
+    pub event_type: i16,
+    pub old_state: Option<TicketState>,
+    pub new_state: TicketState,
+    pub comment: Option<&'a str>,
+    pub created_time: NaiveDateTime,
+}
+
+impl TicketEvent {
+    pub fn create(
+        conn: &mut PgConnection,
+        insert_event: InsertTicketEvent,
+    ) -> Result<TicketEvent, AppError> {
+        let event = diesel::insert_into(ticket_event::table)
+            .values(insert_event)
+            .get_result(conn)?;
+        Notification::on_ticket_event(conn, &event)?;
+        WebhookDelivery::on_ticket_event(conn, &event)?;
+        Ok(event)
+    }
+
+    pub fn mget_by_ticket_id(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+    ) -> Result<Vec<TicketEvent>, AppError> {
+        let events = FilterDsl::filter(ticket_event::table, ticket_event::ticket_id.eq(ticket_id))
+            .order((ticket_event::created_time.asc(), ticket_event::id.asc()))
+            .get_results(conn)?;
+        Ok(events)
+    }
+
+    // 每个工单最后一个事件，用来算进入当前状态的时间
+    pub fn mget_latest_by_ticket_ids(
+        conn: &mut PgConnection,
+        ticket_ids: &[i32],
+    ) -> Result<HashMap<i32, TicketEvent>, AppError> {
+        let events: Vec<TicketEvent> = FilterDsl::filter(
+            ticket_event::table,
+            ticket_event::ticket_id.eq_any(ticket_ids),
+        )
+        .order((ticket_event::created_time.asc(), ticket_event::id.asc()))
+        .get_results(conn)?;
+        Ok(events.into_iter().map(|x| (x.ticket_id, x)).collect())
+    }
+
+    // 每个工单的所有事件，按时间先后
+    pub fn mget_by_ticket_ids(
+        conn: &mut PgConnection,
+        ticket_ids: &[i32],
+    ) -> Result<HashMap<i32, Vec<TicketEvent>>, AppError> {
+        let events: Vec<TicketEvent> = FilterDsl::filter(
+            ticket_event::table,
+            ticket_event::ticket_id.eq_any(ticket_ids),
+        )
+        .order((ticket_event::created_time.asc(), ticket_event::id.asc()))
+        .get_results(conn)?;
+        let mut ret: HashMap<i32, Vec<TicketEvent>> = HashMap::new();
+        for event in events.into_iter() {
+            ret.entry(event.ticket_id).or_default().push(event);
+        }
+        Ok(ret)
+    }
+
+    // 某一时刻每个工单的状态，就是那之前最后一个事件的 new_state
+    pub fn mget_state_at_moment(
+        conn: &mut PgConnection,
+        system_id: i32,
+        t: NaiveDateTime,
+    ) -> Result<HashMap<i32, TicketState>, AppError> {
+        let events: Vec<(i32, TicketState)> = FilterDsl::filter(
+            ticket_event::table.inner_join(ticket_info::table),
+            ticket_info::system_id
+                .eq(system_id)
+                .and(ticket_event::created_time.le(t)),
+        )
+        .select((ticket_event::ticket_id, ticket_event::new_state))
+        .order((ticket_event::created_time.asc(), ticket_event::id.asc()))
+        .get_results(conn)?;
+        Ok(events.into_iter().collect())
+    }
+
+    // 有事件记录的工单，没有的是加事件表之前的老工单
+    pub fn mget_recorded_ticket_ids(
+        conn: &mut PgConnection,
+        system_id: i32,
+    ) -> Result<HashSet<i32>, AppError> {
+        let ids: Vec<i32> = FilterDsl::filter(
+            ticket_event::table.inner_join(ticket_info::table),
+            ticket_info::system_id.eq(system_id),
+        )
+        .select(ticket_event::ticket_id)
+        .distinct()
+        .get_results(conn)?;
+        Ok(ids.into_iter().collect())
+    }
+
+    // 某一时刻之前发生过这种事件的工单
+    pub fn mget_ticket_id_by_event_type(
+        conn: &mut PgConnection,
+        system_id: i32,
+        event_type: i16,
+        t: NaiveDateTime,
+    ) -> Result<HashSet<i32>, AppError> {
+        let ids: Vec<i32> = FilterDsl::filter(
+            ticket_event::table.inner_join(ticket_info::table),
+            ticket_info::system_id
+                .eq(system_id)
+                .and(ticket_event::event_type.eq(event_type))
+                .and(ticket_event::created_time.le(t)),
+        )
+        .select(ticket_event::ticket_id)
+        .distinct()
+        .get_results(conn)?;
+        Ok(ids.into_iter().collect())
+    }
+}
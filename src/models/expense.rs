@@ -0,0 +1,389 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    schema::{overrun_approval, ticket_expense, ticket_info},
+    utils::constant::{
+        OVERRUN_STATE_APPROVED, OVERRUN_STATE_PENDING, OVERRUN_STATE_REJECTED, TICKET_EVENT_OVERRUN,
+    },
+    utils::money::{Amount, Money},
+};
+
+use super::{
+    approval::Approval,
+    employee::Employee,
+    event::{InsertTicketEvent, TicketEvent},
+    notification::Notification,
+    system::System,
+    ticket::Ticket,
+};
+
+// 实际花费台账，完工报告里填的花费也记在这里
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = ticket_expense)]
+pub struct Expense {
+    pub id: i32,
+    pub ticket_id: i32,
+    pub employee_id: i32,
+    pub report_id: Option<i32>,
+    pub reason: String,
+    pub amount: Amount,
+    pub receipt: Option<String>, // 票据的地址
+    pub created_time: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = ticket_expense)]
+pub struct InsertExpense<'a> {
+    pub ticket_id: i32,
+    pub employee_id: i32,
+    pub report_id: Option<i32>,
+    pub reason: &'a str,
+    pub amount: Amount,
+    pub receipt: Option<&'a str>,
+    pub created_time: NaiveDateTime,
+}
+
+impl Expense {
+    pub fn mcreate(
+        conn: &mut PgConnection,
+        inserts: Vec<InsertExpense>,
+    ) -> Result<usize, AppError> {
+        let n = diesel::insert_into(ticket_expense::table)
+            .values(inserts)
+            .execute(conn)?;
+        Ok(n)
+    }
+
+    pub fn mget_by_ticket_id(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+    ) -> Result<Vec<Expense>, AppError> {
+        let expenses = ticket_expense::table
+            .filter(ticket_expense::ticket_id.eq(ticket_id))
+            .order(ticket_expense::id.asc())
+            .get_results(conn)?;
+        Ok(expenses)
+    }
+
+    pub fn mget_by_report_id(
+        conn: &mut PgConnection,
+        report_id: i32,
+    ) -> Result<Vec<Expense>, AppError> {
+        let expenses = ticket_expense::table
+            .filter(ticket_expense::report_id.eq(report_id))
+            .order(ticket_expense::id.asc())
+            .get_results(conn)?;
+        Ok(expenses)
+    }
+
+    pub fn get_total_by_ticket_id(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+    ) -> Result<Amount, AppError> {
+        let amounts: Vec<Amount> = ticket_expense::table
+            .filter(ticket_expense::ticket_id.eq(ticket_id))
+            .select(ticket_expense::amount)
+            .get_results(conn)?;
+        Ok(amounts.into_iter().sum())
+    }
+}
+
+// 超出预算的部分走一遍审批
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = overrun_approval)]
+pub struct Overrun {
+    pub id: i32,
+    pub ticket_id: i32,
+    pub amount: Amount,           // 超出预算的部分
+    pub approval_id: Option<i32>, // 当前轮到的审批级别
+    pub state: i16,               // OVERRUN_STATE_*
+    pub last_approver_id: Option<i32>,
+    pub comment: Option<String>,
+    pub created_time: NaiveDateTime,
+    pub finished_time: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = overrun_approval)]
+pub struct InsertOverrun {
+    pub ticket_id: i32,
+    pub amount: Amount,
+    pub approval_id: Option<i32>,
+    pub state: i16,
+    pub created_time: NaiveDateTime,
+    pub finished_time: Option<NaiveDateTime>,
+}
+
+#[derive(AsChangeset)]
+#[diesel(table_name = overrun_approval)]
+struct UpdateOverrun<'a> {
+    approval_id: Option<Option<i32>>,
+    state: i16,
+    last_approver_id: Option<i32>,
+    comment: Option<&'a str>,
+    finished_time: Option<NaiveDateTime>,
+}
+
+impl Overrun {
+    pub fn get_by_id(conn: &mut PgConnection, id: i32) -> Result<Overrun, AppError> {
+        let overrun = overrun_approval::table.find(id).get_result(conn)?;
+        Ok(overrun)
+    }
+
+    // 审批时先锁工单再锁超支审批，和记花费的顺序一样
+    pub fn get_by_id_for_update(conn: &mut PgConnection, id: i32) -> Result<Overrun, AppError> {
+        let overrun = overrun_approval::table
+            .find(id)
+            .for_update()
+            .get_result(conn)?;
+        Ok(overrun)
+    }
+
+    pub fn get_pending_by_ticket_id(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+    ) -> Result<Option<Overrun>, AppError> {
+        let overrun = overrun_approval::table
+            .filter(
+                overrun_approval::ticket_id
+                    .eq(ticket_id)
+                    .and(overrun_approval::state.eq(OVERRUN_STATE_PENDING)),
+            )
+            .first(conn)
+            .optional()?;
+        Ok(overrun)
+    }
+
+    pub fn mget_by_ticket_id(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+    ) -> Result<Vec<Overrun>, AppError> {
+        let overruns = overrun_approval::table
+            .filter(overrun_approval::ticket_id.eq(ticket_id))
+            .order(overrun_approval::id.asc())
+            .get_results(conn)?;
+        Ok(overruns)
+    }
+
+    // 轮到这一级审批的，先提交的在前
+    pub fn mget_pending_by_approval_id(
+        conn: &mut PgConnection,
+        system_id: i32,
+        approval_id: i32,
+    ) -> Result<Vec<(Overrun, Ticket)>, AppError> {
+        let overruns = overrun_approval::table
+            .inner_join(ticket_info::table)
+            .filter(
+                overrun_approval::approval_id
+                    .eq(approval_id)
+                    .and(overrun_approval::state.eq(OVERRUN_STATE_PENDING))
+                    .and(ticket_info::system_id.eq(system_id)),
+            )
+            .order(overrun_approval::id.asc())
+            .select((Overrun::as_select(), Ticket::as_select()))
+            .get_results(conn)?;
+        Ok(overruns)
+    }
+
+    pub fn get_approved_total(conn: &mut PgConnection, ticket_id: i32) -> Result<Amount, AppError> {
+        let amounts: Vec<Amount> = overrun_approval::table
+            .filter(
+                overrun_approval::ticket_id
+                    .eq(ticket_id)
+                    .and(overrun_approval::state.eq(OVERRUN_STATE_APPROVED)),
+            )
+            .select(overrun_approval::amount)
+            .get_results(conn)?;
+        Ok(amounts.into_iter().sum())
+    }
+
+    // 和工单审批一样，在这一级的额度内就通过，否则交给下一级，没有下一级也算通过
+    pub fn approve(
+        conn: &mut PgConnection,
+        overrun: &Overrun,
+        approver: &Employee,
+        comment: Option<&str>,
+    ) -> Result<Overrun, AppError> {
+        let ticket = Ticket::get_by_id(conn, overrun.ticket_id)?;
+        let amount = Money::new(overrun.amount.clone(), &ticket.currency);
+        let limit = match overrun.approval_id {
+            Some(approval_id) => Approval::get_by_id(conn, approval_id)?.limit(),
+            None => Money::new(Amount::zero(), &ticket.currency),
+        };
+        let next = if amount.checked_le(&limit)? {
+            None
+        } else {
+            Approval::get_next_by_company(
+                conn,
+                approver.system_id,
+                approver.company_name.clone(),
+                limit.amount,
+            )?
+        };
+        let (state, finished_time, event_comment) = match next {
+            Some(ref next) => (
+                OVERRUN_STATE_PENDING,
+                None,
+                format!("超支审批通过一级，交给{}", next.approval_name),
+            ),
+            None => (
+                OVERRUN_STATE_APPROVED,
+                Some(chrono::Utc::now().naive_local()),
+                format!("超支 {} 审批通过", amount),
+            ),
+        };
+        let updated = diesel::update(overrun_approval::table.find(overrun.id))
+            .set(UpdateOverrun {
+                approval_id: Some(next.as_ref().map(|x| x.id)),
+                state,
+                last_approver_id: Some(approver.id),
+                comment,
+                finished_time,
+            })
+            .get_result(conn)?;
+        create_event(conn, &ticket, Some(approver.id), &event_comment)?;
+        if next.is_some() {
+            Notification::on_overrun_pending(conn, &ticket, &updated)?;
+        } else {
+            Notification::on_overrun_decided(conn, &ticket, &updated)?;
+        }
+        Ok(updated)
+    }
+
+    // 驳回之后花费还在台账里，预算不增加
+    pub fn reject(
+        conn: &mut PgConnection,
+        overrun: &Overrun,
+        approver: &Employee,
+        comment: Option<&str>,
+    ) -> Result<Overrun, AppError> {
+        let updated = diesel::update(overrun_approval::table.find(overrun.id))
+            .set(UpdateOverrun {
+                approval_id: None,
+                state: OVERRUN_STATE_REJECTED,
+                last_approver_id: Some(approver.id),
+                comment,
+                finished_time: Some(chrono::Utc::now().naive_local()),
+            })
+            .get_result(conn)?;
+        let ticket = Ticket::get_by_id(conn, overrun.ticket_id)?;
+        let amount = Money::new(overrun.amount.clone(), &ticket.currency);
+        let event_comment = match comment {
+            Some(comment) => format!("超支 {} 审批驳回：{}", amount, comment),
+            None => format!("超支 {} 审批驳回", amount),
+        };
+        create_event(conn, &ticket, Some(approver.id), &event_comment)?;
+        Notification::on_overrun_decided(conn, &ticket, &updated)?;
+        Ok(updated)
+    }
+}
+
+// 超支审批不改工单状态，事件记在工单上
+fn create_event(
+    conn: &mut PgConnection,
+    ticket: &Ticket,
+    employee_id: Option<i32>,
+    comment: &str,
+) -> Result<(), AppError> {
+    TicketEvent::create(
+        conn,
+        InsertTicketEvent {
+            ticket_id: ticket.id,
+            employee_id,
+            event_type: TICKET_EVENT_OVERRUN,
+            old_state: Some(ticket.state),
+            new_state: ticket.state,
+            comment: Some(comment),
+            created_time: chrono::Utc::now().naive_local(),
+        },
+    )?;
+    Ok(())
+}
+
+// 审批过的预算加上批准的超支
+pub fn get_budget(conn: &mut PgConnection, ticket: &Ticket) -> Result<Amount, AppError> {
+    Ok(ticket.amount.clone() + Overrun::get_approved_total(conn, ticket.id)?)
+}
+
+// 实际花费超过预算的 percent% 以上
+fn exceeds(budget: &Amount, actual: &Amount, percent: i32) -> bool {
+    actual.exceeds(budget, percent)
+}
+
+// 记完花费之后在同一个事务里调用，调用方已经锁住工单。
+// 超过阈值就把超出预算的部分交给审批，已经在审批的只更新金额
+pub fn check_overrun(conn: &mut PgConnection, ticket_id: i32) -> Result<Option<Overrun>, AppError> {
+    let ticket = Ticket::get_by_id(conn, ticket_id)?;
+    let system = System::get_by_id(conn, ticket.system_id)?;
+    let budget = get_budget(conn, &ticket)?;
+    let actual = Expense::get_total_by_ticket_id(conn, ticket.id)?;
+    if !exceeds(&budget, &actual, system.overrun_threshold_percent) {
+        return Ok(None);
+    }
+    let amount = actual.clone() - budget;
+    if let Some(pending) = Overrun::get_pending_by_ticket_id(conn, ticket.id)? {
+        let overrun = diesel::update(overrun_approval::table.find(pending.id))
+            .set(overrun_approval::amount.eq(&amount))
+            .get_result(conn)?;
+        return Ok(Some(overrun));
+    }
+    // 和新工单一样从最低一级开始审批
+    let creator = Employee::get_by_id(conn, ticket.creator_id)?;
+    let mut approval =
+        Approval::get_next_by_company(conn, system.id, creator.company_name, Amount::zero())?;
+    if approval.is_none() {
+        approval = Approval::get_next_by_company(conn, system.id, None, Amount::zero())?;
+    }
+    let now = chrono::Utc::now().naive_local();
+    // 系统里没有审批级别就直接通过
+    let (state, finished_time) = match approval {
+        Some(_) => (OVERRUN_STATE_PENDING, None),
+        None => (OVERRUN_STATE_APPROVED, Some(now)),
+    };
+    let overrun: Overrun = diesel::insert_into(overrun_approval::table)
+        .values(InsertOverrun {
+            ticket_id: ticket.id,
+            amount: amount.clone(),
+            approval_id: approval.map(|x| x.id),
+            state,
+            created_time: now,
+            finished_time,
+        })
+        .get_result(conn)?;
+    create_event(
+        conn,
+        &ticket,
+        None,
+        &format!(
+            "实际花费 {}，超出预算 {}，提交审批",
+            Money::new(actual, &ticket.currency),
+            Money::new(amount, &ticket.currency)
+        ),
+    )?;
+    if overrun.state == OVERRUN_STATE_PENDING {
+        Notification::on_overrun_pending(conn, &ticket, &overrun)?;
+    }
+    Ok(Some(overrun))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exceeds() {
+        let exceeds = |budget: &str, actual: &str, percent| {
+            exceeds(&budget.parse().unwrap(), &actual.parse().unwrap(), percent)
+        };
+        assert!(!exceeds("1000", "1000", 10));
+        assert!(!exceeds("1000", "1100", 10));
+        assert!(exceeds("1000", "1100.01", 10));
+        assert!(exceeds("1000", "1001", 0));
+        // 没有预算的工单花一分钱都算超支
+        assert!(exceeds("0", "0.01", 50));
+        assert!(!exceeds("0", "0", 0));
+    }
+}
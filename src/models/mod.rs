@@ -1,7 +1,16 @@
 pub mod account;
 pub mod approval;
 pub mod assist;
+pub mod attachment;
+pub mod comment;
 pub mod department;
+pub mod dispatch;
 pub mod employee;
+pub mod event;
+pub mod expense;
+pub mod notification;
+pub mod report;
+pub mod sla;
 pub mod system;
 pub mod ticket;
+pub mod webhook;
@@ -0,0 +1,813 @@
+use chrono::NaiveDateTime;
+use diesel::{prelude::*, query_dsl::methods::FilterDsl};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    schema::{employee_info, notification},
+    utils::{
+        constant::{
+            TicketState, COMMENT_EXCERPT_CHARS, EMAIL_BATCH_SIZE, EMAIL_LEASE_SECS,
+            EMAIL_MAX_ATTEMPTS, EMAIL_RETRY_BASE_SECS, NOTIFICATION_EMAIL_FAILED,
+            NOTIFICATION_EMAIL_PENDING, NOTIFICATION_EMAIL_SENT,
+            NOTIFICATION_KIND_APPROVAL_PENDING, NOTIFICATION_KIND_APPROVED,
+            NOTIFICATION_KIND_ASSIST, NOTIFICATION_KIND_AVAILABLE,
+            NOTIFICATION_KIND_CONFIRM_PENDING, NOTIFICATION_KIND_DISPATCHED,
+            NOTIFICATION_KIND_DISPUTED, NOTIFICATION_KIND_MENTIONED,
+            NOTIFICATION_KIND_OVERRUN_DECIDED, NOTIFICATION_KIND_OVERRUN_PENDING,
+            NOTIFICATION_KIND_REJECTED, NOTIFICATION_KIND_REOPENED, NOTIFICATION_KIND_RETURNED,
+            NOTIFICATION_KIND_TAKEN, OVERRUN_STATE_APPROVED, TICKET_EVENT_DISPUTE,
+            TICKET_EVENT_FINISH, TICKET_EVENT_REASSIGN, TICKET_EVENT_REOPEN,
+        },
+        mailer::{self, Mailer},
+        money::Money,
+    },
+};
+
+use super::{
+    comment::Comment,
+    department::EmployeeWithDepartments,
+    employee::Employee,
+    event::TicketEvent,
+    expense::Overrun,
+    ticket::{Fund, Ticket, TicketWithDepartments},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = notification)]
+pub struct Notification {
+    pub id: i32,
+    pub employee_id: i32, // 接收人
+    pub ticket_id: i32,
+    pub kind: i16,
+    pub content: String,
+    pub is_read: bool,
+    pub created_time: NaiveDateTime,
+    pub email_state: Option<i16>, // 为空表示不发邮件
+    pub email_attempts: i32,
+    pub email_next_attempt_time: Option<NaiveDateTime>, // 为空表示马上可以发
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = notification)]
+pub struct InsertNotification<'a> {
+    pub employee_id: i32,
+    pub ticket_id: i32,
+    pub kind: i16,
+    pub content: &'a str,
+    pub created_time: NaiveDateTime,
+    pub email_state: Option<i16>,
+}
+
+// 第 n 次发送失败后等多久再试
+fn email_backoff(attempts: i32) -> chrono::Duration {
+    let exp = (attempts - 1).clamp(0, 16) as u32;
+    chrono::Duration::seconds(EMAIL_RETRY_BASE_SECS * 2i64.pow(exp))
+}
+
+impl Notification {
+    // 给一批人发同一条通知，同一个人只发一次
+    pub fn mcreate(
+        conn: &mut PgConnection,
+        employee_ids: &[i32],
+        ticket_id: i32,
+        kind: i16,
+        content: &str,
+    ) -> Result<Vec<Notification>, AppError> {
+        let mut employee_ids = employee_ids.to_vec();
+        employee_ids.sort_unstable();
+        employee_ids.dedup();
+        let created_time = chrono::Utc::now().naive_utc();
+        // 留了邮箱又没退订的人才发邮件，真正发送由后台任务做
+        let email_ids: Vec<i32> = FilterDsl::filter(
+            employee_info::table,
+            employee_info::id
+                .eq_any(&employee_ids)
+                .and(employee_info::email.is_not_null())
+                .and(employee_info::email_opt_out.eq(false)),
+        )
+        .select(employee_info::id)
+        .get_results(conn)?;
+        let values: Vec<InsertNotification> = employee_ids
+            .into_iter()
+            .map(|employee_id| InsertNotification {
+                employee_id,
+                ticket_id,
+                kind,
+                content,
+                created_time,
+                email_state: email_ids
+                    .contains(&employee_id)
+                    .then_some(NOTIFICATION_EMAIL_PENDING),
+            })
+            .collect();
+        let notifications = diesel::insert_into(notification::table)
+            .values(values)
+            .get_results(conn)?;
+        Ok(notifications)
+    }
+
+    pub fn mget_by_employee_id(
+        conn: &mut PgConnection,
+        employee_id: i32,
+        unread_only: bool,
+        size: i32,
+        page: i32,
+    ) -> Result<Vec<Notification>, AppError> {
+        let mut query = FilterDsl::filter(
+            notification::table,
+            notification::employee_id.eq(employee_id),
+        )
+        .into_boxed();
+        if unread_only {
+            query = FilterDsl::filter(query, notification::is_read.eq(false));
+        }
+        let notifications = query
+            .order((notification::created_time.desc(), notification::id.desc()))
+            .limit(size as i64)
+            .offset(((page - 1) * size) as i64)
+            .get_results(conn)?;
+        Ok(notifications)
+    }
+
+    // (总数, 未读数)
+    pub fn get_count_by_employee_id(
+        conn: &mut PgConnection,
+        employee_id: i32,
+    ) -> Result<(i64, i64), AppError> {
+        let total = FilterDsl::filter(
+            notification::table,
+            notification::employee_id.eq(employee_id),
+        )
+        .count()
+        .get_result(conn)?;
+        let unread = FilterDsl::filter(
+            notification::table,
+            notification::employee_id
+                .eq(employee_id)
+                .and(notification::is_read.eq(false)),
+        )
+        .count()
+        .get_result(conn)?;
+        Ok((total, unread))
+    }
+
+    // ids 为空表示全部标为已读，只能标自己的
+    pub fn mark_read(
+        conn: &mut PgConnection,
+        employee_id: i32,
+        ids: Option<&[i32]>,
+    ) -> Result<usize, AppError> {
+        let target = FilterDsl::filter(
+            notification::table,
+            notification::employee_id
+                .eq(employee_id)
+                .and(notification::is_read.eq(false)),
+        );
+        let n = match ids {
+            Some(ids) => diesel::update(FilterDsl::filter(target, notification::id.eq_any(ids)))
+                .set(notification::is_read.eq(true))
+                .execute(conn)?,
+            None => diesel::update(target)
+                .set(notification::is_read.eq(true))
+                .execute(conn)?,
+        };
+        Ok(n)
+    }
+
+    // 每个工单事件都会走到这里，按事件后的状态决定通知谁
+    pub fn on_ticket_event(conn: &mut PgConnection, event: &TicketEvent) -> Result<(), AppError> {
+        let ticket = Ticket::get_by_id(conn, event.ticket_id)?;
+        let title = &ticket.title;
+        match event.new_state {
+            TicketState::Unapproved | TicketState::Approving => {
+                if let Some(approval_id) = ticket.approval_id {
+                    let ids =
+                        Employee::mget_id_by_approval_id(conn, ticket.system_id, approval_id)?;
+                    let content = format!("工单《{}》等待你审批", title);
+                    Self::mcreate(
+                        conn,
+                        &ids,
+                        ticket.id,
+                        NOTIFICATION_KIND_APPROVAL_PENDING,
+                        &content,
+                    )?;
+                }
+            }
+            TicketState::Open if event.old_state != Some(TicketState::Open) => {
+                if matches!(
+                    event.old_state,
+                    Some(TicketState::Unapproved) | Some(TicketState::Approving)
+                ) {
+                    let content = format!("你的工单《{}》审批通过了", title);
+                    Self::mcreate(
+                        conn,
+                        &[ticket.creator_id],
+                        ticket.id,
+                        NOTIFICATION_KIND_APPROVED,
+                        &content,
+                    )?;
+                }
+                let department_ids =
+                    TicketWithDepartments::mget_department_id_by_ticket_ids(conn, &[ticket.id])?
+                        .remove(&ticket.id)
+                        .unwrap_or_default();
+                Self::notify_departments(
+                    conn,
+                    &department_ids,
+                    ticket.id,
+                    NOTIFICATION_KIND_AVAILABLE,
+                    &format!("工单《{}》可以接取了", title),
+                )?;
+            }
+            TicketState::Rejected => {
+                let content = format!("你的工单《{}》被驳回了", title);
+                Self::mcreate(
+                    conn,
+                    &[ticket.creator_id],
+                    ticket.id,
+                    NOTIFICATION_KIND_REJECTED,
+                    &content,
+                )?;
+            }
+            TicketState::Returned => {
+                let content = format!("你的工单《{}》被退回修改", title);
+                Self::mcreate(
+                    conn,
+                    &[ticket.creator_id],
+                    ticket.id,
+                    NOTIFICATION_KIND_RETURNED,
+                    &content,
+                )?;
+            }
+            TicketState::Assigned if event.event_type == TICKET_EVENT_REOPEN => {
+                if let Some(receiver_id) = ticket.receiver_id {
+                    let content = format!(
+                        "工单《{}》被重新打开了，原因：{}",
+                        title,
+                        event.comment.as_deref().unwrap_or_default()
+                    );
+                    Self::mcreate(
+                        conn,
+                        &[receiver_id],
+                        ticket.id,
+                        NOTIFICATION_KIND_REOPENED,
+                        &content,
+                    )?;
+                }
+            }
+            TicketState::AwaitingConfirmation if event.event_type == TICKET_EVENT_FINISH => {
+                let content = format!("你的工单《{}》已处理完成，请确认", title);
+                Self::mcreate(
+                    conn,
+                    &[ticket.creator_id],
+                    ticket.id,
+                    NOTIFICATION_KIND_CONFIRM_PENDING,
+                    &content,
+                )?;
+            }
+            TicketState::Assigned if event.event_type == TICKET_EVENT_DISPUTE => {
+                if let Some(receiver_id) = ticket.receiver_id {
+                    let content = format!(
+                        "工单《{}》的完工报告被退回，原因：{}",
+                        title,
+                        event.comment.as_deref().unwrap_or_default()
+                    );
+                    Self::mcreate(
+                        conn,
+                        &[receiver_id],
+                        ticket.id,
+                        NOTIFICATION_KIND_DISPUTED,
+                        &content,
+                    )?;
+                }
+            }
+            TicketState::Assigned if event.event_type == TICKET_EVENT_REASSIGN => {
+                if let Some(receiver_id) = ticket.receiver_id {
+                    let content = format!("工单《{}》改派给你处理了", title);
+                    Self::mcreate(
+                        conn,
+                        &[receiver_id],
+                        ticket.id,
+                        NOTIFICATION_KIND_DISPATCHED,
+                        &content,
+                    )?;
+                }
+            }
+            TicketState::Assigned if event.old_state == Some(TicketState::Open) => {
+                let receiver = match ticket.receiver_id {
+                    Some(id) => Employee::get_by_id(conn, id)?.name,
+                    None => String::new(),
+                };
+                // 不是自己接的就是派过去的，要告诉接受人
+                let dispatched = event.employee_id != ticket.receiver_id;
+                let content = if dispatched {
+                    format!("你的工单《{}》已派给{}处理", title, receiver)
+                } else {
+                    format!("你的工单《{}》已被{}接取", title, receiver)
+                };
+                Self::mcreate(
+                    conn,
+                    &[ticket.creator_id],
+                    ticket.id,
+                    NOTIFICATION_KIND_TAKEN,
+                    &content,
+                )?;
+                if let (true, Some(receiver_id)) = (dispatched, ticket.receiver_id) {
+                    let content = format!("工单《{}》派给你处理了", title);
+                    Self::mcreate(
+                        conn,
+                        &[receiver_id],
+                        ticket.id,
+                        NOTIFICATION_KIND_DISPATCHED,
+                        &content,
+                    )?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // 协助工单不改主工单状态，由提交的地方直接调用
+    pub fn on_assist_created(
+        conn: &mut PgConnection,
+        ticket: &Ticket,
+        department_ids: &[i32],
+        submitter_id: i32,
+    ) -> Result<(), AppError> {
+        let ids: Vec<i32> =
+            EmployeeWithDepartments::mget_employee_id_by_department_ids(conn, department_ids)?
+                .into_iter()
+                .filter(|x| *x != submitter_id)
+                .collect();
+        let content = format!("工单《{}》需要你所在部门协助", ticket.title);
+        Self::mcreate(conn, &ids, ticket.id, NOTIFICATION_KIND_ASSIST, &content)?;
+        Ok(())
+    }
+
+    // 超支审批不改工单状态，由提交和审批的地方直接调用
+    pub fn on_overrun_pending(
+        conn: &mut PgConnection,
+        ticket: &Ticket,
+        overrun: &Overrun,
+    ) -> Result<(), AppError> {
+        if let Some(approval_id) = overrun.approval_id {
+            let ids = Employee::mget_id_by_approval_id(conn, ticket.system_id, approval_id)?;
+            let content = format!(
+                "工单《{}》超支 {}，等待你审批",
+                ticket.title,
+                Money::new(overrun.amount.clone(), &ticket.currency)
+            );
+            Self::mcreate(
+                conn,
+                &ids,
+                ticket.id,
+                NOTIFICATION_KIND_OVERRUN_PENDING,
+                &content,
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn on_overrun_decided(
+        conn: &mut PgConnection,
+        ticket: &Ticket,
+        overrun: &Overrun,
+    ) -> Result<(), AppError> {
+        if let Some(receiver_id) = ticket.receiver_id {
+            let result = if overrun.state == OVERRUN_STATE_APPROVED {
+                "通过了"
+            } else {
+                "被驳回了"
+            };
+            let content = format!(
+                "工单《{}》超支 {} 的审批{}",
+                ticket.title,
+                Money::new(overrun.amount.clone(), &ticket.currency),
+                result
+            );
+            Self::mcreate(
+                conn,
+                &[receiver_id],
+                ticket.id,
+                NOTIFICATION_KIND_OVERRUN_DECIDED,
+                &content,
+            )?;
+        }
+        Ok(())
+    }
+
+    // 评论里被 @ 的人，内容太长只带开头
+    pub fn on_mentioned(
+        conn: &mut PgConnection,
+        ticket: &Ticket,
+        author: &Employee,
+        comment: &Comment,
+    ) -> Result<(), AppError> {
+        let mut excerpt: String = comment
+            .content
+            .chars()
+            .take(COMMENT_EXCERPT_CHARS)
+            .collect();
+        if excerpt.len() < comment.content.len() {
+            excerpt.push('…');
+        }
+        let content = format!(
+            "{}在工单《{}》的评论里提到了你：{}",
+            author.name, ticket.title, excerpt
+        );
+        Self::mcreate(
+            conn,
+            &comment.mentioned_ids,
+            ticket.id,
+            NOTIFICATION_KIND_MENTIONED,
+            &content,
+        )?;
+        Ok(())
+    }
+
+    fn notify_departments(
+        conn: &mut PgConnection,
+        department_ids: &[i32],
+        ticket_id: i32,
+        kind: i16,
+        content: &str,
+    ) -> Result<(), AppError> {
+        let ids =
+            EmployeeWithDepartments::mget_employee_id_by_department_ids(conn, department_ids)?;
+        Self::mcreate(conn, &ids, ticket_id, kind, content)?;
+        Ok(())
+    }
+
+    // 取出到期的邮件，顺便把下次时间推后一个租期，
+    // 事务马上提交，发送时不持有行锁；发送途中进程挂了，租期过后会被重新取出来
+    fn claim_pending_emails(conn: &mut PgConnection) -> Result<Vec<Notification>, AppError> {
+        conn.transaction::<_, AppError, _>(|conn| {
+            let now = chrono::Utc::now().naive_utc();
+            let notifications: Vec<Notification> = FilterDsl::filter(
+                notification::table,
+                notification::email_state
+                    .eq(NOTIFICATION_EMAIL_PENDING)
+                    .and(
+                        notification::email_next_attempt_time
+                            .is_null()
+                            .or(notification::email_next_attempt_time.le(now)),
+                    ),
+            )
+            .order(notification::id.asc())
+            .limit(EMAIL_BATCH_SIZE)
+            .for_update()
+            .skip_locked()
+            .get_results(conn)?;
+            if notifications.is_empty() {
+                return Ok(vec![]);
+            }
+            let ids: Vec<i32> = notifications.iter().map(|x| x.id).collect();
+            diesel::update(FilterDsl::filter(
+                notification::table,
+                notification::id.eq_any(&ids),
+            ))
+            .set(
+                notification::email_next_attempt_time
+                    .eq(Some(now + chrono::Duration::seconds(EMAIL_LEASE_SECS))),
+            )
+            .execute(conn)?;
+            Ok(notifications)
+        })
+    }
+
+    // 返回 None 表示排队期间删了邮箱或者退订了，不用再发
+    fn send_email(
+        &self,
+        conn: &mut PgConnection,
+        mailer: &Mailer,
+    ) -> Result<Option<Result<(), String>>, AppError> {
+        let employee = Employee::get_by_id(conn, self.employee_id)?;
+        let email = match employee.email {
+            Some(email) if !employee.email_opt_out => email,
+            _ => return Ok(None),
+        };
+        let ticket = Ticket::get_by_id(conn, self.ticket_id)?;
+        let funds = Fund::mget_by_ticket_id(conn, ticket.id)?;
+        let (subject, body) =
+            mailer::render(self.kind, &employee.name, &self.content, &ticket, &funds);
+        let result = mailer.send(&email, &subject, body).map_err(|e| {
+            log::error!(
+                "failed to send notification {} to {}: {}",
+                self.id,
+                email,
+                e
+            );
+            e
+        });
+        Ok(Some(result))
+    }
+
+    // 每封单独一条语句记结果，前面发过的不会因为后面出错被回滚
+    fn record_email(
+        conn: &mut PgConnection,
+        notification: &Notification,
+        result: Option<Result<(), String>>,
+    ) -> Result<(), AppError> {
+        let target = notification::table.find(notification.id);
+        let Some(result) = result else {
+            diesel::update(target)
+                .set(notification::email_state.eq(None::<i16>))
+                .execute(conn)?;
+            return Ok(());
+        };
+        let attempts = notification.email_attempts + 1;
+        let (state, next_attempt_time) = match result {
+            Ok(()) => (NOTIFICATION_EMAIL_SENT, None),
+            Err(_) if attempts >= EMAIL_MAX_ATTEMPTS => (NOTIFICATION_EMAIL_FAILED, None),
+            Err(_) => (
+                NOTIFICATION_EMAIL_PENDING,
+                Some(chrono::Utc::now().naive_utc() + email_backoff(attempts)),
+            ),
+        };
+        diesel::update(target)
+            .set((
+                notification::email_state.eq(Some(state)),
+                notification::email_attempts.eq(attempts),
+                notification::email_next_attempt_time.eq(next_attempt_time),
+            ))
+            .execute(conn)?;
+        Ok(())
+    }
+}
+
+// 发送等待中的邮件，返回发送成功的数量。
+// 取出来的邮件在租期内不会被别的实例再取，多个实例同时跑也不会重复发送
+pub fn deliver_pending_emails(conn: &mut PgConnection, mailer: &Mailer) -> Result<usize, AppError> {
+    let notifications = Notification::claim_pending_emails(conn)?;
+    let mut n = 0;
+    for x in notifications.iter() {
+        // 查数据出错的这一封等租期过了再试，不影响别的
+        let result = match x.send_email(conn, mailer) {
+            Ok(result) => result,
+            Err(e) => {
+                log::error!("failed to prepare notification email {}: {}", x.id, e);
+                continue;
+            }
+        };
+        if let Some(Ok(())) = result {
+            n += 1;
+        }
+        if let Err(e) = Notification::record_email(conn, x, result) {
+            log::error!("failed to record notification email {}: {}", x.id, e);
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::{mpsc, Mutex},
+        thread,
+    };
+
+    use super::*;
+    use crate::{
+        models::ticket::InsertFund,
+        utils::{
+            constant::{ACCOUNT_TYPE_APPLICANT, ACCOUNT_TYPE_APPROVER},
+            mailer::{SmtpConfig, SmtpSecurity},
+            money::Amount,
+            testing::{self, create_employee, create_system, create_ticket},
+        },
+    };
+
+    // 发送会取走库里所有等待中的邮件，两个测试一起跑会互相取走对方的
+    static DELIVER_LOCK: Mutex<()> = Mutex::new(());
+
+    // 最简单的 SMTP 接收端，每封信把收件人和 DATA 发回来，地址里带 bad 的拒收
+    fn start_smtp_sink() -> (u16, mpsc::Receiver<(Vec<String>, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                stream.write_all(b"220 sink\r\n").unwrap();
+                let mut rcpt = vec![];
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 {
+                        break;
+                    }
+                    let upper = line.to_uppercase();
+                    if upper.starts_with("RCPT TO:") && upper.contains("BAD") {
+                        stream.write_all(b"550 no such user\r\n").unwrap();
+                    } else if upper.starts_with("RCPT TO:") {
+                        rcpt.push(line.trim().to_string());
+                        stream.write_all(b"250 OK\r\n").unwrap();
+                    } else if upper.starts_with("DATA") {
+                        stream.write_all(b"354 go ahead\r\n").unwrap();
+                        let mut data = String::new();
+                        loop {
+                            let mut line = String::new();
+                            reader.read_line(&mut line).unwrap();
+                            if line == ".\r\n" {
+                                break;
+                            }
+                            data.push_str(&line);
+                        }
+                        stream.write_all(b"250 OK\r\n").unwrap();
+                        tx.send((std::mem::take(&mut rcpt), data)).unwrap();
+                    } else if upper.starts_with("QUIT") {
+                        stream.write_all(b"221 bye\r\n").unwrap();
+                        break;
+                    } else {
+                        stream.write_all(b"250 OK\r\n").unwrap();
+                    }
+                }
+            }
+        });
+        (port, rx)
+    }
+
+    #[test]
+    #[ignore = "requires DATABASE_URL"]
+    fn test_deliver_pending_emails() {
+        let _guard = DELIVER_LOCK.lock().unwrap();
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let l1 = ts.approvals[0].id;
+        let (applicant, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let mut approvers = vec![];
+        for (email, opt_out) in [
+            (Some("a@example.com"), false),
+            (Some("b@example.com"), true),
+            (None, false),
+        ] {
+            let (employee, _) = create_employee(
+                &mut conn,
+                ts.system.id,
+                ACCOUNT_TYPE_APPROVER,
+                Some(l1),
+                vec![],
+            );
+            Employee::update_email(&mut conn, employee.id, email, opt_out).unwrap();
+            approvers.push(employee.id);
+        }
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![],
+            TicketState::Unapproved,
+            None,
+        );
+        Fund::create(
+            &mut conn,
+            InsertFund {
+                ticket_id: ticket.id,
+                reason: "材料",
+                amount: Amount::from(500),
+            },
+        )
+        .unwrap();
+        let notifications = Notification::mcreate(
+            &mut conn,
+            &approvers,
+            ticket.id,
+            NOTIFICATION_KIND_APPROVAL_PENDING,
+            "等待你审批",
+        )
+        .unwrap();
+        let states: Vec<_> = notifications.iter().map(|x| x.email_state).collect();
+        assert_eq!(states, vec![Some(NOTIFICATION_EMAIL_PENDING), None, None]);
+
+        let (port, rx) = start_smtp_sink();
+        let mailer = Mailer::new(&SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+            from: "ticket@example.com".to_string(),
+        })
+        .unwrap();
+        assert_eq!(deliver_pending_emails(&mut conn, &mailer).unwrap(), 1);
+        let (rcpt, data) = rx.recv().unwrap();
+        assert_eq!(rcpt, vec!["RCPT TO:<a@example.com>".to_string()]);
+        assert!(data.contains("Subject: "));
+        assert!(data.contains("To: a@example.com"));
+
+        let sent = notification::table
+            .find(notifications[0].id)
+            .first::<Notification>(&mut conn)
+            .unwrap();
+        assert_eq!(sent.email_state, Some(NOTIFICATION_EMAIL_SENT));
+        assert_eq!(sent.email_attempts, 1);
+        // 发过的不会再发
+        assert_eq!(deliver_pending_emails(&mut conn, &mailer).unwrap(), 0);
+    }
+
+    #[test]
+    #[ignore = "requires DATABASE_URL"]
+    fn test_deliver_pending_emails_partial_failure() {
+        let _guard = DELIVER_LOCK.lock().unwrap();
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = create_system(&mut conn, true);
+        let (applicant, _) = create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let mut receivers = vec![];
+        for email in ["a@example.com", "bad@example.com", "c@example.com"] {
+            let (employee, _) = create_employee(
+                &mut conn,
+                ts.system.id,
+                ACCOUNT_TYPE_APPROVER,
+                Some(ts.approvals[0].id),
+                vec![],
+            );
+            Employee::update_email(&mut conn, employee.id, Some(email), false).unwrap();
+            receivers.push(employee.id);
+        }
+        let ticket = create_ticket(
+            &mut conn,
+            ts.system.id,
+            applicant.id,
+            vec![],
+            TicketState::Unapproved,
+            None,
+        );
+        let notifications = Notification::mcreate(
+            &mut conn,
+            &receivers,
+            ticket.id,
+            NOTIFICATION_KIND_APPROVAL_PENDING,
+            "等待你审批",
+        )
+        .unwrap();
+
+        let (port, rx) = start_smtp_sink();
+        let mailer = Mailer::new(&SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+            from: "ticket@example.com".to_string(),
+        })
+        .unwrap();
+        // 中间那封失败，前后两封照样发出去并记成已发送
+        assert_eq!(deliver_pending_emails(&mut conn, &mailer).unwrap(), 2);
+        let (rcpt, _) = rx.recv().unwrap();
+        assert_eq!(rcpt, vec!["RCPT TO:<a@example.com>".to_string()]);
+        let (rcpt, _) = rx.recv().unwrap();
+        assert_eq!(rcpt, vec!["RCPT TO:<c@example.com>".to_string()]);
+        let get = |conn: &mut PgConnection, id: i32| {
+            notification::table
+                .find(id)
+                .first::<Notification>(conn)
+                .unwrap()
+        };
+        for x in [&notifications[0], &notifications[2]] {
+            let sent = get(&mut conn, x.id);
+            assert_eq!(sent.email_state, Some(NOTIFICATION_EMAIL_SENT));
+            assert_eq!(sent.email_attempts, 1);
+            assert_eq!(sent.email_next_attempt_time, None);
+        }
+        let failed = get(&mut conn, notifications[1].id);
+        assert_eq!(failed.email_state, Some(NOTIFICATION_EMAIL_PENDING));
+        assert_eq!(failed.email_attempts, 1);
+        assert!(failed.email_next_attempt_time.unwrap() > chrono::Utc::now().naive_utc());
+        // 失败的要等退避时间过了才重试，发过的不会再发
+        assert_eq!(deliver_pending_emails(&mut conn, &mailer).unwrap(), 0);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(get(&mut conn, notifications[1].id).email_attempts, 1);
+
+        // 到期之后重试，次数用完记成失败
+        for attempts in 2..=EMAIL_MAX_ATTEMPTS {
+            diesel::update(notification::table.find(notifications[1].id))
+                .set(notification::email_next_attempt_time.eq(None::<NaiveDateTime>))
+                .execute(&mut conn)
+                .unwrap();
+            assert_eq!(deliver_pending_emails(&mut conn, &mailer).unwrap(), 0);
+            assert_eq!(get(&mut conn, notifications[1].id).email_attempts, attempts);
+        }
+        let failed = get(&mut conn, notifications[1].id);
+        assert_eq!(failed.email_state, Some(NOTIFICATION_EMAIL_FAILED));
+        assert_eq!(email_backoff(1).num_seconds(), EMAIL_RETRY_BASE_SECS);
+        assert_eq!(email_backoff(3).num_seconds(), EMAIL_RETRY_BASE_SECS * 4);
+    }
+}
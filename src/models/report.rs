@@ -0,0 +1,144 @@
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    schema::{completion_report, system_info, ticket_info},
+    utils::{constant::TicketState, money::Amount},
+};
+
+use super::{
+    assist::Assist,
+    expense::{Expense, InsertExpense, Overrun},
+    ticket::Ticket,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = completion_report)]
+pub struct CompletionReport {
+    pub id: i32,
+    pub ticket_id: i32,
+    pub employee_id: i32,
+    pub notes: String,
+    pub created_time: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = completion_report)]
+pub struct InsertCompletionReport<'a> {
+    pub ticket_id: i32,
+    pub employee_id: i32,
+    pub notes: &'a str,
+    pub created_time: NaiveDateTime,
+}
+
+impl CompletionReport {
+    // 被退回之后重新提交会再多一份，旧的留着。花费记进台账，重新提交只填新增的
+    pub fn create(
+        conn: &mut PgConnection,
+        insert: InsertCompletionReport,
+        costs: &[(&str, Amount)],
+    ) -> Result<CompletionReport, AppError> {
+        let report: CompletionReport = diesel::insert_into(completion_report::table)
+            .values(insert)
+            .get_result(conn)?;
+        let costs: Vec<InsertExpense> = costs
+            .iter()
+            .map(|(reason, amount)| InsertExpense {
+                ticket_id: report.ticket_id,
+                employee_id: report.employee_id,
+                report_id: Some(report.id),
+                reason,
+                amount: amount.clone(),
+                receipt: None,
+                created_time: report.created_time,
+            })
+            .collect();
+        Expense::mcreate(conn, costs)?;
+        Ok(report)
+    }
+
+    // 最新的在前
+    pub fn mget_by_ticket_id(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+    ) -> Result<Vec<CompletionReport>, AppError> {
+        let reports = completion_report::table
+            .filter(completion_report::ticket_id.eq(ticket_id))
+            .order(completion_report::id.desc())
+            .get_results(conn)?;
+        Ok(reports)
+    }
+}
+
+// 完工后超过系统设置的时间还没确认
+fn is_unconfirmed(finished_time: Option<NaiveDateTime>, hours: i32, now: NaiveDateTime) -> bool {
+    finished_time.is_some_and(|x| x + Duration::hours(hours as i64) <= now)
+}
+
+// 定时任务调用，返回自动关闭了的工单 id
+pub fn close_unconfirmed(conn: &mut PgConnection) -> Result<Vec<i32>, AppError> {
+    let now = chrono::Utc::now().naive_local();
+    let tickets: Vec<(Ticket, i32)> = ticket_info::table
+        .inner_join(system_info::table)
+        .filter(ticket_info::state.eq(TicketState::AwaitingConfirmation))
+        .select((Ticket::as_select(), system_info::confirm_timeout_hours))
+        .get_results(conn)?;
+    let mut closed = vec![];
+    for (ticket, hours) in tickets.into_iter() {
+        if !is_unconfirmed(ticket.finished_time, hours, now) {
+            continue;
+        }
+        // 每个工单一个事务，扫描之后创建人可能已经确认或者退回了。超支还在审批、协助工单没结束的先不关
+        let result = conn.transaction::<_, AppError, _>(|conn| {
+            let current = Ticket::get_by_id_for_update(conn, ticket.id)?;
+            if current.state != TicketState::AwaitingConfirmation
+                || current.finished_time != ticket.finished_time
+                || Overrun::get_pending_by_ticket_id(conn, ticket.id)?.is_some()
+                || Assist::count_active_by_ticket_id(conn, ticket.id)? > 0
+            {
+                return Ok(false);
+            }
+            Ticket::confirm(conn, ticket.id, None)?;
+            Ok(true)
+        });
+        match result {
+            Ok(true) => closed.push(ticket.id),
+            Ok(false) => {}
+            Err(e) => log::error!("failed to close ticket {}: {}", ticket.id, e),
+        }
+    }
+    Ok(closed)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    #[test]
+    fn test_is_unconfirmed() {
+        let finished = NaiveDate::from_ymd_opt(2026, 10, 1)
+            .unwrap()
+            .and_hms_opt(8, 0, 0)
+            .unwrap();
+        assert!(!is_unconfirmed(None, 72, finished));
+        assert!(!is_unconfirmed(
+            Some(finished),
+            72,
+            finished + Duration::hours(71)
+        ));
+        assert!(is_unconfirmed(
+            Some(finished),
+            72,
+            finished + Duration::hours(72)
+        ));
+        assert!(is_unconfirmed(
+            Some(finished),
+            1,
+            finished + Duration::days(5)
+        ));
+    }
+}
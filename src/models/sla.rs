@@ -0,0 +1,599 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use chrono::{Duration, NaiveDateTime};
+use diesel::{prelude::*, query_dsl::methods::FilterDsl};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    schema::{sla_policy, ticket_info},
+    utils::constant::{
+        TicketState, SLA_DEFAULT_OVERDUE_HOURS, SLA_DEFAULT_WARNING_HOURS, TICKET_EVENT_ESCALATE,
+    },
+    utils::money::Amount,
+};
+
+use super::{
+    approval::Approval,
+    dispatch,
+    employee::Employee,
+    event::TicketEvent,
+    ticket::{Ticket, TicketWithDepartments},
+};
+
+// 工单在某个状态下停留多久算预警、多久算超时
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = sla_policy)]
+pub struct SlaPolicy {
+    pub id: i32,
+    pub system_id: i32,
+    pub state: TicketState,
+    pub department_id: Option<i32>, // 为空表示所有部门
+    pub min_amount: Option<Amount>, // 含
+    pub max_amount: Option<Amount>, // 不含
+    pub warning_hours: i32,
+    pub overdue_hours: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = sla_policy)]
+pub struct InsertSlaPolicy {
+    pub system_id: i32,
+    pub state: TicketState,
+    pub department_id: Option<i32>,
+    pub min_amount: Option<Amount>,
+    pub max_amount: Option<Amount>,
+    pub warning_hours: i32,
+    pub overdue_hours: i32,
+}
+
+impl SlaPolicy {
+    pub fn create(
+        conn: &mut PgConnection,
+        insert_policy: InsertSlaPolicy,
+    ) -> Result<SlaPolicy, AppError> {
+        let policy = diesel::insert_into(sla_policy::table)
+            .values(insert_policy)
+            .get_result(conn)?;
+        Ok(policy)
+    }
+
+    pub fn mget_by_system_id(
+        conn: &mut PgConnection,
+        system_id: i32,
+    ) -> Result<Vec<SlaPolicy>, AppError> {
+        let policies = FilterDsl::filter(sla_policy::table, sla_policy::system_id.eq(system_id))
+            .order(sla_policy::id.asc())
+            .get_results(conn)?;
+        Ok(policies)
+    }
+
+    // 返回删掉的行数，不是这个系统的删不掉
+    pub fn delete(conn: &mut PgConnection, system_id: i32, id: i32) -> Result<usize, AppError> {
+        let n = diesel::delete(FilterDsl::filter(
+            sla_policy::table,
+            sla_policy::id
+                .eq(id)
+                .and(sla_policy::system_id.eq(system_id)),
+        ))
+        .execute(conn)?;
+        Ok(n)
+    }
+
+    fn matches(&self, state: TicketState, department_ids: &[i32], amount: &Amount) -> bool {
+        self.state == state
+            && self
+                .department_id
+                .is_none_or(|x| department_ids.contains(&x))
+            && self.min_amount.as_ref().is_none_or(|x| amount >= x)
+            && self.max_amount.as_ref().is_none_or(|x| amount < x)
+    }
+
+    // 越具体越优先：指定了部门 > 指定了金额范围 > 通用
+    fn specificity(&self) -> (bool, bool) {
+        (
+            self.department_id.is_some(),
+            self.min_amount.is_some() || self.max_amount.is_some(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlaLevel {
+    Normal,
+    Warning,
+    Overdue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlaEvaluation {
+    pub level: SlaLevel,
+    pub started_time: NaiveDateTime, // 进入当前状态的时间
+    pub warning_time: NaiveDateTime,
+    pub deadline: NaiveDateTime,
+}
+
+impl SlaEvaluation {
+    // 离超时还有几个小时，超时了是负数
+    pub fn remaining_hours(&self, now: NaiveDateTime) -> i64 {
+        (self.deadline - now).num_hours()
+    }
+}
+
+// 多条规则都能匹配时取最具体的，一样具体取超时时间最短的
+pub fn select_policy<'a>(
+    policies: &'a [SlaPolicy],
+    state: TicketState,
+    department_ids: &[i32],
+    amount: &Amount,
+) -> Option<&'a SlaPolicy> {
+    policies
+        .iter()
+        .filter(|x| x.matches(state, department_ids, amount))
+        .max_by(|a, b| {
+            a.specificity()
+                .cmp(&b.specificity())
+                .then(b.overdue_hours.cmp(&a.overdue_hours))
+        })
+}
+
+// 没有配置时的默认时限，只有还在流转中的状态才算
+fn default_hours(state: TicketState) -> Option<(i32, i32)> {
+    match state {
+        TicketState::Unapproved
+        | TicketState::Approving
+        | TicketState::Open
+        | TicketState::Assigned => Some((SLA_DEFAULT_WARNING_HOURS, SLA_DEFAULT_OVERDUE_HOURS)),
+        _ => None,
+    }
+}
+
+pub fn evaluate_at(
+    policies: &[SlaPolicy],
+    ticket: &Ticket,
+    department_ids: &[i32],
+    started_time: NaiveDateTime,
+    now: NaiveDateTime,
+) -> Option<SlaEvaluation> {
+    let (warning_hours, overdue_hours) =
+        match select_policy(policies, ticket.state, department_ids, &ticket.amount) {
+            Some(policy) => (policy.warning_hours, policy.overdue_hours),
+            None => default_hours(ticket.state)?,
+        };
+    let warning_time = started_time + Duration::hours(warning_hours as i64);
+    let deadline = started_time + Duration::hours(overdue_hours as i64);
+    let level = if now >= deadline {
+        SlaLevel::Overdue
+    } else if now >= warning_time {
+        SlaLevel::Warning
+    } else {
+        SlaLevel::Normal
+    };
+    Some(SlaEvaluation {
+        level,
+        started_time,
+        warning_time,
+        deadline,
+    })
+}
+
+// 没有事件记录的老工单用工单上的时间
+fn fallback_started_time(ticket: &Ticket) -> NaiveDateTime {
+    match ticket.state {
+        TicketState::Approving | TicketState::Open => {
+            ticket.approved_time.unwrap_or(ticket.created_time)
+        }
+        TicketState::Assigned => ticket.received_time.unwrap_or(ticket.created_time),
+        _ => ticket.created_time,
+    }
+}
+
+// 从进入当前状态开始计时。协助、超支、改派这些状态不变的事件不重新计时，只有升级会
+fn clock_started_time(ticket: &Ticket, events: &[TicketEvent]) -> NaiveDateTime {
+    events
+        .iter()
+        .rev()
+        .find(|x| x.old_state != Some(x.new_state) || x.event_type == TICKET_EVENT_ESCALATE)
+        .filter(|x| x.new_state == ticket.state)
+        .map(|x| x.created_time)
+        .unwrap_or_else(|| fallback_started_time(ticket))
+}
+
+pub fn mevaluate(
+    conn: &mut PgConnection,
+    tickets: &[Ticket],
+) -> Result<Vec<Option<SlaEvaluation>>, AppError> {
+    let now = chrono::Utc::now().naive_utc();
+    let mut policies: HashMap<i32, Vec<SlaPolicy>> = HashMap::new();
+    for ticket in tickets.iter() {
+        if let Entry::Vacant(e) = policies.entry(ticket.system_id) {
+            e.insert(SlaPolicy::mget_by_system_id(conn, ticket.system_id)?);
+        }
+    }
+    let ids: Vec<i32> = tickets.iter().map(|x| x.id).collect();
+    let departments = TicketWithDepartments::mget_department_id_by_ticket_ids(conn, &ids)?;
+    let events = TicketEvent::mget_by_ticket_ids(conn, &ids)?;
+    let ret = tickets
+        .iter()
+        .map(|ticket| {
+            let ticket_events = events.get(&ticket.id).map(|x| x.as_slice()).unwrap_or(&[]);
+            let started_time = clock_started_time(ticket, ticket_events);
+            let department_ids = departments
+                .get(&ticket.id)
+                .map(|x| x.as_slice())
+                .unwrap_or(&[]);
+            evaluate_at(
+                &policies[&ticket.system_id],
+                ticket,
+                department_ids,
+                started_time,
+                now,
+            )
+        })
+        .collect();
+    Ok(ret)
+}
+
+// 预警和超时的工单，超时的在前，同级别按截止时间先后
+pub fn mget_alarm_tickets(
+    conn: &mut PgConnection,
+    system_id: i32,
+) -> Result<Vec<(Ticket, SlaEvaluation)>, AppError> {
+    let tickets: Vec<Ticket> = FilterDsl::filter(
+        ticket_info::table,
+        ticket_info::system_id
+            .eq(system_id)
+            .and(ticket_info::state.le(TicketState::Assigned)),
+    )
+    .get_results(conn)?;
+    let evaluations = mevaluate(conn, &tickets)?;
+    let mut ret: Vec<(Ticket, SlaEvaluation)> = tickets
+        .into_iter()
+        .zip(evaluations)
+        .filter_map(|(ticket, evaluation)| match evaluation {
+            Some(e) if e.level != SlaLevel::Normal => Some((ticket, e)),
+            _ => None,
+        })
+        .collect();
+    ret.sort_by(|(a, x), (b, y)| {
+        y.level
+            .cmp(&x.level)
+            .then(x.deadline.cmp(&y.deadline))
+            .then(a.id.cmp(&b.id))
+    });
+    Ok(ret)
+}
+
+// 定时任务调用，扫所有系统里超过 SLA 的工单
+pub fn escalate_overdue(conn: &mut PgConnection) -> Result<Vec<i32>, AppError> {
+    let tickets: Vec<Ticket> = FilterDsl::filter(
+        ticket_info::table,
+        ticket_info::state.eq_any([
+            TicketState::Unapproved,
+            TicketState::Approving,
+            TicketState::Assigned,
+        ]),
+    )
+    .get_results(conn)?;
+    escalate(conn, tickets)
+}
+
+// 返回升级了的工单 id
+pub fn escalate(conn: &mut PgConnection, tickets: Vec<Ticket>) -> Result<Vec<i32>, AppError> {
+    let evaluations = mevaluate(conn, &tickets)?;
+    let mut escalated = vec![];
+    for (ticket, evaluation) in tickets.into_iter().zip(evaluations) {
+        if !matches!(evaluation, Some(e) if e.level == SlaLevel::Overdue) {
+            continue;
+        }
+        // 每个工单一个事务，一个失败不影响其他的
+        match conn.transaction::<_, AppError, _>(|conn| escalate_ticket(conn, &ticket)) {
+            Ok(true) => escalated.push(ticket.id),
+            Ok(false) => {}
+            Err(e) => log::error!("failed to escalate ticket {}: {}", ticket.id, e),
+        }
+    }
+    Ok(escalated)
+}
+
+// 审批超时交给上一级审批，处理超时收回重新派单
+fn escalate_ticket(conn: &mut PgConnection, ticket: &Ticket) -> Result<bool, AppError> {
+    // 扫描之后可能已经有人处理了
+    let current = Ticket::get_by_id_for_update(conn, ticket.id)?;
+    if current.state != ticket.state
+        || current.approval_id != ticket.approval_id
+        || current.receiver_id != ticket.receiver_id
+    {
+        return Ok(false);
+    }
+    match ticket.state {
+        TicketState::Unapproved | TicketState::Approving => {
+            let cur_money_limit = match ticket.approval_id {
+                Some(approval_id) => Approval::get_by_id(conn, approval_id)?.amount,
+                None => return Ok(false),
+            };
+            let creator = Employee::get_by_id(conn, ticket.creator_id)?;
+            let next = Approval::get_next_by_company(
+                conn,
+                ticket.system_id,
+                creator.company_name,
+                cur_money_limit,
+            )?;
+            match next {
+                Some(next) => {
+                    Ticket::escalate_approval(conn, ticket.id, &next)?;
+                    Ok(true)
+                }
+                // 已经是最高一级了
+                None => Ok(false),
+            }
+        }
+        TicketState::Assigned => {
+            Ticket::unassign(
+                conn,
+                ticket.id,
+                None,
+                TICKET_EVENT_ESCALATE,
+                Some("接受人超时未处理，重新派单"),
+            )?;
+            // 先派给别人再放出原来的接受人，加锁顺序和审批通过派单一样
+            dispatch::auto_dispatch(conn, ticket.id, ticket.receiver_id)?;
+            if let Some(receiver_id) = ticket.receiver_id {
+                Employee::refresh_state(conn, receiver_id)?;
+            }
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDate};
+    use diesel::prelude::*;
+
+    use super::{clock_started_time, escalate, evaluate_at, select_policy, SlaLevel, SlaPolicy};
+    use crate::{
+        models::{employee::Employee, event::TicketEvent, ticket::Ticket},
+        schema::ticket_info,
+        utils::{
+            constant::{
+                TicketState, ACCOUNT_TYPE_APPLICANT, ACCOUNT_TYPE_OPERATOR,
+                EMPLOYEE_STATUS_AVAILABLE, EMPLOYEE_STATUS_UNAVAILABLE, TICKET_EVENT_APPROVE,
+                TICKET_EVENT_ASSIST, TICKET_EVENT_CREATE, TICKET_EVENT_ESCALATE,
+                TICKET_EVENT_REASSIGN, TICKET_EVENT_TAKE,
+            },
+            money::Amount,
+            testing,
+        },
+    };
+
+    fn policy(
+        id: i32,
+        department_id: Option<i32>,
+        amount: (Option<i32>, Option<i32>),
+        hours: (i32, i32),
+    ) -> SlaPolicy {
+        SlaPolicy {
+            id,
+            system_id: 1,
+            state: TicketState::Open,
+            department_id,
+            min_amount: amount.0.map(Amount::from),
+            max_amount: amount.1.map(Amount::from),
+            warning_hours: hours.0,
+            overdue_hours: hours.1,
+        }
+    }
+
+    fn ticket(state: TicketState, amount: i32) -> Ticket {
+        let t = NaiveDate::from_ymd_opt(2023, 6, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        Ticket {
+            id: 1,
+            creator_id: 1,
+            approval_id: None,
+            last_approver_id: None,
+            title: String::new(),
+            amount: Amount::from(amount),
+            reason: String::new(),
+            state,
+            address: String::new(),
+            created_time: t,
+            approved_time: None,
+            system_id: 1,
+            receiver_id: None,
+            received_time: None,
+            finished_time: None,
+            rejected_time: None,
+            reopen_count: 0,
+            currency: "CNY".to_string(),
+            closed_time: None,
+        }
+    }
+
+    #[test]
+    fn test_select_policy() {
+        let policies = vec![
+            policy(1, None, (None, None), (10, 20)),
+            policy(2, None, (Some(1000), None), (5, 10)),
+            policy(3, Some(7), (None, None), (1, 2)),
+            policy(4, None, (None, None), (8, 15)),
+        ];
+        let id = |department_ids: &[i32], amount| {
+            select_policy(
+                &policies,
+                TicketState::Open,
+                department_ids,
+                &Amount::from(amount),
+            )
+            .map(|x| x.id)
+        };
+        // 两条通用规则取更严格的
+        assert_eq!(id(&[], 100), Some(4));
+        assert_eq!(id(&[], 1000), Some(2));
+        assert_eq!(id(&[7], 1000), Some(3));
+        assert_eq!(
+            select_policy(&policies, TicketState::Assigned, &[7], &Amount::from(1000))
+                .map(|x| x.id),
+            None
+        );
+    }
+
+    #[test]
+    fn test_evaluate_at() {
+        let policies = vec![policy(1, None, (None, None), (10, 20))];
+        let t = ticket(TicketState::Open, 100);
+        let start = t.created_time;
+        let level = |hours| {
+            evaluate_at(&policies, &t, &[], start, start + Duration::hours(hours))
+                .unwrap()
+                .level
+        };
+        assert_eq!(level(9), SlaLevel::Normal);
+        assert_eq!(level(10), SlaLevel::Warning);
+        assert_eq!(level(20), SlaLevel::Overdue);
+
+        // 没配规则就用默认的，终态不算
+        let t = ticket(TicketState::Assigned, 100);
+        let e = evaluate_at(&policies, &t, &[], start, start).unwrap();
+        assert_eq!(e.remaining_hours(start), 72);
+        let t = ticket(TicketState::Closed, 100);
+        assert!(evaluate_at(&policies, &t, &[], start, start).is_none());
+    }
+
+    #[test]
+    fn test_clock_started_time() {
+        let t = ticket(TicketState::Assigned, 100);
+        let start = t.created_time;
+        let event = |hours, event_type, old_state, new_state| TicketEvent {
+            id: hours as i32,
+            ticket_id: t.id,
+            employee_id: None,
+            event_type,
+            old_state,
+            new_state,
+            comment: None,
+            created_time: start + Duration::hours(hours),
+        };
+        assert_eq!(clock_started_time(&t, &[]), start);
+
+        // 接取之后的协助和改派不重新计时
+        let mut events = vec![
+            event(0, TICKET_EVENT_CREATE, None, TicketState::Unapproved),
+            event(
+                1,
+                TICKET_EVENT_APPROVE,
+                Some(TicketState::Unapproved),
+                TicketState::Open,
+            ),
+            event(
+                2,
+                TICKET_EVENT_TAKE,
+                Some(TicketState::Open),
+                TicketState::Assigned,
+            ),
+            event(
+                3,
+                TICKET_EVENT_ASSIST,
+                Some(TicketState::Assigned),
+                TicketState::Assigned,
+            ),
+            event(
+                4,
+                TICKET_EVENT_REASSIGN,
+                Some(TicketState::Assigned),
+                TicketState::Assigned,
+            ),
+        ];
+        assert_eq!(clock_started_time(&t, &events), start + Duration::hours(2));
+
+        // 审批中升级给上一级会重新计时
+        let t = ticket(TicketState::Approving, 100);
+        events.truncate(1);
+        events.push(event(
+            1,
+            TICKET_EVENT_APPROVE,
+            Some(TicketState::Unapproved),
+            TicketState::Approving,
+        ));
+        events.push(event(
+            2,
+            TICKET_EVENT_APPROVE,
+            Some(TicketState::Approving),
+            TicketState::Approving,
+        ));
+        assert_eq!(clock_started_time(&t, &events), start + Duration::hours(1));
+        events.push(event(
+            3,
+            TICKET_EVENT_ESCALATE,
+            Some(TicketState::Approving),
+            TicketState::Approving,
+        ));
+        assert_eq!(clock_started_time(&t, &events), start + Duration::hours(3));
+    }
+
+    #[test]
+    #[ignore = "requires DATABASE_URL"]
+    fn test_escalate() {
+        let pool = testing::pool();
+        let mut conn = pool.get().unwrap();
+        let ts = testing::create_system(&mut conn, true);
+        let (l1, l2) = (ts.approvals[0].id, ts.approvals[1].id);
+        let (applicant, _) = testing::create_employee(
+            &mut conn,
+            ts.system.id,
+            ACCOUNT_TYPE_APPLICANT,
+            None,
+            vec![],
+        );
+        let (operator, _) =
+            testing::create_employee(&mut conn, ts.system.id, ACCOUNT_TYPE_OPERATOR, None, vec![]);
+        Employee::update_state(&mut conn, operator.id, EMPLOYEE_STATUS_UNAVAILABLE).unwrap();
+        let long_ago = chrono::Utc::now().naive_utc() - Duration::hours(100);
+        let mut ticket = |state, receiver_id| {
+            let t = testing::create_ticket(
+                &mut conn,
+                ts.system.id,
+                applicant.id,
+                vec![],
+                state,
+                receiver_id,
+            );
+            diesel::update(ticket_info::table.find(t.id))
+                .set(ticket_info::created_time.eq(long_ago))
+                .get_result::<Ticket>(&mut conn)
+                .unwrap()
+        };
+        let approving = ticket(TicketState::Approving, None);
+        let assigned = ticket(TicketState::Assigned, Some(operator.id));
+        Ticket::update_approval_id(&mut conn, approving.id, Some(l1)).unwrap();
+        let approving: Ticket = diesel::update(ticket_info::table.find(approving.id))
+            .set(ticket_info::approved_time.eq(long_ago))
+            .get_result(&mut conn)
+            .unwrap();
+        let tickets = vec![approving.clone(), assigned.clone()];
+
+        assert_eq!(escalate(&mut conn, tickets).unwrap().len(), 2);
+        let approving = Ticket::get_by_id(&mut conn, approving.id).unwrap();
+        assert_eq!(approving.state, TicketState::Approving);
+        assert_eq!(approving.approval_id, Some(l2));
+        let assigned = Ticket::get_by_id(&mut conn, assigned.id).unwrap();
+        assert_eq!(assigned.state, TicketState::Open);
+        assert_eq!(assigned.receiver_id, None);
+        let operator = Employee::get_by_id(&mut conn, operator.id).unwrap();
+        assert_eq!(operator.state, EMPLOYEE_STATUS_AVAILABLE);
+        for id in [approving.id, assigned.id] {
+            let events = TicketEvent::mget_by_ticket_id(&mut conn, id).unwrap();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].event_type, TICKET_EVENT_ESCALATE);
+        }
+
+        // 升级后重新计时，不会马上再升级
+        let tickets = vec![approving, assigned];
+        assert!(escalate(&mut conn, tickets).unwrap().is_empty());
+    }
+}
@@ -9,6 +9,12 @@ pub struct System {
     pub name: String,
     pub admin_account_id: Option<i32>,
     pub initialized: i16, // 1: initialized, 0: uninitialized
+    pub dispatch_strategy: i16,
+    pub dispatch_cursor: Option<i32>,   // 轮流派单上一次派给谁
+    pub reopen_window_hours: i32,       // 关闭多久内可以重新打开
+    pub confirm_timeout_hours: i32,     // 多久不确认就自动关闭
+    pub overrun_threshold_percent: i32, // 实际花费超出预算多少要重新审批
+    pub currency: String,               // 工单和审批额度用的币种
 }
 
 #[derive(Insertable)]
@@ -58,4 +64,75 @@ impl System {
             .get_result(conn)?;
         Ok(system)
     }
+
+    pub fn get_by_id_for_update(conn: &mut PgConnection, id: i32) -> Result<System, AppError> {
+        let system: System = system_info::table.find(id).for_update().first(conn)?;
+        Ok(system)
+    }
+
+    pub fn set_dispatch_strategy(
+        conn: &mut PgConnection,
+        id: i32,
+        strategy: i16,
+    ) -> Result<System, AppError> {
+        let system = diesel::update(system_info::table.find(id))
+            .set(system_info::dispatch_strategy.eq(strategy))
+            .get_result(conn)?;
+        Ok(system)
+    }
+
+    pub fn set_reopen_window_hours(
+        conn: &mut PgConnection,
+        id: i32,
+        hours: i32,
+    ) -> Result<System, AppError> {
+        let system = diesel::update(system_info::table.find(id))
+            .set(system_info::reopen_window_hours.eq(hours))
+            .get_result(conn)?;
+        Ok(system)
+    }
+
+    pub fn set_confirm_timeout_hours(
+        conn: &mut PgConnection,
+        id: i32,
+        hours: i32,
+    ) -> Result<System, AppError> {
+        let system = diesel::update(system_info::table.find(id))
+            .set(system_info::confirm_timeout_hours.eq(hours))
+            .get_result(conn)?;
+        Ok(system)
+    }
+
+    pub fn set_overrun_threshold_percent(
+        conn: &mut PgConnection,
+        id: i32,
+        percent: i32,
+    ) -> Result<System, AppError> {
+        let system = diesel::update(system_info::table.find(id))
+            .set(system_info::overrun_threshold_percent.eq(percent))
+            .get_result(conn)?;
+        Ok(system)
+    }
+
+    pub fn set_currency(
+        conn: &mut PgConnection,
+        id: i32,
+        currency: &str,
+    ) -> Result<System, AppError> {
+        let system = diesel::update(system_info::table.find(id))
+            .set(system_info::currency.eq(currency))
+            .get_result(conn)?;
+        Ok(system)
+    }
+
+    pub fn set_dispatch_cursor(
+        conn: &mut PgConnection,
+        id: i32,
+        employee_id: i32,
+    ) -> Result<System, AppError> {
+        let system = diesel::update(system_info::table.find(id))
+            .set(system_info::dispatch_cursor.eq(employee_id))
+            .get_result(conn)?;
+        Ok(system)
+    }
 }
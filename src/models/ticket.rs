@@ -1,14 +1,19 @@
+use std::collections::HashMap;
+
 use crate::{
     api::response::figure::{BarChartState, GetPieChartDataResponse, GetTableResponse, TableState},
-    error::new_ok_error,
+    error::{new_conflict_error, new_ok_error},
     models::department::Department,
     schema::apply_dev_info,
     utils::constant::{
-        TICKET_STATE_APPROVING, TICKET_STATE_ASSIGNED, TICKET_STATE_CLOSED, TICKET_STATE_OPEN,
-        TICKET_STATE_REJECTED, TICKET_STATE_UNAPPROVED,
+        TicketState, TICKET_EVENT_APPROVE, TICKET_EVENT_CANCEL, TICKET_EVENT_CONFIRM,
+        TICKET_EVENT_DISPUTE, TICKET_EVENT_EDIT, TICKET_EVENT_ESCALATE, TICKET_EVENT_FINISH,
+        TICKET_EVENT_REASSIGN, TICKET_EVENT_REJECT, TICKET_EVENT_REOPEN, TICKET_EVENT_RETURN,
+        TICKET_EVENT_TAKE,
     },
+    utils::money::{Amount, Money},
 };
-use chrono::{Duration, NaiveDateTime};
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use diesel::query_dsl::methods::FilterDsl;
 use serde::{Deserialize, Serialize};
@@ -18,7 +23,12 @@ use crate::{
     schema::{approved_info, fund_list, ticket_info},
 };
 
-use super::{approval::Approval, assist::AssistWithEmployees, employee::Employee};
+use super::{
+    approval::Approval,
+    assist::AssistWithEmployees,
+    employee::Employee,
+    event::{InsertTicketEvent, TicketEvent},
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, Selectable)]
 #[diesel(table_name = ticket_info)]
@@ -28,10 +38,9 @@ pub struct Ticket {
     pub approval_id: Option<i32>,
     pub last_approver_id: Option<i32>,
     pub title: String,
-    pub amount: i32,
+    pub amount: Amount,
     pub reason: String,
-    pub state: i16,
-    pub image: Option<String>,
+    pub state: TicketState,
     pub address: String,
     pub created_time: NaiveDateTime,
     pub approved_time: Option<NaiveDateTime>,
@@ -40,6 +49,9 @@ pub struct Ticket {
     pub received_time: Option<NaiveDateTime>,
     pub finished_time: Option<NaiveDateTime>,
     pub rejected_time: Option<NaiveDateTime>,
+    pub reopen_count: i32,
+    pub currency: String,
+    pub closed_time: Option<NaiveDateTime>, // 重新打开的期限从这里算
 }
 
 #[derive(Insertable)]
@@ -47,26 +59,36 @@ pub struct Ticket {
 pub struct InsertTicket<'a> {
     pub creator_id: i32,
     pub title: &'a str,
-    pub amount: i32,
+    pub amount: Amount,
     pub reason: &'a str,
-    pub image: Option<&'a str>,
     pub address: &'a str,
     pub system_id: i32,
     pub created_time: NaiveDateTime,
+    pub currency: &'a str,
 }
 
-#[derive(AsChangeset)]
+#[derive(AsChangeset, Default)]
 #[diesel(table_name = ticket_info)]
 pub struct UpdateTicket {
     pub last_approver_id: Option<i32>,
-    pub amount: Option<i32>,
-    pub state: Option<i16>,
+    pub amount: Option<Amount>,
+    pub state: Option<TicketState>,
     pub approval_id: Option<Option<i32>>,
     pub receiver_id: Option<i32>,
     pub approved_time: Option<NaiveDateTime>,
     pub received_time: Option<NaiveDateTime>,
     pub finished_time: Option<NaiveDateTime>,
     pub rejected_time: Option<NaiveDateTime>,
+    pub closed_time: Option<NaiveDateTime>,
+}
+
+// 创建人能改的字段，附件单独存在 ticket_attachment
+#[derive(AsChangeset)]
+#[diesel(table_name = ticket_info)]
+pub struct EditTicket<'a> {
+    pub title: &'a str,
+    pub reason: &'a str,
+    pub address: &'a str,
 }
 
 // static methods
@@ -111,7 +133,7 @@ impl Ticket {
             ticket_info::system_id
                 .eq(system_id)
                 .and(ticket_info::approval_id.eq(approval_id))
-                .and(ticket_info::state.lt(TICKET_STATE_OPEN)),
+                .and(ticket_info::state.lt(TicketState::Open)),
         )
         .count()
         .get_result(conn)?;
@@ -144,7 +166,7 @@ impl Ticket {
             ticket_info::system_id
                 .eq(system_id)
                 .and(ticket_info::approval_id.eq(approval_id))
-                .and(ticket_info::state.lt(TICKET_STATE_OPEN)),
+                .and(ticket_info::state.lt(TicketState::Open)),
         )
         .limit(size as i64)
         .offset(((page - 1) * size) as i64)
@@ -219,48 +241,17 @@ impl Ticket {
         Ok(tickets)
     }
 
-    pub fn get_alarm_count(conn: &mut PgConnection, system_id: i32) -> Result<i64, AppError> {
-        let now = chrono::Utc::now().naive_local();
-        let expired_datetime = now - Duration::days(3);
-        // let warning_datetime = now - Duration::days(2);
-        let a = FilterDsl::filter(
-            ticket_info::table,
-            ticket_info::system_id
-                .eq(system_id)
-                .and(ticket_info::created_time.le(expired_datetime))
-                .and(ticket_info::state.le(TICKET_STATE_ASSIGNED)),
-        )
-        .count()
-        .get_result(conn)?;
-        Ok(a)
-    }
-
-    pub fn mget_alarm_by_page(
-        conn: &mut PgConnection,
-        system_id: i32,
-        size: i32,
-        page: i32,
-    ) -> Result<Vec<Self>, AppError> {
-        let now = chrono::Utc::now().naive_local();
-        let expired_datetime = now - Duration::days(2);
-        let tickets = FilterDsl::filter(
-            ticket_info::table,
-            ticket_info::system_id
-                .eq(system_id)
-                .and(ticket_info::created_time.le(expired_datetime))
-                .and(ticket_info::state.le(TICKET_STATE_ASSIGNED)),
-        )
-        .limit(size as i64)
-        .offset(((page - 1) * size) as i64)
-        .get_results(conn)?;
-        Ok(tickets)
-    }
-
     pub fn get_by_id(conn: &mut PgConnection, id: i32) -> Result<Self, AppError> {
         let ticket = ticket_info::table.find(id).first(conn)?;
         Ok(ticket)
     }
 
+    // 锁住这一行直到事务结束，状态变更前先拿锁，避免两个请求同时通过状态检查
+    pub fn get_by_id_for_update(conn: &mut PgConnection, id: i32) -> Result<Self, AppError> {
+        let ticket = ticket_info::table.find(id).for_update().first(conn)?;
+        Ok(ticket)
+    }
+
     pub fn get_by_creator(conn: &mut PgConnection, creator_id: i32) -> Result<Vec<Self>, AppError> {
         let tickets = FilterDsl::filter(ticket_info::table, ticket_info::creator_id.eq(creator_id))
             .get_results(conn)?;
@@ -280,48 +271,118 @@ impl Ticket {
         }
     }
 
-    pub fn set_receiver(
+    // 所有工单状态的变化都走这里，非法的状态转移直接报错，合法的记一条事件
+    fn transition(
         conn: &mut PgConnection,
         ticket_id: i32,
-        receiver_id: i32,
+        next: TicketState,
+        changeset: UpdateTicket,
+        operator_id: Option<i32>,
+        event_type: i16,
+        comment: Option<&str>,
     ) -> Result<Ticket, AppError> {
-        let ticket = Self::get_by_id(conn, ticket_id)?;
-        if ticket.receiver_id.is_some() {
-            return Err(new_ok_error("该主工单已有接受人"));
-        }
-        let updated_ticket: Ticket = diesel::update(ticket_info::table.find(ticket_id))
-            // .set(ticket_info::receiver_id.eq(receiver_id))
+        let ticket = Self::get_by_id_for_update(conn, ticket_id)?;
+        let state = ticket.state.transition(next)?;
+        let updated_ticket = diesel::update(ticket_info::table.find(ticket_id))
             .set(UpdateTicket {
-                last_approver_id: None,
-                amount: None,
-                state: Some(TICKET_STATE_ASSIGNED),
-                approval_id: None,
-                receiver_id: Some(receiver_id),
-                approved_time: None,
-                received_time: Some(chrono::Utc::now().naive_local()),
-                finished_time: None,
-                rejected_time: None,
+                state: Some(state),
+                ..changeset
             })
             .get_result(conn)?;
+        TicketEvent::create(
+            conn,
+            InsertTicketEvent {
+                ticket_id,
+                employee_id: operator_id,
+                event_type,
+                old_state: Some(ticket.state),
+                new_state: state,
+                comment,
+                created_time: chrono::Utc::now().naive_local(),
+            },
+        )?;
         Ok(updated_ticket)
     }
 
-    // handler 判断 error 状态
-    pub fn get_current_by_receiver(
+    pub fn set_receiver(
         conn: &mut PgConnection,
+        ticket_id: i32,
         receiver_id: i32,
-    ) -> Result<Option<Self>, AppError> {
-        // open 状态的工单，且 receiver 是他
-        let ticket = FilterDsl::filter(
+    ) -> Result<Ticket, AppError> {
+        Self::assign(conn, ticket_id, receiver_id, Some(receiver_id), None)
+    }
+
+    // 自动派单，事件里没有操作人
+    pub fn dispatch(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        receiver_id: i32,
+    ) -> Result<Ticket, AppError> {
+        Self::assign(conn, ticket_id, receiver_id, None, Some("自动派单"))
+    }
+
+    // 只有 receiver 为空且待接取时才更新，同时接取的两个人只有一个能更新成功
+    fn assign(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        receiver_id: i32,
+        operator_id: Option<i32>,
+        comment: Option<&str>,
+    ) -> Result<Ticket, AppError> {
+        let updated_ticket: Option<Ticket> = diesel::update(FilterDsl::filter(
+            ticket_info::table.find(ticket_id),
+            ticket_info::receiver_id
+                .is_null()
+                .and(ticket_info::state.eq(TicketState::Open)),
+        ))
+        .set(UpdateTicket {
+            state: Some(TicketState::Assigned),
+            receiver_id: Some(receiver_id),
+            received_time: Some(chrono::Utc::now().naive_local()),
+            ..Default::default()
+        })
+        .get_result(conn)
+        .optional()?;
+        let updated_ticket = match updated_ticket {
+            Some(ticket) => ticket,
+            None => {
+                let ticket = Self::get_by_id(conn, ticket_id)?;
+                if ticket.receiver_id.is_some() {
+                    return Err(new_conflict_error("该工单已被其他人接取"));
+                }
+                ticket.state.transition(TicketState::Assigned)?;
+                return Err(new_ok_error("工单状态已变化，请刷新后重试"));
+            }
+        };
+        TicketEvent::create(
+            conn,
+            InsertTicketEvent {
+                ticket_id,
+                employee_id: operator_id,
+                event_type: TICKET_EVENT_TAKE,
+                old_state: Some(TicketState::Open),
+                new_state: TicketState::Assigned,
+                comment,
+                created_time: chrono::Utc::now().naive_local(),
+            },
+        )?;
+        Ok(updated_ticket)
+    }
+
+    // 接了还没完成的主工单，先接的在前
+    pub fn mget_current_by_receiver(
+        conn: &mut PgConnection,
+        receiver_id: i32,
+    ) -> Result<Vec<Self>, AppError> {
+        let tickets = FilterDsl::filter(
             ticket_info::table,
             ticket_info::state
-                .eq(TICKET_STATE_ASSIGNED)
+                .eq(TicketState::Assigned)
                 .and(ticket_info::receiver_id.eq(receiver_id)),
         )
-        .limit(1)
-        .get_result::<Ticket>(conn)
-        .optional()?;
-        Ok(ticket)
+        .order((ticket_info::received_time.asc(), ticket_info::id.asc()))
+        .get_results::<Ticket>(conn)?;
+        Ok(tickets)
     }
 
     pub fn mget_history_by_receiver(
@@ -331,70 +392,340 @@ impl Ticket {
         // 拿到所有的，不管是历史的还是现在在做的
         let tickets = FilterDsl::filter(
             ticket_info::table,
-            ticket_info::receiver_id
-                .eq(receiver_id)
-                .and(ticket_info::state.eq(TICKET_STATE_CLOSED)),
+            ticket_info::receiver_id.eq(receiver_id).and(
+                ticket_info::state.eq_any([TicketState::AwaitingConfirmation, TicketState::Closed]),
+            ),
         )
         .get_results::<Ticket>(conn)?;
         Ok(tickets)
     }
 
-    pub fn open(conn: &mut PgConnection, ticket_id: i32) -> Result<Ticket, AppError> {
-        let ticket = diesel::update(ticket_info::table.find(ticket_id))
-            // .set(ticket_info::state.eq(new_state))
-            .set(UpdateTicket {
-                last_approver_id: None,
-                amount: None,
-                state: Some(TICKET_STATE_OPEN),
-                approval_id: None,
-                receiver_id: None,
-                approved_time: None,
-                received_time: None,
-                finished_time: None,
-                rejected_time: None,
-            })
-            .get_result(conn)?;
-        Ok(ticket)
+    // 通过了一级审批，但后面还有审批
+    pub fn approve(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        approver_id: i32,
+    ) -> Result<Ticket, AppError> {
+        Self::transition(
+            conn,
+            ticket_id,
+            TicketState::Approving,
+            UpdateTicket::default(),
+            Some(approver_id),
+            TICKET_EVENT_APPROVE,
+            None,
+        )
     }
 
-    pub fn reject(conn: &mut PgConnection, ticket_id: i32) -> Result<Ticket, AppError> {
-        let ticket = diesel::update(ticket_info::table.find(ticket_id))
-            .set(UpdateTicket {
-                last_approver_id: None,
-                amount: None,
-                state: Some(TICKET_STATE_REJECTED),
-                approval_id: None,
-                receiver_id: None,
-                approved_time: None,
-                received_time: None,
-                finished_time: None,
+    pub fn open(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        approver_id: i32,
+    ) -> Result<Ticket, AppError> {
+        Self::transition(
+            conn,
+            ticket_id,
+            TicketState::Open,
+            UpdateTicket::default(),
+            Some(approver_id),
+            TICKET_EVENT_APPROVE,
+            None,
+        )
+    }
+
+    pub fn reject(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        approver_id: i32,
+    ) -> Result<Ticket, AppError> {
+        Self::transition(
+            conn,
+            ticket_id,
+            TicketState::Rejected,
+            UpdateTicket {
                 rejected_time: Some(chrono::Utc::now().naive_local()),
-            })
+                ..Default::default()
+            },
+            Some(approver_id),
+            TICKET_EVENT_REJECT,
+            None,
+        )
+    }
+
+    // 退回给创建人修改，不再挂在任何审批级别上
+    pub fn send_back(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        approver_id: i32,
+    ) -> Result<Ticket, AppError> {
+        Self::transition(
+            conn,
+            ticket_id,
+            TicketState::Returned,
+            UpdateTicket {
+                approval_id: Some(None),
+                ..Default::default()
+            },
+            Some(approver_id),
+            TICKET_EVENT_RETURN,
+            None,
+        )
+    }
+
+    // 审批超时，交给更高一级审批，状态不变
+    pub fn escalate_approval(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        next: &Approval,
+    ) -> Result<Ticket, AppError> {
+        let ticket = Self::get_by_id_for_update(conn, ticket_id)?;
+        let updated_ticket = diesel::update(ticket_info::table.find(ticket_id))
+            .set(ticket_info::approval_id.eq(next.id))
             .get_result(conn)?;
-        Ok(ticket)
+        TicketEvent::create(
+            conn,
+            InsertTicketEvent {
+                ticket_id,
+                employee_id: None,
+                event_type: TICKET_EVENT_ESCALATE,
+                old_state: Some(ticket.state),
+                new_state: ticket.state,
+                comment: Some(&format!("审批超时，升级到{}", next.approval_name)),
+                created_time: chrono::Utc::now().naive_local(),
+            },
+        )?;
+        Ok(updated_ticket)
     }
 
-    pub fn close(conn: &mut PgConnection, ticket_id: i32) -> Result<Ticket, AppError> {
-        let ticket = diesel::update(ticket_info::table.find(ticket_id))
-            .set(UpdateTicket {
-                last_approver_id: None,
-                amount: None,
-                state: Some(TICKET_STATE_CLOSED),
-                approval_id: None,
-                receiver_id: None,
-                approved_time: None,
-                received_time: None,
+    // 收回接受人，工单重新变成待接取，接受人的状态由调用方处理
+    pub fn unassign(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        operator_id: Option<i32>,
+        event_type: i16,
+        comment: Option<&str>,
+    ) -> Result<Ticket, AppError> {
+        let ticket = Self::get_by_id_for_update(conn, ticket_id)?;
+        let state = ticket.state.transition(TicketState::Open)?;
+        let updated_ticket = diesel::update(ticket_info::table.find(ticket_id))
+            .set((
+                ticket_info::state.eq(state),
+                ticket_info::receiver_id.eq(None::<i32>),
+                ticket_info::received_time.eq(None::<NaiveDateTime>),
+            ))
+            .get_result(conn)?;
+        TicketEvent::create(
+            conn,
+            InsertTicketEvent {
+                ticket_id,
+                employee_id: operator_id,
+                event_type,
+                old_state: Some(ticket.state),
+                new_state: state,
+                comment,
+                created_time: chrono::Utc::now().naive_local(),
+            },
+        )?;
+        Ok(updated_ticket)
+    }
+
+    // 换个人处理，状态还是已接取，原因记在事件里
+    pub fn reassign(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        receiver_id: i32,
+        operator_id: i32,
+        reason: &str,
+    ) -> Result<Ticket, AppError> {
+        let updated_ticket = diesel::update(ticket_info::table.find(ticket_id))
+            .set((
+                ticket_info::receiver_id.eq(receiver_id),
+                ticket_info::received_time.eq(chrono::Utc::now().naive_local()),
+            ))
+            .get_result(conn)?;
+        TicketEvent::create(
+            conn,
+            InsertTicketEvent {
+                ticket_id,
+                employee_id: Some(operator_id),
+                event_type: TICKET_EVENT_REASSIGN,
+                old_state: Some(TicketState::Assigned),
+                new_state: TicketState::Assigned,
+                comment: Some(reason),
+                created_time: chrono::Utc::now().naive_local(),
+            },
+        )?;
+        Ok(updated_ticket)
+    }
+
+    // 撤回原因记在事件里
+    pub fn cancel(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        operator_id: i32,
+        reason: Option<&str>,
+    ) -> Result<Ticket, AppError> {
+        Self::transition(
+            conn,
+            ticket_id,
+            TicketState::Cancelled,
+            UpdateTicket::default(),
+            Some(operator_id),
+            TICKET_EVENT_CANCEL,
+            reason,
+        )
+    }
+
+    // 未审批的工单改完还是未审批，被退回的改完重新变成未审批
+    pub fn edit(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        creator_id: i32,
+        changeset: EditTicket,
+    ) -> Result<Ticket, AppError> {
+        let ticket = Self::get_by_id_for_update(conn, ticket_id)?;
+        let state = match ticket.state {
+            TicketState::Unapproved => TicketState::Unapproved,
+            state => state.transition(TicketState::Unapproved)?,
+        };
+        let updated_ticket = diesel::update(ticket_info::table.find(ticket_id))
+            .set((changeset, ticket_info::state.eq(state)))
+            .get_result(conn)?;
+        TicketEvent::create(
+            conn,
+            InsertTicketEvent {
+                ticket_id,
+                employee_id: Some(creator_id),
+                event_type: TICKET_EVENT_EDIT,
+                old_state: Some(ticket.state),
+                new_state: state,
+                comment: None,
+                created_time: chrono::Utc::now().naive_local(),
+            },
+        )?;
+        Ok(updated_ticket)
+    }
+
+    // 接受人提交完工报告，等创建人确认
+    pub fn finish(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        operator_id: i32,
+    ) -> Result<Ticket, AppError> {
+        Self::transition(
+            conn,
+            ticket_id,
+            TicketState::AwaitingConfirmation,
+            UpdateTicket {
                 finished_time: Some(chrono::Utc::now().naive_local()),
-                rejected_time: None,
-            })
+                ..Default::default()
+            },
+            Some(operator_id),
+            TICKET_EVENT_FINISH,
+            None,
+        )
+    }
+
+    // operator_id 为空表示超时自动关闭
+    pub fn confirm(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        operator_id: Option<i32>,
+    ) -> Result<Ticket, AppError> {
+        Self::transition(
+            conn,
+            ticket_id,
+            TicketState::Closed,
+            UpdateTicket {
+                closed_time: Some(chrono::Utc::now().naive_local()),
+                ..Default::default()
+            },
+            operator_id,
+            TICKET_EVENT_CONFIRM,
+            operator_id.map_or(Some("超时未确认，自动关闭"), |_| None),
+        )
+    }
+
+    // 创建人有异议，退回接受人继续处理
+    pub fn dispute(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        operator_id: i32,
+        reason: &str,
+    ) -> Result<Ticket, AppError> {
+        let ticket = Self::get_by_id_for_update(conn, ticket_id)?;
+        let state = ticket.state.transition(TicketState::Assigned)?;
+        let updated_ticket = diesel::update(ticket_info::table.find(ticket_id))
+            .set((
+                ticket_info::state.eq(state),
+                ticket_info::finished_time.eq(None::<NaiveDateTime>),
+            ))
             .get_result(conn)?;
-        Ok(ticket)
+        TicketEvent::create(
+            conn,
+            InsertTicketEvent {
+                ticket_id,
+                employee_id: Some(operator_id),
+                event_type: TICKET_EVENT_DISPUTE,
+                old_state: Some(ticket.state),
+                new_state: state,
+                comment: Some(reason),
+                created_time: chrono::Utc::now().naive_local(),
+            },
+        )?;
+        Ok(updated_ticket)
+    }
+
+    // 创建人重新打开，keep_receiver 为 false 时放回待接取
+    pub fn reopen(
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        operator_id: i32,
+        reason: &str,
+        keep_receiver: bool,
+    ) -> Result<Ticket, AppError> {
+        let ticket = Self::get_by_id_for_update(conn, ticket_id)?;
+        let next = if keep_receiver && ticket.receiver_id.is_some() {
+            TicketState::Assigned
+        } else {
+            TicketState::Open
+        };
+        let state = ticket.state.transition(next)?;
+        let receiver_id = ticket
+            .receiver_id
+            .filter(|_| state == TicketState::Assigned);
+        let received_time = ticket
+            .received_time
+            .filter(|_| state == TicketState::Assigned);
+        let updated_ticket = diesel::update(ticket_info::table.find(ticket_id))
+            .set((
+                ticket_info::state.eq(state),
+                ticket_info::receiver_id.eq(receiver_id),
+                ticket_info::received_time.eq(received_time),
+                ticket_info::finished_time.eq(None::<NaiveDateTime>),
+                ticket_info::closed_time.eq(None::<NaiveDateTime>),
+                ticket_info::reopen_count.eq(ticket_info::reopen_count + 1),
+            ))
+            .get_result(conn)?;
+        TicketEvent::create(
+            conn,
+            InsertTicketEvent {
+                ticket_id,
+                employee_id: Some(operator_id),
+                event_type: TICKET_EVENT_REOPEN,
+                old_state: Some(ticket.state),
+                new_state: state,
+                comment: Some(reason),
+                created_time: chrono::Utc::now().naive_local(),
+            },
+        )?;
+        Ok(updated_ticket)
     }
 
     pub fn update_amount(
         conn: &mut PgConnection,
         ticket_id: i32,
-        amount: i32,
+        amount: Amount,
     ) -> Result<Ticket, AppError> {
         let ticket = diesel::update(ticket_info::table.find(ticket_id))
             // .set(ticket_info::amount.eq(amount))
@@ -408,6 +739,7 @@ impl Ticket {
                 received_time: None,
                 finished_time: None,
                 rejected_time: None,
+                closed_time: None,
             })
             .get_result(conn)?;
         Ok(ticket)
@@ -438,8 +770,8 @@ impl Ticket {
             ticket_info::table,
             ticket_info::system_id.eq(system_id).and(
                 ticket_info::state
-                    .ge(TICKET_STATE_UNAPPROVED)
-                    .and(ticket_info::state.le(TICKET_STATE_APPROVING)),
+                    .ge(TicketState::Unapproved)
+                    .and(ticket_info::state.le(TicketState::Approving)),
             ),
         )
         .get_results(conn)?;
@@ -461,7 +793,7 @@ impl Ticket {
             ticket_info::table,
             ticket_info::id
                 .eq_any(ticket_ids)
-                .and(ticket_info::state.eq(TICKET_STATE_OPEN)),
+                .and(ticket_info::state.eq(TicketState::Open)),
         )
         .get_results(conn)?;
         Ok(assists)
@@ -485,6 +817,7 @@ impl Ticket {
                 received_time: None,
                 finished_time: None,
                 rejected_time: None,
+                closed_time: None,
             })
             .get_result(conn)?;
         Ok(a)
@@ -495,9 +828,12 @@ impl Ticket {
         ticket_id: i32,
         company_name: Option<String>,
     ) -> Result<(), AppError> {
-        let mut new_approval = Approval::get_next_by_company(conn, company_name, 0)?;
+        let ticket = Self::get_by_id(conn, ticket_id)?;
+        let mut new_approval =
+            Approval::get_next_by_company(conn, ticket.system_id, company_name, Amount::zero())?;
         if new_approval.is_none() {
-            new_approval = Approval::get_next_by_company(conn, None, 0)?;
+            new_approval =
+                Approval::get_next_by_company(conn, ticket.system_id, None, Amount::zero())?;
         }
         diesel::update(ticket_info::table)
             .filter(ticket_info::id.eq(ticket_id))
@@ -511,6 +847,7 @@ impl Ticket {
                 received_time: None,
                 finished_time: None,
                 rejected_time: None,
+                closed_time: None,
             })
             .execute(conn)?;
         Ok(())
@@ -524,12 +861,11 @@ impl Ticket {
     ) -> Result<bool, AppError> {
         let ticket = Self::get_by_id(conn, ticket_id)?;
         let cur_money_limit = if let Some(approval_id) = ticket.approval_id {
-            let approval = Approval::get_by_id(conn, approval_id)?;
-            approval.amount
+            Approval::get_by_id(conn, approval_id)?.limit()
         } else {
-            0
+            Money::new(Amount::zero(), &ticket.currency)
         };
-        if ticket.amount <= cur_money_limit {
+        if ticket.money().checked_le(&cur_money_limit)? {
             // Self::update_approval_id(conn, ticket_id, None)?;
             diesel::update(ticket_info::table)
                 .filter(ticket_info::id.eq(ticket_id))
@@ -543,11 +879,17 @@ impl Ticket {
                     received_time: None,
                     finished_time: None,
                     rejected_time: None,
+                    closed_time: None,
                 })
                 .execute(conn)?;
             Ok(false)
         } else {
-            let new_approval = Approval::get_next_by_company(conn, company_name, cur_money_limit)?;
+            let new_approval = Approval::get_next_by_company(
+                conn,
+                ticket.system_id,
+                company_name,
+                cur_money_limit.amount,
+            )?;
             let ret = new_approval.is_some();
             diesel::update(ticket_info::table)
                 .filter(ticket_info::id.eq(ticket_id))
@@ -561,6 +903,7 @@ impl Ticket {
                     received_time: None,
                     finished_time: None,
                     rejected_time: None,
+                    closed_time: None,
                 })
                 .execute(conn)?;
             Ok(ret)
@@ -576,33 +919,46 @@ impl Ticket {
         let mut approving = 0;
         let mut available = 0;
         let mut received = 0;
+        let mut awaiting_confirmation = 0;
         let mut closed = 0;
         let mut rejected = 0;
+        let mut returned = 0;
+        let mut cancelled = 0;
 
         let tickets: Vec<Ticket> =
             FilterDsl::filter(ticket_info::table, ticket_info::system_id.eq(system_id))
                 .get_results(conn)?;
+        let reopened = Self::count_reopened_at_moment(conn, system_id, &tickets, t)?;
 
-        for ticket in tickets.into_iter() {
-            match ticket.get_state_at_moment(t)? {
-                Some(TICKET_STATE_UNAPPROVED) => {
+        for state in Self::mget_state_at_moment(conn, system_id, &tickets, t)? {
+            match state {
+                Some(TicketState::Unapproved) => {
                     unapproved += 1;
                 }
-                Some(TICKET_STATE_APPROVING) => {
+                Some(TicketState::Approving) => {
                     approving += 1;
                 }
-                Some(TICKET_STATE_OPEN) => {
+                Some(TicketState::Open) => {
                     available += 1;
                 }
-                Some(TICKET_STATE_ASSIGNED) => {
+                Some(TicketState::Assigned) => {
                     received += 1;
                 }
-                Some(TICKET_STATE_CLOSED) => {
+                Some(TicketState::AwaitingConfirmation) => {
+                    awaiting_confirmation += 1;
+                }
+                Some(TicketState::Closed) => {
                     closed += 1;
                 }
-                Some(TICKET_STATE_REJECTED) => {
+                Some(TicketState::Rejected) => {
                     rejected += 1;
                 }
+                Some(TicketState::Returned) => {
+                    returned += 1;
+                }
+                Some(TicketState::Cancelled) => {
+                    cancelled += 1;
+                }
                 _ => {}
             }
         }
@@ -611,8 +967,12 @@ impl Ticket {
             approving,
             available,
             received,
+            awaiting_confirmation,
             closed,
             rejected,
+            returned,
+            cancelled,
+            reopened,
         })
     }
 
@@ -628,15 +988,20 @@ impl Ticket {
         let tickets: Vec<Ticket> =
             FilterDsl::filter(ticket_info::table, ticket_info::system_id.eq(system_id))
                 .get_results(conn)?;
-        for ticket in tickets.into_iter() {
-            match ticket.get_state_at_moment(t)? {
-                Some(TICKET_STATE_UNAPPROVED)
-                | Some(TICKET_STATE_APPROVING)
-                | Some(TICKET_STATE_OPEN)
-                | Some(TICKET_STATE_ASSIGNED) => {
+        let reopened = Self::count_reopened_at_moment(conn, system_id, &tickets, t)?;
+        for state in Self::mget_state_at_moment(conn, system_id, &tickets, t)? {
+            match state {
+                Some(TicketState::Unapproved)
+                | Some(TicketState::Approving)
+                | Some(TicketState::Open)
+                | Some(TicketState::Assigned)
+                | Some(TicketState::AwaitingConfirmation)
+                | Some(TicketState::Returned) => {
                     open += 1;
                 }
-                Some(TICKET_STATE_CLOSED) | Some(TICKET_STATE_REJECTED) => {
+                Some(TicketState::Closed)
+                | Some(TicketState::Rejected)
+                | Some(TicketState::Cancelled) => {
                     closed += 1;
                 }
                 _ => {}
@@ -647,14 +1012,15 @@ impl Ticket {
             period,
             open,
             closed,
+            reopened,
         })
     }
 
     pub fn get_table_by_date(
         conn: &mut PgConnection,
         system_id: i32,
-        ranges: Vec<i32>, // 审批钱数
-        t: NaiveDateTime, // 时间
+        ranges: Vec<Amount>, // 审批钱数
+        t: NaiveDateTime,    // 时间
     ) -> Result<GetTableResponse, AppError> {
         let mut resp = vec![];
         for i in 0..(ranges.len() - 1) {
@@ -663,20 +1029,25 @@ impl Ticket {
                 ticket_info::table,
                 ticket_info::system_id
                     .eq(system_id)
-                    .and(ticket_info::amount.between(ranges[i], ranges[i + 1])),
+                    .and(ticket_info::amount.between(&ranges[i], &ranges[i + 1])),
             )
             .get_results(conn)?;
             let mut open = 0;
             let mut closed = 0;
-            for ticket in tickets.into_iter() {
-                match ticket.get_state_at_moment(t)? {
-                    Some(TICKET_STATE_UNAPPROVED)
-                    | Some(TICKET_STATE_APPROVING)
-                    | Some(TICKET_STATE_OPEN)
-                    | Some(TICKET_STATE_ASSIGNED) => {
+            let reopened = Self::count_reopened_at_moment(conn, system_id, &tickets, t)?;
+            for state in Self::mget_state_at_moment(conn, system_id, &tickets, t)? {
+                match state {
+                    Some(TicketState::Unapproved)
+                    | Some(TicketState::Approving)
+                    | Some(TicketState::Open)
+                    | Some(TicketState::Assigned)
+                    | Some(TicketState::AwaitingConfirmation)
+                    | Some(TicketState::Returned) => {
                         open += 1;
                     }
-                    Some(TICKET_STATE_CLOSED) | Some(TICKET_STATE_REJECTED) => {
+                    Some(TicketState::Closed)
+                    | Some(TicketState::Rejected)
+                    | Some(TicketState::Cancelled) => {
                         closed += 1;
                     }
                     _ => {}
@@ -686,6 +1057,7 @@ impl Ticket {
                 range,
                 open,
                 closed,
+                reopened,
             });
         }
         Ok(resp)
@@ -693,34 +1065,77 @@ impl Ticket {
 }
 
 impl Ticket {
-    pub fn get_state_at_moment(&self, timestamp: NaiveDateTime) -> Result<Option<i16>, AppError> {
+    // 工单金额，和审批额度比较时带上币种
+    pub fn money(&self) -> Money {
+        Money::new(self.amount.clone(), &self.currency)
+    }
+
+    // 优先用事件记录推算某一时刻的状态，没有事件记录的老工单才用时间戳推算
+    fn mget_state_at_moment(
+        conn: &mut PgConnection,
+        system_id: i32,
+        tickets: &[Ticket],
+        t: NaiveDateTime,
+    ) -> Result<Vec<Option<TicketState>>, AppError> {
+        let states = TicketEvent::mget_state_at_moment(conn, system_id, t)?;
+        let recorded = TicketEvent::mget_recorded_ticket_ids(conn, system_id)?;
+        tickets
+            .iter()
+            .map(|ticket| {
+                if let Some(state) = states.get(&ticket.id) {
+                    Ok(Some(*state))
+                } else if recorded.contains(&ticket.id) {
+                    Ok(None)
+                } else {
+                    ticket.get_state_at_moment(t)
+                }
+            })
+            .collect()
+    }
+
+    // 到某一时刻为止被重新打开过的工单数
+    fn count_reopened_at_moment(
+        conn: &mut PgConnection,
+        system_id: i32,
+        tickets: &[Ticket],
+        t: NaiveDateTime,
+    ) -> Result<i32, AppError> {
+        let ids =
+            TicketEvent::mget_ticket_id_by_event_type(conn, system_id, TICKET_EVENT_REOPEN, t)?;
+        Ok(tickets.iter().filter(|x| ids.contains(&x.id)).count() as i32)
+    }
+
+    pub fn get_state_at_moment(
+        &self,
+        timestamp: NaiveDateTime,
+    ) -> Result<Option<TicketState>, AppError> {
         if let Some(rejected_time) = self.rejected_time {
             if timestamp >= rejected_time {
-                return Ok(Some(TICKET_STATE_REJECTED));
+                return Ok(Some(TicketState::Rejected));
             }
         }
         if timestamp < self.created_time {
             Ok(None)
         } else if self.approved_time.is_none() {
-            Ok(Some(TICKET_STATE_UNAPPROVED))
+            Ok(Some(TicketState::Unapproved))
         } else {
             let approved_time = self.approved_time.unwrap();
             if timestamp < approved_time {
-                Ok(Some(TICKET_STATE_APPROVING))
+                Ok(Some(TicketState::Approving))
             } else if self.received_time.is_none() {
-                Ok(Some(TICKET_STATE_OPEN))
+                Ok(Some(TicketState::Open))
             } else {
                 let received_time = self.received_time.unwrap();
                 if timestamp < received_time {
-                    Ok(Some(TICKET_STATE_OPEN))
+                    Ok(Some(TicketState::Open))
                 } else if self.finished_time.is_none() {
-                    Ok(Some(TICKET_STATE_ASSIGNED))
+                    Ok(Some(TicketState::Assigned))
                 } else {
                     let finished_time = self.finished_time.unwrap();
                     if timestamp < finished_time {
-                        Ok(Some(TICKET_STATE_ASSIGNED))
+                        Ok(Some(TicketState::Assigned))
                     } else {
-                        Ok(Some(TICKET_STATE_CLOSED))
+                        Ok(Some(TicketState::Closed))
                     }
                 }
             }
@@ -734,7 +1149,7 @@ pub struct Fund {
     pub id: i32,
     pub ticket_id: i32,
     pub reason: String,
-    pub amount: i32,
+    pub amount: Amount,
 }
 
 #[derive(Insertable)]
@@ -742,7 +1157,7 @@ pub struct Fund {
 pub struct InsertFund<'a> {
     pub ticket_id: i32,
     pub reason: &'a str,
-    pub amount: i32,
+    pub amount: Amount,
 }
 
 impl Fund {
@@ -753,7 +1168,7 @@ impl Fund {
         Ok(fund)
     }
 
-    pub fn get_total_cost(conn: &mut PgConnection, ticket_id: i32) -> Result<i32, AppError> {
+    pub fn get_total_cost(conn: &mut PgConnection, ticket_id: i32) -> Result<Amount, AppError> {
         let funds: Vec<Fund> =
             FilterDsl::filter(fund_list::table, fund_list::ticket_id.eq(ticket_id))
                 .get_results(conn)?;
@@ -770,6 +1185,15 @@ impl Fund {
                 .get_results(conn)?;
         Ok(funds)
     }
+
+    pub fn delete_by_ticket_id(conn: &mut PgConnection, ticket_id: i32) -> Result<(), AppError> {
+        diesel::delete(FilterDsl::filter(
+            fund_list::table,
+            fund_list::ticket_id.eq(ticket_id),
+        ))
+        .execute(conn)?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Identifiable, Selectable, Queryable, Associations)]
@@ -821,4 +1245,30 @@ impl TicketWithDepartments {
         }
         Ok(names)
     }
+
+    pub fn mget_department_id_by_ticket_ids(
+        conn: &mut PgConnection,
+        ticket_ids: &[i32],
+    ) -> Result<HashMap<i32, Vec<i32>>, AppError> {
+        let pairs: Vec<(i32, i32)> = FilterDsl::filter(
+            apply_dev_info::table,
+            apply_dev_info::ticket_id.eq_any(ticket_ids),
+        )
+        .select((apply_dev_info::ticket_id, apply_dev_info::department_id))
+        .get_results(conn)?;
+        let mut ret: HashMap<i32, Vec<i32>> = HashMap::new();
+        for (ticket_id, department_id) in pairs.into_iter() {
+            ret.entry(ticket_id).or_default().push(department_id);
+        }
+        Ok(ret)
+    }
+
+    pub fn delete_by_ticket_id(conn: &mut PgConnection, ticket_id: i32) -> Result<(), AppError> {
+        diesel::delete(FilterDsl::filter(
+            apply_dev_info::table,
+            apply_dev_info::ticket_id.eq(ticket_id),
+        ))
+        .execute(conn)?;
+        Ok(())
+    }
 }
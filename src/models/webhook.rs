@@ -0,0 +1,400 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::NaiveDateTime;
+use diesel::{prelude::*, query_dsl::methods::FilterDsl};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{
+    error::AppError,
+    schema::{webhook, webhook_delivery},
+    utils::constant::{
+        TICKET_EVENT_APPROVE, TICKET_EVENT_ASSIST, TICKET_EVENT_CONFIRM, TICKET_EVENT_CREATE,
+        TICKET_EVENT_DISPUTE, TICKET_EVENT_FINISH, TICKET_EVENT_OVERRUN, TICKET_EVENT_REASSIGN,
+        TICKET_EVENT_REJECT, TICKET_EVENT_RELEASE, TICKET_EVENT_REOPEN, TICKET_EVENT_TAKE,
+        WEBHOOK_BATCH_SIZE, WEBHOOK_DELIVERY_FAILED, WEBHOOK_DELIVERY_PENDING,
+        WEBHOOK_DELIVERY_SUCCEEDED, WEBHOOK_LEASE_SECS, WEBHOOK_MAX_ATTEMPTS,
+        WEBHOOK_RETRY_BASE_SECS, WEBHOOK_TIMEOUT_SECS,
+    },
+};
+
+use super::{event::TicketEvent, ticket::Ticket};
+
+// 可以订阅的工单事件，以及推送里用的名字
+pub const WEBHOOK_EVENTS: [(i16, &str); 12] = [
+    (TICKET_EVENT_CREATE, "create"),
+    (TICKET_EVENT_APPROVE, "approve"),
+    (TICKET_EVENT_REJECT, "reject"),
+    (TICKET_EVENT_TAKE, "take"),
+    (TICKET_EVENT_FINISH, "finish"),
+    (TICKET_EVENT_ASSIST, "assist"),
+    (TICKET_EVENT_REASSIGN, "reassign"),
+    (TICKET_EVENT_RELEASE, "release"),
+    (TICKET_EVENT_REOPEN, "reopen"),
+    (TICKET_EVENT_CONFIRM, "confirm"),
+    (TICKET_EVENT_DISPUTE, "dispute"),
+    (TICKET_EVENT_OVERRUN, "overrun"),
+];
+
+pub fn event_name(event_type: i16) -> Option<&'static str> {
+    WEBHOOK_EVENTS
+        .iter()
+        .find(|(t, _)| *t == event_type)
+        .map(|(_, name)| *name)
+}
+
+// 接收方用同一个 secret 对原始请求体算一遍比对 X-Webhook-Signature
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+// 第 attempts 次失败之后要等多久
+pub fn backoff(attempts: i32) -> chrono::Duration {
+    let exp = (attempts - 1).clamp(0, 16) as u32;
+    chrono::Duration::seconds(WEBHOOK_RETRY_BASE_SECS * 2i64.pow(exp))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = webhook)]
+pub struct Webhook {
+    pub id: i32,
+    pub system_id: i32,
+    pub url: String,
+    pub secret: String,
+    pub event_types: Vec<i16>, // 为空表示全部
+    pub created_time: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = webhook)]
+pub struct InsertWebhook<'a> {
+    pub system_id: i32,
+    pub url: &'a str,
+    pub secret: &'a str,
+    pub event_types: Vec<i16>,
+    pub created_time: NaiveDateTime,
+}
+
+impl Webhook {
+    pub fn create(conn: &mut PgConnection, insert: InsertWebhook) -> Result<Webhook, AppError> {
+        let webhook = diesel::insert_into(webhook::table)
+            .values(insert)
+            .get_result(conn)?;
+        Ok(webhook)
+    }
+
+    pub fn get_by_id(conn: &mut PgConnection, id: i32) -> Result<Webhook, AppError> {
+        let webhook = FilterDsl::filter(webhook::table, webhook::id.eq(id)).first(conn)?;
+        Ok(webhook)
+    }
+
+    pub fn mget_by_system_id(
+        conn: &mut PgConnection,
+        system_id: i32,
+    ) -> Result<Vec<Webhook>, AppError> {
+        let webhooks = FilterDsl::filter(webhook::table, webhook::system_id.eq(system_id))
+            .order(webhook::id.asc())
+            .get_results(conn)?;
+        Ok(webhooks)
+    }
+
+    // 投递记录跟着级联删掉
+    pub fn delete(conn: &mut PgConnection, system_id: i32, id: i32) -> Result<usize, AppError> {
+        let n = diesel::delete(FilterDsl::filter(
+            webhook::table,
+            webhook::id.eq(id).and(webhook::system_id.eq(system_id)),
+        ))
+        .execute(conn)?;
+        Ok(n)
+    }
+
+    pub fn accepts(&self, event_type: i16) -> bool {
+        event_name(event_type).is_some()
+            && (self.event_types.is_empty() || self.event_types.contains(&event_type))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = webhook_delivery)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub ticket_id: i32,
+    pub event_type: i16,
+    pub payload: String,
+    pub state: i16,
+    pub attempts: i32,
+    pub next_attempt_time: NaiveDateTime,
+    pub last_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_time: NaiveDateTime,
+    pub delivered_time: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = webhook_delivery)]
+pub struct InsertWebhookDelivery<'a> {
+    pub webhook_id: i32,
+    pub ticket_id: i32,
+    pub event_type: i16,
+    pub payload: &'a str,
+    pub next_attempt_time: NaiveDateTime,
+    pub created_time: NaiveDateTime,
+}
+
+impl WebhookDelivery {
+    // 和工单事件在同一个事务里写入，事务回滚就不会推出去
+    pub fn on_ticket_event(conn: &mut PgConnection, event: &TicketEvent) -> Result<(), AppError> {
+        let Some(name) = event_name(event.event_type) else {
+            return Ok(());
+        };
+        let ticket = Ticket::get_by_id(conn, event.ticket_id)?;
+        let webhooks: Vec<Webhook> = Webhook::mget_by_system_id(conn, ticket.system_id)?
+            .into_iter()
+            .filter(|x| x.accepts(event.event_type))
+            .collect();
+        if webhooks.is_empty() {
+            return Ok(());
+        }
+        let payload = serde_json::json!({
+            "event": name,
+            "event_id": event.id,
+            "system_id": ticket.system_id,
+            "operator_id": event.employee_id,
+            "old_state": event.old_state,
+            "new_state": event.new_state,
+            "comment": event.comment,
+            "created_time": event.created_time,
+            "ticket": {
+                "id": ticket.id,
+                "title": ticket.title,
+                "amount": ticket.amount,
+                "currency": ticket.currency,
+                "state": ticket.state,
+                "creator_id": ticket.creator_id,
+                "approval_id": ticket.approval_id,
+                "receiver_id": ticket.receiver_id,
+                "created_time": ticket.created_time,
+            },
+        })
+        .to_string();
+        let now = chrono::Utc::now().naive_utc();
+        let values: Vec<InsertWebhookDelivery> = webhooks
+            .iter()
+            .map(|x| InsertWebhookDelivery {
+                webhook_id: x.id,
+                ticket_id: ticket.id,
+                event_type: event.event_type,
+                payload: &payload,
+                next_attempt_time: now,
+                created_time: now,
+            })
+            .collect();
+        diesel::insert_into(webhook_delivery::table)
+            .values(values)
+            .execute(conn)?;
+        Ok(())
+    }
+
+    pub fn get_count_by_webhook_id(
+        conn: &mut PgConnection,
+        webhook_id: i32,
+    ) -> Result<i64, AppError> {
+        let count = FilterDsl::filter(
+            webhook_delivery::table,
+            webhook_delivery::webhook_id.eq(webhook_id),
+        )
+        .count()
+        .get_result(conn)?;
+        Ok(count)
+    }
+
+    pub fn mget_by_webhook_id(
+        conn: &mut PgConnection,
+        webhook_id: i32,
+        size: i32,
+        page: i32,
+    ) -> Result<Vec<WebhookDelivery>, AppError> {
+        let deliveries = FilterDsl::filter(
+            webhook_delivery::table,
+            webhook_delivery::webhook_id.eq(webhook_id),
+        )
+        .order((
+            webhook_delivery::created_time.desc(),
+            webhook_delivery::id.desc(),
+        ))
+        .limit(size as i64)
+        .offset(((page - 1) * size) as i64)
+        .get_results(conn)?;
+        Ok(deliveries)
+    }
+
+    // 取出到期的投递，顺便把下次时间推后一个租期，
+    // 投递途中进程挂了，租期过后会被重新取出来
+    fn claim_due(conn: &mut PgConnection) -> Result<Vec<(WebhookDelivery, Webhook)>, AppError> {
+        conn.transaction::<_, AppError, _>(|conn| {
+            let now = chrono::Utc::now().naive_utc();
+            let deliveries: Vec<WebhookDelivery> = FilterDsl::filter(
+                webhook_delivery::table,
+                webhook_delivery::state
+                    .eq(WEBHOOK_DELIVERY_PENDING)
+                    .and(webhook_delivery::next_attempt_time.le(now)),
+            )
+            .order(webhook_delivery::next_attempt_time.asc())
+            .limit(WEBHOOK_BATCH_SIZE)
+            .for_update()
+            .skip_locked()
+            .get_results(conn)?;
+            if deliveries.is_empty() {
+                return Ok(vec![]);
+            }
+            let ids: Vec<i32> = deliveries.iter().map(|x| x.id).collect();
+            diesel::update(FilterDsl::filter(
+                webhook_delivery::table,
+                webhook_delivery::id.eq_any(&ids),
+            ))
+            .set(
+                webhook_delivery::next_attempt_time
+                    .eq(now + chrono::Duration::seconds(WEBHOOK_LEASE_SECS)),
+            )
+            .execute(conn)?;
+            let webhook_ids: Vec<i32> = deliveries.iter().map(|x| x.webhook_id).collect();
+            let webhooks: HashMap<i32, Webhook> =
+                FilterDsl::filter(webhook::table, webhook::id.eq_any(webhook_ids))
+                    .get_results::<Webhook>(conn)?
+                    .into_iter()
+                    .map(|x| (x.id, x))
+                    .collect();
+            Ok(deliveries
+                .into_iter()
+                .filter_map(|x| {
+                    let webhook = webhooks.get(&x.webhook_id)?.clone();
+                    Some((x, webhook))
+                })
+                .collect())
+        })
+    }
+
+    fn send(&self, agent: &ureq::Agent, webhook: &Webhook) -> Result<u16, (Option<u16>, String)> {
+        let result = agent
+            .post(&webhook.url)
+            .set("Content-Type", "application/json")
+            .set("X-Webhook-Event", event_name(self.event_type).unwrap_or(""))
+            .set("X-Webhook-Delivery", &self.id.to_string())
+            .set("X-Webhook-Signature", &sign(&webhook.secret, &self.payload))
+            .send_string(&self.payload);
+        match result {
+            Ok(resp) => Ok(resp.status()),
+            Err(ureq::Error::Status(status, resp)) => {
+                let body = resp.into_string().unwrap_or_default();
+                Err((Some(status), body))
+            }
+            Err(e) => Err((None, e.to_string())),
+        }
+    }
+
+    fn record(
+        conn: &mut PgConnection,
+        delivery: &WebhookDelivery,
+        result: Result<u16, (Option<u16>, String)>,
+    ) -> Result<(), AppError> {
+        let now = chrono::Utc::now().naive_utc();
+        let attempts = delivery.attempts + 1;
+        let target = FilterDsl::filter(
+            webhook_delivery::table,
+            webhook_delivery::id.eq(delivery.id),
+        );
+        match result {
+            Ok(status) => {
+                diesel::update(target)
+                    .set((
+                        webhook_delivery::state.eq(WEBHOOK_DELIVERY_SUCCEEDED),
+                        webhook_delivery::attempts.eq(attempts),
+                        webhook_delivery::last_status.eq(Some(status as i32)),
+                        webhook_delivery::last_error.eq(None::<String>),
+                        webhook_delivery::delivered_time.eq(Some(now)),
+                    ))
+                    .execute(conn)?;
+            }
+            Err((status, error)) => {
+                let state = if attempts >= WEBHOOK_MAX_ATTEMPTS {
+                    WEBHOOK_DELIVERY_FAILED
+                } else {
+                    WEBHOOK_DELIVERY_PENDING
+                };
+                let error: String = error.chars().take(500).collect();
+                diesel::update(target)
+                    .set((
+                        webhook_delivery::state.eq(state),
+                        webhook_delivery::attempts.eq(attempts),
+                        webhook_delivery::next_attempt_time.eq(now + backoff(attempts)),
+                        webhook_delivery::last_status.eq(status.map(|x| x as i32)),
+                        webhook_delivery::last_error.eq(Some(error)),
+                    ))
+                    .execute(conn)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// 返回投递成功了几个
+pub fn deliver_due(conn: &mut PgConnection) -> Result<usize, AppError> {
+    let claimed = WebhookDelivery::claim_due(conn)?;
+    if claimed.is_empty() {
+        return Ok(0);
+    }
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECS))
+        .build();
+    let mut n = 0;
+    for (delivery, webhook) in claimed.iter() {
+        let result = delivery.send(&agent, webhook);
+        if result.is_ok() {
+            n += 1;
+        }
+        if let Err(e) = WebhookDelivery::record(conn, delivery, result) {
+            log::error!("failed to record webhook delivery {}: {}", delivery.id, e);
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_backoff_and_accepts() {
+        assert_eq!(backoff(1).num_seconds(), WEBHOOK_RETRY_BASE_SECS);
+        assert_eq!(backoff(3).num_seconds(), WEBHOOK_RETRY_BASE_SECS * 4);
+        let mut webhook = Webhook {
+            id: 1,
+            system_id: 1,
+            url: String::new(),
+            secret: String::new(),
+            event_types: vec![],
+            created_time: chrono::Utc::now().naive_utc(),
+        };
+        assert!(webhook.accepts(TICKET_EVENT_CREATE));
+        assert!(webhook.accepts(TICKET_EVENT_ASSIST));
+        // 撤回、编辑之类的事件不推送
+        assert!(!webhook.accepts(crate::utils::constant::TICKET_EVENT_EDIT));
+        webhook.event_types = vec![TICKET_EVENT_FINISH];
+        assert!(webhook.accepts(TICKET_EVENT_FINISH));
+        assert!(!webhook.accepts(TICKET_EVENT_CREATE));
+    }
+}
@@ -1,6 +1,9 @@
 use actix_web::{web, HttpResponse};
 
-use crate::api::handlers::{ticket::get_available_tickets, *};
+use crate::{
+    api::handlers::{ticket::get_available_tickets, *},
+    utils::constant::ATTACHMENT_MAX_JSON_BYTES,
+};
 
 async fn healthcheck() -> HttpResponse {
     HttpResponse::Ok().finish()
@@ -13,22 +16,45 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
         web::scope("/auth")
             .route("login", web::post().to(auth::login))
             .route("admin", web::post().to(auth::register_admin))
+            .route("email", web::put().to(auth::update_email))
             .route("", web::get().to(auth::get_myself)),
     );
 
     cfg.service(
         web::scope("/system")
             .route("", web::post().to(system::initialize_system))
-            .route("employee", web::post().to(system::create_employee)),
+            .route("employee", web::post().to(system::create_employee))
+            .route(
+                "employee/capacity",
+                web::put().to(system::update_employee_capacity),
+            )
+            .route(
+                "employee/lead",
+                web::put().to(system::update_department_lead),
+            )
+            .route("dispatch", web::get().to(system::get_dispatch_strategy))
+            .route("dispatch", web::put().to(system::update_dispatch_strategy))
+            .route("reopen", web::put().to(system::update_reopen_window))
+            .route("confirm", web::put().to(system::update_confirm_timeout))
+            .route("overrun", web::put().to(system::update_overrun_threshold)),
     );
 
     cfg.service(
         web::scope("/ticket")
-            .route("approve", web::get().to(approval::approve_ticket))
-            .route("reject", web::get().to(approval::reject_ticket))
+            .route("approve", web::post().to(approval::approve_ticket))
+            .route("reject", web::post().to(approval::reject_ticket))
+            .route("return", web::post().to(approval::return_ticket))
             .route("page", web::get().to(ticket::get_tickets_by_page))
             .route("", web::post().to(ticket::create_ticket))
+            .route("", web::put().to(ticket::update_ticket))
             .route("assist", web::post().to(ticket::create_assist))
+            .route(
+                "assist/available",
+                web::get().to(ticket::get_available_assists),
+            )
+            .route("assist/finish", web::post().to(ticket::finish_assist))
+            .route("assist/cancel", web::post().to(ticket::cancel_assist))
+            .route("assist/leave", web::post().to(ticket::leave_assist))
             .route("current", web::get().to(ticket::get_current_ticket))
             .route(
                 "history/page",
@@ -42,14 +68,59 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
             .route("available", web::get().to(get_available_tickets))
             .route("take", web::post().to(ticket::take_ticket))
             .route("finish", web::post().to(ticket::finish_ticket))
+            .route("confirm", web::post().to(ticket::confirm_ticket))
+            .route("dispute", web::post().to(ticket::dispute_ticket))
+            .route("report", web::get().to(ticket::get_ticket_reports))
+            .route("comment", web::post().to(comment::create_comment))
+            .route("comments", web::get().to(comment::get_comments))
+            .route("cancel", web::post().to(ticket::cancel_ticket))
+            .route("reassign", web::post().to(ticket::reassign_ticket))
+            .route("release", web::post().to(ticket::release_ticket))
+            .route("reopen", web::post().to(ticket::reopen_ticket))
+            .route("timeline", web::get().to(ticket::get_ticket_timeline))
             .route("", web::get().to(ticket::get_ticket_by_id)),
     );
 
+    cfg.service(
+        web::scope("/expense")
+            .route("overrun", web::get().to(expense::get_pending_overruns))
+            .route("overrun/approve", web::post().to(expense::approve_overrun))
+            .route("overrun/reject", web::post().to(expense::reject_overrun))
+            .route("", web::get().to(expense::get_expense_summary))
+            .route("", web::post().to(expense::add_expense)),
+    );
+
     cfg.service(web::scope("/department").route("", web::get().to(department::list_departments)));
     cfg.service(
         web::scope("/approval").route("", web::get().to(approval::get_approval_levels_by_company)),
     );
 
+    cfg.service(web::scope("/events").route("", web::get().to(event::get_events)));
+
+    cfg.service(
+        web::scope("/notification")
+            .route("read", web::post().to(notification::read_notifications))
+            .route("", web::get().to(notification::get_notifications_by_page)),
+    );
+
+    cfg.service(
+        web::scope("/sla")
+            .route("", web::get().to(sla::list_sla_policies))
+            .route("", web::post().to(sla::create_sla_policy))
+            .route("", web::delete().to(sla::delete_sla_policy)),
+    );
+
+    cfg.service(
+        web::scope("/webhook")
+            .route(
+                "delivery",
+                web::get().to(webhook::get_webhook_deliveries_by_page),
+            )
+            .route("", web::get().to(webhook::list_webhooks))
+            .route("", web::post().to(webhook::create_webhook))
+            .route("", web::delete().to(webhook::delete_webhook)),
+    );
+
     cfg.service(
         web::scope("/figure")
             .route("pie", web::get().to(figure::get_pie_chart_data))
@@ -59,7 +130,11 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
 
     cfg.service(
         web::scope("/upload")
-            .route("/v2", web::post().to(upload::save_file_v2))
+            .service(
+                web::resource("/v2")
+                    .app_data(web::JsonConfig::default().limit(ATTACHMENT_MAX_JSON_BYTES))
+                    .route(web::post().to(upload::save_file_v2)),
+            )
             .route("", web::post().to(upload::save_file)),
     );
 }
@@ -24,11 +24,13 @@ diesel::table! {
     approval_info (id) {
         #[max_length = 100]
         approval_name -> Varchar,
-        amount -> Int4,
+        amount -> Numeric,
         #[max_length = 50]
         company -> Nullable<Varchar>,
         system_id -> Int4,
         id -> Int4,
+        #[max_length = 3]
+        currency -> Varchar,
     }
 }
 
@@ -40,6 +42,8 @@ diesel::table! {
         employee_id -> Int4,
         created_time -> Timestamp,
         result -> Int2,
+        #[max_length = 500]
+        comment -> Nullable<Varchar>,
     }
 }
 
@@ -59,6 +63,7 @@ diesel::table! {
         id -> Int4,
         assist_id -> Int4,
         employee_id -> Int4,
+        department_id -> Nullable<Int4>,
     }
 }
 
@@ -72,6 +77,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    completion_report (id) {
+        id -> Int4,
+        ticket_id -> Int4,
+        employee_id -> Int4,
+        notes -> Text,
+        created_time -> Timestamp,
+    }
+}
+
 diesel::table! {
     employee_info (id) {
         id -> Int4,
@@ -88,6 +103,11 @@ diesel::table! {
         sex -> Int2,
         #[max_length = 100]
         company_name -> Nullable<Varchar>,
+        #[max_length = 255]
+        email -> Nullable<Varchar>,
+        email_opt_out -> Bool,
+        max_wip -> Int4,
+        last_assigned_time -> Nullable<Timestamp>,
     }
 }
 
@@ -95,6 +115,7 @@ diesel::table! {
     employee_operation_info (employee_id, department_id) {
         employee_id -> Int4,
         department_id -> Int4,
+        is_lead -> Bool,
     }
 }
 
@@ -104,7 +125,23 @@ diesel::table! {
         ticket_id -> Int4,
         #[max_length = 100]
         reason -> Varchar,
-        amount -> Int4,
+        amount -> Numeric,
+    }
+}
+
+diesel::table! {
+    notification (id) {
+        id -> Int4,
+        employee_id -> Int4,
+        ticket_id -> Int4,
+        kind -> Int2,
+        #[max_length = 500]
+        content -> Varchar,
+        is_read -> Bool,
+        created_time -> Timestamp,
+        email_state -> Nullable<Int2>,
+        email_attempts -> Int4,
+        email_next_attempt_time -> Nullable<Timestamp>,
     }
 }
 
@@ -117,6 +154,33 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    overrun_approval (id) {
+        id -> Int4,
+        ticket_id -> Int4,
+        amount -> Numeric,
+        approval_id -> Nullable<Int4>,
+        state -> Int2,
+        last_approver_id -> Nullable<Int4>,
+        comment -> Nullable<Text>,
+        created_time -> Timestamp,
+        finished_time -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    sla_policy (id) {
+        id -> Int4,
+        system_id -> Int4,
+        state -> Int2,
+        department_id -> Nullable<Int4>,
+        min_amount -> Nullable<Numeric>,
+        max_amount -> Nullable<Numeric>,
+        warning_hours -> Int4,
+        overdue_hours -> Int4,
+    }
+}
+
 diesel::table! {
     system_info (id) {
         id -> Int4,
@@ -124,6 +188,74 @@ diesel::table! {
         name -> Varchar,
         admin_account_id -> Nullable<Int4>,
         initialized -> Int2,
+        dispatch_strategy -> Int2,
+        dispatch_cursor -> Nullable<Int4>,
+        reopen_window_hours -> Int4,
+        confirm_timeout_hours -> Int4,
+        overrun_threshold_percent -> Int4,
+        #[max_length = 3]
+        currency -> Varchar,
+    }
+}
+
+diesel::table! {
+    ticket_attachment (id) {
+        id -> Int4,
+        ticket_id -> Nullable<Int4>,
+        comment_id -> Nullable<Int4>,
+        report_id -> Nullable<Int4>,
+        uploader_id -> Nullable<Int4>,
+        #[max_length = 500]
+        url -> Varchar,
+        #[max_length = 255]
+        filename -> Varchar,
+        #[max_length = 100]
+        content_type -> Varchar,
+        size -> Nullable<Int8>,
+        #[max_length = 64]
+        checksum -> Nullable<Varchar>,
+        created_time -> Timestamp,
+    }
+}
+
+diesel::table! {
+    ticket_comment (id) {
+        id -> Int4,
+        ticket_id -> Int4,
+        employee_id -> Int4,
+        #[max_length = 2000]
+        content -> Varchar,
+        mentioned_ids -> Array<Int4>,
+        created_time -> Timestamp,
+    }
+}
+
+diesel::table! {
+    ticket_event (id) {
+        id -> Int4,
+        ticket_id -> Int4,
+        employee_id -> Nullable<Int4>,
+        event_type -> Int2,
+        old_state -> Nullable<Int2>,
+        new_state -> Int2,
+        #[max_length = 500]
+        comment -> Nullable<Varchar>,
+        created_time -> Timestamp,
+    }
+}
+
+diesel::table! {
+    ticket_expense (id) {
+        id -> Int4,
+        ticket_id -> Int4,
+        employee_id -> Int4,
+        report_id -> Nullable<Int4>,
+        #[max_length = 255]
+        reason -> Varchar,
+        amount -> Numeric,
+        #[max_length = 255]
+        receipt -> Nullable<Varchar>,
+        created_time -> Timestamp,
     }
 }
 
@@ -135,12 +267,10 @@ diesel::table! {
         last_approver_id -> Nullable<Int4>,
         #[max_length = 100]
         title -> Varchar,
-        amount -> Int4,
+        amount -> Numeric,
         #[max_length = 500]
         reason -> Varchar,
         state -> Int2,
-        #[max_length = 255]
-        image -> Nullable<Varchar>,
         #[max_length = 500]
         address -> Varchar,
         created_time -> Timestamp,
@@ -150,6 +280,41 @@ diesel::table! {
         received_time -> Nullable<Timestamp>,
         finished_time -> Nullable<Timestamp>,
         rejected_time -> Nullable<Timestamp>,
+        reopen_count -> Int4,
+        #[max_length = 3]
+        currency -> Varchar,
+        closed_time -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    webhook (id) {
+        id -> Int4,
+        system_id -> Int4,
+        #[max_length = 500]
+        url -> Varchar,
+        #[max_length = 255]
+        secret -> Varchar,
+        event_types -> Array<Int2>,
+        created_time -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webhook_delivery (id) {
+        id -> Int4,
+        webhook_id -> Int4,
+        ticket_id -> Int4,
+        event_type -> Int2,
+        payload -> Text,
+        state -> Int2,
+        attempts -> Int4,
+        next_attempt_time -> Timestamp,
+        last_status -> Nullable<Int4>,
+        #[max_length = 500]
+        last_error -> Nullable<Varchar>,
+        created_time -> Timestamp,
+        delivered_time -> Nullable<Timestamp>,
     }
 }
 
@@ -164,16 +329,40 @@ diesel::joinable!(assist_department_info -> assist_info (assist_id));
 diesel::joinable!(assist_department_info -> operation_info (department_id));
 diesel::joinable!(assist_employee_info -> assist_info (assist_id));
 diesel::joinable!(assist_employee_info -> employee_info (employee_id));
+diesel::joinable!(assist_employee_info -> operation_info (department_id));
 diesel::joinable!(assist_info -> ticket_info (ticket_id));
+diesel::joinable!(completion_report -> employee_info (employee_id));
+diesel::joinable!(completion_report -> ticket_info (ticket_id));
 diesel::joinable!(employee_info -> approval_info (approval_id));
 diesel::joinable!(employee_info -> system_info (system_id));
 diesel::joinable!(employee_operation_info -> employee_info (employee_id));
 diesel::joinable!(employee_operation_info -> operation_info (department_id));
 diesel::joinable!(fund_list -> ticket_info (ticket_id));
+diesel::joinable!(notification -> employee_info (employee_id));
+diesel::joinable!(notification -> ticket_info (ticket_id));
 diesel::joinable!(operation_info -> system_info (system_id));
+diesel::joinable!(overrun_approval -> approval_info (approval_id));
+diesel::joinable!(overrun_approval -> employee_info (last_approver_id));
+diesel::joinable!(overrun_approval -> ticket_info (ticket_id));
+diesel::joinable!(sla_policy -> operation_info (department_id));
+diesel::joinable!(sla_policy -> system_info (system_id));
 diesel::joinable!(system_info -> account_info (admin_account_id));
+diesel::joinable!(ticket_attachment -> completion_report (report_id));
+diesel::joinable!(ticket_attachment -> employee_info (uploader_id));
+diesel::joinable!(ticket_attachment -> ticket_comment (comment_id));
+diesel::joinable!(ticket_attachment -> ticket_info (ticket_id));
+diesel::joinable!(ticket_comment -> employee_info (employee_id));
+diesel::joinable!(ticket_comment -> ticket_info (ticket_id));
+diesel::joinable!(ticket_event -> employee_info (employee_id));
+diesel::joinable!(ticket_event -> ticket_info (ticket_id));
+diesel::joinable!(ticket_expense -> completion_report (report_id));
+diesel::joinable!(ticket_expense -> employee_info (employee_id));
+diesel::joinable!(ticket_expense -> ticket_info (ticket_id));
 diesel::joinable!(ticket_info -> approval_info (approval_id));
 diesel::joinable!(ticket_info -> system_info (system_id));
+diesel::joinable!(webhook -> system_info (system_id));
+diesel::joinable!(webhook_delivery -> ticket_info (ticket_id));
+diesel::joinable!(webhook_delivery -> webhook (webhook_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     account_info,
@@ -183,10 +372,20 @@ diesel::allow_tables_to_appear_in_same_query!(
     assist_department_info,
     assist_employee_info,
     assist_info,
+    completion_report,
     employee_info,
     employee_operation_info,
     fund_list,
+    notification,
     operation_info,
+    overrun_approval,
+    sla_policy,
     system_info,
+    ticket_attachment,
+    ticket_comment,
+    ticket_event,
+    ticket_expense,
     ticket_info,
+    webhook,
+    webhook_delivery,
 );
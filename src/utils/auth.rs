@@ -71,7 +71,7 @@ impl SkipAuthRoute {
                 return false;
             }
         }
-        return true;
+        true
     }
 
     fn matches_method(&self, method: &Method) -> bool {
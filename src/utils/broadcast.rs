@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use diesel::PgConnection;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{
+    error::AppError,
+    models::{
+        event::TicketEvent,
+        ticket::{Ticket, TicketWithDepartments},
+    },
+    utils::constant::{TicketState, SSE_CHANNEL_CAPACITY},
+};
+
+// 推给前端的工单变化，system_id 等只用来过滤不下发
+#[derive(Debug, Clone, Serialize)]
+pub struct TicketUpdate {
+    pub ticket_id: i32,
+    pub title: String,
+    pub event_type: Option<i16>,
+    pub old_state: Option<TicketState>,
+    pub state: TicketState,
+    pub approval_id: Option<i32>,
+    pub department_ids: Vec<i32>,
+    pub updated_time: NaiveDateTime,
+    #[serde(skip)]
+    pub system_id: i32,
+    #[serde(skip)]
+    pub creator_id: i32,
+    #[serde(skip)]
+    pub receiver_id: Option<i32>,
+}
+
+impl TicketUpdate {
+    // 事务提交之后再读，保证推出去的是已经生效的状态
+    pub fn load(conn: &mut PgConnection, ticket_id: i32) -> Result<Self, AppError> {
+        let ticket = Ticket::get_by_id(conn, ticket_id)?;
+        let event = TicketEvent::mget_latest_by_ticket_ids(conn, &[ticket_id])?.remove(&ticket_id);
+        let department_ids =
+            TicketWithDepartments::mget_department_id_by_ticket_ids(conn, &[ticket_id])?
+                .remove(&ticket_id)
+                .unwrap_or_default();
+        Ok(Self {
+            ticket_id,
+            title: ticket.title,
+            event_type: event.as_ref().map(|x| x.event_type),
+            old_state: event.as_ref().and_then(|x| x.old_state),
+            state: ticket.state,
+            approval_id: ticket.approval_id,
+            department_ids,
+            updated_time: event.map(|x| x.created_time).unwrap_or(ticket.created_time),
+            system_id: ticket.system_id,
+            creator_id: ticket.creator_id,
+            receiver_id: ticket.receiver_id,
+        })
+    }
+}
+
+// 一个 SSE 连接能看到哪些工单，连接建立时确定
+#[derive(Debug, Clone)]
+pub struct Subscriber {
+    pub employee_id: i32,
+    pub system_id: i32,
+    pub approval_id: Option<i32>,
+    pub department_ids: Vec<i32>,
+}
+
+impl Subscriber {
+    pub fn can_see(&self, update: &TicketUpdate) -> bool {
+        if update.system_id != self.system_id {
+            return false;
+        }
+        update.creator_id == self.employee_id
+            || update.receiver_id == Some(self.employee_id)
+            || (self.approval_id.is_some() && update.approval_id == self.approval_id)
+            || update
+                .department_ids
+                .iter()
+                .any(|x| self.department_ids.contains(x))
+    }
+}
+
+// 进程内的广播，多实例部署时每个实例只能推自己处理的变化
+#[derive(Clone)]
+pub struct Broadcaster {
+    sender: broadcast::Sender<Arc<TicketUpdate>>,
+}
+
+impl Default for Broadcaster {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(SSE_CHANNEL_CAPACITY);
+        Self { sender }
+    }
+}
+
+impl Broadcaster {
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<TicketUpdate>> {
+        self.sender.subscribe()
+    }
+
+    pub fn publish(&self, update: TicketUpdate) {
+        // 没有人在听的时候会返回错误，不用管
+        let _ = self.sender.send(Arc::new(update));
+    }
+
+    // 工单已经改完了，推送失败只记日志，不影响请求本身
+    pub fn publish_ticket(&self, conn: &mut PgConnection, ticket_id: i32) {
+        self.publish_with(conn, ticket_id, |_| {});
+    }
+
+    pub fn publish_with(
+        &self,
+        conn: &mut PgConnection,
+        ticket_id: i32,
+        f: impl FnOnce(&mut TicketUpdate),
+    ) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        match TicketUpdate::load(conn, ticket_id) {
+            Ok(mut update) => {
+                f(&mut update);
+                self.publish(update);
+            }
+            Err(e) => log::error!("failed to publish ticket {}: {}", ticket_id, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_see() {
+        let update = TicketUpdate {
+            ticket_id: 1,
+            title: "t".to_string(),
+            event_type: None,
+            old_state: None,
+            state: TicketState::Open,
+            approval_id: Some(10),
+            department_ids: vec![1, 2],
+            updated_time: chrono::Utc::now().naive_utc(),
+            system_id: 1,
+            creator_id: 100,
+            receiver_id: Some(101),
+        };
+        let sub = |employee_id, system_id, approval_id, department_ids| Subscriber {
+            employee_id,
+            system_id,
+            approval_id,
+            department_ids,
+        };
+        assert!(sub(100, 1, None, vec![]).can_see(&update));
+        assert!(sub(101, 1, None, vec![]).can_see(&update));
+        assert!(sub(102, 1, Some(10), vec![]).can_see(&update));
+        assert!(sub(102, 1, None, vec![3, 2]).can_see(&update));
+        assert!(!sub(102, 1, Some(11), vec![3]).can_see(&update));
+        assert!(!sub(102, 1, None, vec![]).can_see(&update));
+        // 别的系统同样 id 的部门和审批级别
+        assert!(!sub(100, 2, Some(10), vec![1]).can_see(&update));
+    }
+}
@@ -1,9 +1,144 @@
-pub const TICKET_STATE_UNAPPROVED: i16 = 0; // 未审批
-pub const TICKET_STATE_APPROVING: i16 = 1; // 审批中
-pub const TICKET_STATE_OPEN: i16 = 2; // 审批完，还没人接
-pub const TICKET_STATE_ASSIGNED: i16 = 3; // 有人接
-pub const TICKET_STATE_CLOSED: i16 = 4; // 关闭了
-pub const TICKET_STATE_REJECTED: i16 = 5; // 审批驳回
+use std::io::Write;
+
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    pg::{Pg, PgValue},
+    serialize::{self, Output, ToSql},
+    sql_types::SmallInt,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::{new_ok_error, AppError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, AsExpression, FromSqlRow)]
+#[diesel(sql_type = SmallInt)]
+#[repr(i16)]
+pub enum TicketState {
+    Unapproved = 0,           // 未审批
+    Approving = 1,            // 审批中
+    Open = 2,                 // 审批完，还没人接
+    Assigned = 3,             // 有人接
+    Closed = 4,               // 关闭了
+    Rejected = 5,             // 审批驳回
+    Returned = 6,             // 退回给创建人修改
+    Cancelled = 7,            // 创建人撤回
+    AwaitingConfirmation = 8, // 提交了完工报告，等创建人确认
+}
+
+impl TicketState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TicketState::Unapproved => "未审批",
+            TicketState::Approving => "审批中",
+            TicketState::Open => "待接取",
+            TicketState::Assigned => "处理中",
+            TicketState::Closed => "已关闭",
+            TicketState::Rejected => "已驳回",
+            TicketState::Returned => "退回修改",
+            TicketState::Cancelled => "已撤回",
+            TicketState::AwaitingConfirmation => "待确认",
+        }
+    }
+
+    pub fn can_transition_to(&self, next: TicketState) -> bool {
+        use TicketState::*;
+        matches!(
+            (self, next),
+            (Unapproved, Approving)
+                | (Unapproved, Open)
+                | (Unapproved, Rejected)
+                | (Unapproved, Returned)
+                | (Unapproved, Cancelled)
+                | (Approving, Approving)
+                | (Approving, Open)
+                | (Approving, Rejected)
+                | (Approving, Returned)
+                | (Approving, Cancelled)
+                | (Returned, Unapproved)
+                | (Returned, Cancelled)
+                | (Open, Cancelled)
+                | (Assigned, Open)
+                | (Open, Assigned)
+                | (Assigned, AwaitingConfirmation)
+                | (AwaitingConfirmation, Closed)
+                | (AwaitingConfirmation, Assigned)
+                | (Closed, Assigned)
+                | (Closed, Open)
+        )
+    }
+
+    // 所有改工单状态的地方都要经过这里
+    pub fn transition(self, next: TicketState) -> Result<TicketState, AppError> {
+        if self.can_transition_to(next) {
+            Ok(next)
+        } else {
+            Err(new_ok_error(&format!(
+                "工单当前状态为{}，不能变为{}",
+                self.name(),
+                next.name()
+            )))
+        }
+    }
+}
+
+impl From<TicketState> for i16 {
+    fn from(state: TicketState) -> Self {
+        state as i16
+    }
+}
+
+impl TryFrom<i16> for TicketState {
+    type Error = String;
+
+    fn try_from(value: i16) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(TicketState::Unapproved),
+            1 => Ok(TicketState::Approving),
+            2 => Ok(TicketState::Open),
+            3 => Ok(TicketState::Assigned),
+            4 => Ok(TicketState::Closed),
+            5 => Ok(TicketState::Rejected),
+            6 => Ok(TicketState::Returned),
+            7 => Ok(TicketState::Cancelled),
+            8 => Ok(TicketState::AwaitingConfirmation),
+            _ => Err(format!("unknown ticket state: {}", value)),
+        }
+    }
+}
+
+impl ToSql<SmallInt, Pg> for TicketState {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(&i16::from(*self).to_be_bytes())?;
+        Ok(serialize::IsNull::No)
+    }
+}
+
+impl FromSql<SmallInt, Pg> for TicketState {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        let value = <i16 as FromSql<SmallInt, Pg>>::from_sql(bytes)?;
+        Ok(TicketState::try_from(value)?)
+    }
+}
+
+impl Serialize for TicketState {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_i16(i16::from(*self))
+    }
+}
+
+impl<'de> Deserialize<'de> for TicketState {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = i16::deserialize(deserializer)?;
+        TicketState::try_from(value).map_err(serde::de::Error::custom)
+    }
+}
 
 pub const EMPLOYEE_STATUS_AVAILABLE: i16 = 0;
 pub const EMPLOYEE_STATUS_UNAVAILABLE: i16 = 1;
@@ -21,5 +156,151 @@ pub const SEX_MALE: i16 = 1;
 
 pub const APPROVE_RESULT_APPROVED: i16 = 1;
 pub const APPROVE_RESULT_REJECTED: i16 = 0;
+pub const APPROVE_RESULT_RETURNED: i16 = 2; // 退回修改
+
+pub const TICKET_EVENT_CREATE: i16 = 0; // 创建工单
+pub const TICKET_EVENT_APPROVE: i16 = 1; // 审批通过
+pub const TICKET_EVENT_REJECT: i16 = 2; // 审批驳回
+pub const TICKET_EVENT_TAKE: i16 = 3; // 接取工单
+pub const TICKET_EVENT_ASSIST: i16 = 4; // 提交或接取协助工单
+pub const TICKET_EVENT_FINISH: i16 = 5; // 完成工单
+pub const TICKET_EVENT_RETURN: i16 = 6; // 审批退回修改
+pub const TICKET_EVENT_EDIT: i16 = 7; // 创建人修改工单
+pub const TICKET_EVENT_CANCEL: i16 = 8; // 撤回工单
+pub const TICKET_EVENT_ESCALATE: i16 = 9; // 超过 SLA 自动升级
+pub const TICKET_EVENT_REASSIGN: i16 = 10; // 负责人或管理员改派
+pub const TICKET_EVENT_RELEASE: i16 = 11; // 接受人放弃
+pub const TICKET_EVENT_REOPEN: i16 = 12; // 创建人重新打开
+pub const TICKET_EVENT_CONFIRM: i16 = 13; // 创建人确认完工，超时没确认自动关闭时没有操作人
+pub const TICKET_EVENT_DISPUTE: i16 = 14; // 创建人对完工有异议，退回接受人
+pub const TICKET_EVENT_OVERRUN: i16 = 15; // 超支提交审批和审批结果，状态不变
+
+pub const ASSIST_STATE_OPEN: i16 = 0; // 还在招人
+pub const ASSIST_STATE_STAFFED: i16 = 1; // 各部门人都齐了
+pub const ASSIST_STATE_FINISHED: i16 = 2; // 完成
+pub const ASSIST_STATE_CANCELLED: i16 = 3; // 提交人撤销
+pub const ASSIST_DEPARTMENT_OPEN: i16 = 0; // 这个部门还缺人
+pub const ASSIST_DEPARTMENT_FULL: i16 = 1; // 这个部门人满了
+
+pub const OVERRUN_STATE_PENDING: i16 = 0; // 审批中
+pub const OVERRUN_STATE_APPROVED: i16 = 1;
+pub const OVERRUN_STATE_REJECTED: i16 = 2;
+
+pub const DISPATCH_STRATEGY_MANUAL: i16 = 0; // 不自动派单，运维自己接
+pub const DISPATCH_STRATEGY_ROUND_ROBIN: i16 = 1; // 轮流派
+pub const DISPATCH_STRATEGY_LEAST_LOADED: i16 = 2; // 派给手上工单最少的人
+pub const DISPATCH_STRATEGY_LONGEST_IDLE: i16 = 3; // 派给最久没接单的人
+
+pub const NOTIFICATION_KIND_APPROVAL_PENDING: i16 = 0; // 轮到你审批
+pub const NOTIFICATION_KIND_APPROVED: i16 = 1; // 你的工单审批通过
+pub const NOTIFICATION_KIND_REJECTED: i16 = 2; // 你的工单被驳回
+pub const NOTIFICATION_KIND_RETURNED: i16 = 3; // 你的工单被退回修改
+pub const NOTIFICATION_KIND_AVAILABLE: i16 = 4; // 你的部门有工单可以接
+pub const NOTIFICATION_KIND_TAKEN: i16 = 5; // 你的工单被接取
+pub const NOTIFICATION_KIND_ASSIST: i16 = 6; // 你的部门有协助请求
+pub const NOTIFICATION_KIND_DISPATCHED: i16 = 7; // 有工单派给你
+pub const NOTIFICATION_KIND_REASSIGNED: i16 = 8; // 你处理的工单改派给别人了
+pub const NOTIFICATION_KIND_REOPENED: i16 = 9; // 你处理过的工单被重新打开
+pub const NOTIFICATION_KIND_CONFIRM_PENDING: i16 = 10; // 你的工单处理完了，等你确认
+pub const NOTIFICATION_KIND_DISPUTED: i16 = 11; // 你提交的完工报告被创建人退回
+pub const NOTIFICATION_KIND_OVERRUN_PENDING: i16 = 12; // 有超支等待你审批
+pub const NOTIFICATION_KIND_OVERRUN_DECIDED: i16 = 13; // 你处理的工单超支审批有结果了
+pub const NOTIFICATION_KIND_MENTIONED: i16 = 14; // 有人在工单评论里 @ 了你
+
+// @ 通知里带评论开头多少个字
+pub const COMMENT_EXCERPT_CHARS: usize = 50;
+
+// SSE 广播最多缓存多少条，连接处理不过来会收到 lagged
+pub const SSE_CHANNEL_CAPACITY: usize = 256;
+// SSE 心跳间隔，防止代理把空闲连接断掉
+pub const SSE_KEEPALIVE_SECS: u64 = 15;
+
+pub const NOTIFICATION_EMAIL_PENDING: i16 = 0; // 等待发送
+pub const NOTIFICATION_EMAIL_SENT: i16 = 1; // 已发送
+pub const NOTIFICATION_EMAIL_FAILED: i16 = 2; // 重试次数用完
+pub const EMAIL_MAX_ATTEMPTS: i32 = 3;
+pub const EMAIL_BATCH_SIZE: i64 = 50;
+pub const EMAIL_SCAN_INTERVAL_SECS: u64 = 30;
+pub const SMTP_TIMEOUT_SECS: u64 = 10;
+pub const EMAIL_RETRY_BASE_SECS: i64 = 60; // 第 n 次失败后等 60 * 2^(n-1) 秒
+pub const EMAIL_LEASE_SECS: i64 = 600; // 一批最多 50 封、每封最多等 10 秒，租期要比发完一批长
+
+pub const WEBHOOK_DELIVERY_PENDING: i16 = 0; // 等待投递
+pub const WEBHOOK_DELIVERY_SUCCEEDED: i16 = 1; // 投递成功
+pub const WEBHOOK_DELIVERY_FAILED: i16 = 2; // 重试次数用完
+pub const WEBHOOK_MAX_ATTEMPTS: i32 = 6;
+pub const WEBHOOK_RETRY_BASE_SECS: i64 = 30; // 第 n 次失败后等 30 * 2^(n-1) 秒
+pub const WEBHOOK_LEASE_SECS: i64 = 60; // 取出来投递期间别的线程不会再取
+pub const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+pub const WEBHOOK_BATCH_SIZE: i64 = 50;
+pub const WEBHOOK_SCAN_INTERVAL_SECS: u64 = 10;
+
+// 多久扫一次超过 SLA 的工单，可以用环境变量 SLA_SCAN_INTERVAL_SECS 覆盖
+pub const SLA_SCAN_INTERVAL_SECS: u64 = 300;
+
+// 多久扫一次超时没确认的工单，可以用环境变量 CONFIRM_SCAN_INTERVAL_SECS 覆盖
+pub const CONFIRM_SCAN_INTERVAL_SECS: u64 = 300;
+
+// 没有配置 SLA 规则时的默认时限，从进入当前状态开始算
+pub const SLA_DEFAULT_WARNING_HOURS: i32 = 48;
+pub const SLA_DEFAULT_OVERDUE_HOURS: i32 = 72;
 
 pub const IMAGE_URL_PREFIX: &str = "http://8.134.67.143:7878";
+
+// 单个附件最大 20MB，文件名超过 255 个字符会被拒绝
+pub const ATTACHMENT_MAX_BYTES: usize = 20 * 1024 * 1024;
+pub const ATTACHMENT_MAX_FILENAME_CHARS: usize = 255;
+// base64 上传时整个 JSON 的上限：编码后大三分之一，再留点给文件名
+pub const ATTACHMENT_MAX_JSON_BYTES: usize = ATTACHMENT_MAX_BYTES.div_ceil(3) * 4 + 4096;
+
+#[cfg(test)]
+mod tests {
+    use super::TicketState;
+
+    #[test]
+    fn test_ticket_state_transition() {
+        assert!(TicketState::Unapproved.can_transition_to(TicketState::Approving));
+        assert!(TicketState::Unapproved.can_transition_to(TicketState::Open));
+        assert!(TicketState::Approving.can_transition_to(TicketState::Approving));
+        assert!(TicketState::Approving.can_transition_to(TicketState::Rejected));
+        assert!(TicketState::Open.can_transition_to(TicketState::Assigned));
+        assert!(TicketState::Assigned.can_transition_to(TicketState::AwaitingConfirmation));
+        assert!(TicketState::AwaitingConfirmation.can_transition_to(TicketState::Closed));
+        assert!(TicketState::AwaitingConfirmation.can_transition_to(TicketState::Assigned));
+        assert!(TicketState::Approving.can_transition_to(TicketState::Returned));
+        assert!(TicketState::Returned.can_transition_to(TicketState::Unapproved));
+        assert!(TicketState::Open.can_transition_to(TicketState::Cancelled));
+        assert!(TicketState::Closed.can_transition_to(TicketState::Assigned));
+        assert!(TicketState::Closed.can_transition_to(TicketState::Open));
+
+        assert!(TicketState::Open.transition(TicketState::Closed).is_err());
+        assert!(TicketState::Rejected
+            .transition(TicketState::Approving)
+            .is_err());
+        assert!(TicketState::Open
+            .transition(TicketState::Approving)
+            .is_err());
+        assert!(TicketState::Closed
+            .transition(TicketState::Approving)
+            .is_err());
+        assert!(TicketState::Returned
+            .transition(TicketState::Approving)
+            .is_err());
+        assert!(TicketState::Assigned
+            .transition(TicketState::Cancelled)
+            .is_err());
+        // 要先提交完工报告
+        assert!(TicketState::Assigned
+            .transition(TicketState::Closed)
+            .is_err());
+    }
+
+    #[test]
+    fn test_ticket_state_i16() {
+        for value in 0..9 {
+            let state = TicketState::try_from(value).unwrap();
+            assert_eq!(i16::from(state), value);
+        }
+        assert!(TicketState::try_from(9).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use std::time::Duration;
+
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    Address, Message, SmtpTransport, Transport,
+};
+
+use crate::{
+    error::{new_ok_error, AppError},
+    models::ticket::{Fund, Ticket},
+    utils::constant::{
+        NOTIFICATION_KIND_APPROVAL_PENDING, NOTIFICATION_KIND_APPROVED, NOTIFICATION_KIND_ASSIST,
+        NOTIFICATION_KIND_AVAILABLE, NOTIFICATION_KIND_CONFIRM_PENDING,
+        NOTIFICATION_KIND_DISPATCHED, NOTIFICATION_KIND_DISPUTED, NOTIFICATION_KIND_MENTIONED,
+        NOTIFICATION_KIND_OVERRUN_DECIDED, NOTIFICATION_KIND_OVERRUN_PENDING,
+        NOTIFICATION_KIND_REASSIGNED, NOTIFICATION_KIND_REJECTED, NOTIFICATION_KIND_REOPENED,
+        NOTIFICATION_KIND_RETURNED, NOTIFICATION_KIND_TAKEN, SMTP_TIMEOUT_SECS,
+    },
+};
+
+// 空字符串当成不设置
+pub fn normalize_email(email: Option<&str>) -> Result<Option<String>, AppError> {
+    match email.map(str::trim).filter(|x| !x.is_empty()) {
+        None => Ok(None),
+        Some(x) if x.len() <= 255 && x.parse::<Address>().is_ok() => Ok(Some(x.to_string())),
+        Some(_) => Err(new_ok_error("邮箱格式不正确")),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpSecurity {
+    None,     // 明文，只适合本机或内网的中继
+    StartTls, // 默认，一般是 587 端口
+    Tls,      // 一般是 465 端口
+}
+
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+}
+
+impl SmtpConfig {
+    // 没配 SMTP_HOST 就不发邮件
+    pub fn from_env() -> Option<Self> {
+        let var = |key: &str| std::env::var(key).ok().filter(|x| !x.is_empty());
+        let host = var("SMTP_HOST")?;
+        let security = match var("SMTP_SECURITY").as_deref() {
+            Some("none") => SmtpSecurity::None,
+            Some("tls") => SmtpSecurity::Tls,
+            _ => SmtpSecurity::StartTls,
+        };
+        Some(Self {
+            from: var("SMTP_FROM").unwrap_or_else(|| format!("noreply@{}", host)),
+            port: var("SMTP_PORT").and_then(|x| x.parse().ok()),
+            host,
+            security,
+            username: var("SMTP_USERNAME"),
+            password: var("SMTP_PASSWORD"),
+        })
+    }
+}
+
+pub struct Mailer {
+    transport: SmtpTransport,
+    from: Mailbox,
+}
+
+impl Mailer {
+    pub fn new(config: &SmtpConfig) -> Result<Self, AppError> {
+        let from: Mailbox = config
+            .from
+            .parse()
+            .map_err(|_| new_ok_error("SMTP_FROM 格式不正确"))?;
+        let mut builder = match config.security {
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(&config.host),
+            SmtpSecurity::StartTls => SmtpTransport::starttls_relay(&config.host)
+                .map_err(|e| new_ok_error(&format!("SMTP 配置错误: {}", e)))?,
+            SmtpSecurity::Tls => SmtpTransport::relay(&config.host)
+                .map_err(|e| new_ok_error(&format!("SMTP 配置错误: {}", e)))?,
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        let transport = builder
+            .timeout(Some(Duration::from_secs(SMTP_TIMEOUT_SECS)))
+            .build();
+        Ok(Self { transport, from })
+    }
+
+    pub fn send(&self, to: &str, subject: &str, body: String) -> Result<(), String> {
+        let to: Mailbox = to.parse().map_err(|e| format!("{}", e))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)
+            .map_err(|e| e.to_string())?;
+        self.transport.send(&message).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+// 每种通知一个标题前缀，正文都带上工单金额和费用明细
+pub fn render(
+    kind: i16,
+    name: &str,
+    content: &str,
+    ticket: &Ticket,
+    funds: &[Fund],
+) -> (String, String) {
+    let (tag, hint) = match kind {
+        NOTIFICATION_KIND_APPROVAL_PENDING => ("待审批", "请登录工单系统审批。"),
+        NOTIFICATION_KIND_APPROVED => ("审批通过", "工单已进入派单。"),
+        NOTIFICATION_KIND_REJECTED => ("已驳回", "可以登录工单系统查看审批意见。"),
+        NOTIFICATION_KIND_RETURNED => ("退回修改", "请按审批意见修改后重新提交。"),
+        NOTIFICATION_KIND_AVAILABLE => ("可接取", "请登录工单系统接取。"),
+        NOTIFICATION_KIND_TAKEN => ("已接取", "可以登录工单系统查看处理进度。"),
+        NOTIFICATION_KIND_ASSIST => ("协助请求", "请登录工单系统接取协助工单。"),
+        NOTIFICATION_KIND_DISPATCHED => ("派单", "请登录工单系统处理。"),
+        NOTIFICATION_KIND_REASSIGNED => ("改派", "这个工单不需要你继续处理了。"),
+        NOTIFICATION_KIND_REOPENED => ("重新打开", "请登录工单系统继续处理。"),
+        NOTIFICATION_KIND_CONFIRM_PENDING => ("待确认", "请登录工单系统确认完工或提出异议。"),
+        NOTIFICATION_KIND_DISPUTED => ("完工被退回", "请登录工单系统继续处理。"),
+        NOTIFICATION_KIND_OVERRUN_PENDING => ("超支待审批", "请登录工单系统审批。"),
+        NOTIFICATION_KIND_OVERRUN_DECIDED => ("超支审批", "请登录工单系统查看。"),
+        NOTIFICATION_KIND_MENTIONED => ("评论提到你", "请登录工单系统查看评论。"),
+        _ => ("通知", "请登录工单系统查看。"),
+    };
+    let subject = format!("【{}】{}", tag, ticket.title);
+    let mut body = format!(
+        "{}，您好：\n\n{}\n\n工单编号：{}\n工单标题：{}\n申请理由：{}\n地址：{}\n金额：{}\n",
+        name,
+        content,
+        ticket.id,
+        ticket.title,
+        ticket.reason,
+        ticket.address,
+        ticket.money()
+    );
+    if !funds.is_empty() {
+        body.push_str("费用明细：\n");
+        for fund in funds.iter() {
+            body.push_str(&format!(
+                "  - {}：{} {}\n",
+                fund.reason, fund.amount, ticket.currency
+            ));
+        }
+    }
+    body.push('\n');
+    body.push_str(hint);
+    body.push('\n');
+    (subject, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::money::Amount;
+
+    #[test]
+    fn test_normalize_email() {
+        assert_eq!(normalize_email(None).unwrap(), None);
+        assert_eq!(normalize_email(Some("  ")).unwrap(), None);
+        assert_eq!(
+            normalize_email(Some(" a@example.com ")).unwrap(),
+            Some("a@example.com".to_string())
+        );
+        assert!(normalize_email(Some("not an email")).is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let ticket = Ticket {
+            id: 7,
+            creator_id: 1,
+            approval_id: Some(1),
+            last_approver_id: None,
+            title: "更换空调".to_string(),
+            amount: "1200.5".parse().unwrap(),
+            reason: "坏了".to_string(),
+            state: crate::utils::constant::TicketState::Approving,
+            address: "三楼".to_string(),
+            created_time: chrono::Utc::now().naive_utc(),
+            approved_time: None,
+            system_id: 1,
+            receiver_id: None,
+            received_time: None,
+            finished_time: None,
+            rejected_time: None,
+            reopen_count: 0,
+            currency: "CNY".to_string(),
+            closed_time: None,
+        };
+        let funds = vec![
+            Fund {
+                id: 1,
+                ticket_id: 7,
+                reason: "设备".to_string(),
+                amount: Amount::from(1000),
+            },
+            Fund {
+                id: 2,
+                ticket_id: 7,
+                reason: "人工".to_string(),
+                amount: "200.5".parse().unwrap(),
+            },
+        ];
+        let (subject, body) = render(
+            NOTIFICATION_KIND_APPROVAL_PENDING,
+            "张三",
+            "工单《更换空调》等待你审批",
+            &ticket,
+            &funds,
+        );
+        assert_eq!(subject, "【待审批】更换空调");
+        assert!(body.starts_with("张三，您好"));
+        assert!(body.contains("金额：1200.50 CNY"));
+        assert!(body.contains("  - 设备：1000.00 CNY\n  - 人工：200.50 CNY\n"));
+    }
+}
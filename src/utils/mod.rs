@@ -1,5 +1,11 @@
 pub mod auth;
+pub mod broadcast;
 pub mod constant;
 pub mod date_format;
+pub mod mailer;
+pub mod money;
 pub mod response;
+pub mod scheduler;
+#[cfg(test)]
+pub mod testing;
 pub mod token;
@@ -0,0 +1,292 @@
+use std::{
+    fmt::Display,
+    iter::Sum,
+    ops::{Add, Sub},
+    str::FromStr,
+};
+
+use bigdecimal::{BigDecimal, ToPrimitive, Zero};
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    pg::{Pg, PgValue},
+    serialize::{self, Output, ToSql},
+    sql_types::Numeric,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::{new_ok_error, AppError};
+
+// 数据库里是 numeric(14, 2)
+const SCALE: i64 = 2;
+const MAX_INTEGER_DIGITS: usize = 12;
+
+// 金额，两位小数的定点数。币种记在工单、审批级别和系统上
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Numeric)]
+pub struct Amount(BigDecimal);
+
+impl Amount {
+    pub fn zero() -> Self {
+        Self(BigDecimal::zero().with_scale(SCALE))
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < BigDecimal::zero()
+    }
+
+    // self 比 base 多出 percent% 以上
+    pub fn exceeds(&self, base: &Amount, percent: i32) -> bool {
+        &self.0 * BigDecimal::from(100) > &base.0 * BigDecimal::from(100 + percent as i64)
+    }
+
+    // self 比 base 多了百分之几，保留两位小数，base 为 0 时没有
+    pub fn percent_of(&self, base: &Amount) -> Option<f64> {
+        if base.0.is_zero() {
+            return None;
+        }
+        (&self.0 * BigDecimal::from(100) / &base.0)
+            .round(2)
+            .to_f64()
+    }
+}
+
+impl FromStr for Amount {
+    type Err = String;
+
+    // 只接受 123、-123、123.4、123.45 这样的写法，不接受科学计数法
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let digits = s.strip_prefix('-').unwrap_or(s);
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if integer.is_empty()
+            || !integer.bytes().all(|x| x.is_ascii_digit())
+            || !fraction.bytes().all(|x| x.is_ascii_digit())
+            || (digits.contains('.') && fraction.is_empty())
+        {
+            return Err(format!("{:?} 不是数字", s));
+        }
+        if fraction.len() > SCALE as usize {
+            return Err(format!("{} 超过两位小数", s));
+        }
+        if integer.trim_start_matches('0').len() > MAX_INTEGER_DIGITS {
+            return Err(format!("{} 太大了", s));
+        }
+        let value = BigDecimal::from_str(s).map_err(|e| e.to_string())?;
+        Ok(Self(value.with_scale(SCALE)))
+    }
+}
+
+impl From<i32> for Amount {
+    fn from(value: i32) -> Self {
+        Self(BigDecimal::from(value).with_scale(SCALE))
+    }
+}
+
+impl Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.with_scale(SCALE))
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self(self.0 - rhs.0)
+    }
+}
+
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Amount::zero(), |a, b| a + b)
+    }
+}
+
+impl<'a> Sum<&'a Amount> for Amount {
+    fn sum<I: Iterator<Item = &'a Amount>>(iter: I) -> Self {
+        iter.fold(Amount::zero(), |a, b| a + b.clone())
+    }
+}
+
+impl ToSql<Numeric, Pg> for Amount {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        <BigDecimal as ToSql<Numeric, Pg>>::to_sql(&self.0, &mut out.reborrow())
+    }
+}
+
+impl FromSql<Numeric, Pg> for Amount {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        let value = <BigDecimal as FromSql<Numeric, Pg>>::from_sql(bytes)?;
+        Ok(Self(value.with_scale(SCALE)))
+    }
+}
+
+// 返回字符串，前端不会丢精度
+impl Serialize for Amount {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+// 请求里写字符串或者数字都行，格式不对、负数、超过两位小数都会被拒绝
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct AmountVisitor;
+
+        impl de::Visitor<'_> for AmountVisitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a non-negative amount with at most two decimal places")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+                let amount: Amount = v.parse().map_err(E::custom)?;
+                if amount.is_negative() {
+                    return Err(E::custom("金额不能为负"));
+                }
+                Ok(amount)
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Amount, E> {
+                self.visit_str(&v.to_string())
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Amount, E> {
+                self.visit_str(&v.to_string())
+            }
+
+            // 按最短的十进制写法解析，0.1 就是 0.1
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Amount, E> {
+                if !v.is_finite() {
+                    return Err(E::custom("金额不合法"));
+                }
+                self.visit_str(&v.to_string())
+            }
+        }
+
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+// 带币种的金额，币种不同的不能比较
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Money {
+    pub amount: Amount,
+    pub currency: String,
+}
+
+impl Money {
+    pub fn new(amount: Amount, currency: &str) -> Self {
+        Self {
+            amount,
+            currency: currency.to_string(),
+        }
+    }
+
+    pub fn checked_le(&self, other: &Money) -> Result<bool, AppError> {
+        if self.currency != other.currency {
+            return Err(new_ok_error(&format!(
+                "币种不一致，{} 和 {} 不能比较",
+                self.currency, other.currency
+            )));
+        }
+        Ok(self.amount <= other.amount)
+    }
+}
+
+impl Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.amount, self.currency)
+    }
+}
+
+// ISO 4217 的三位字母代码
+pub fn parse_currency(s: &str) -> Result<String, AppError> {
+    let currency = s.trim().to_ascii_uppercase();
+    if currency.len() != 3 || !currency.bytes().all(|x| x.is_ascii_uppercase()) {
+        return Err(new_ok_error(&format!("币种不合法：{}", s)));
+    }
+    Ok(currency)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amount_from_str() {
+        let amount = |s: &str| s.parse::<Amount>().map(|x| x.to_string());
+        assert_eq!(amount("500"), Ok("500.00".to_string()));
+        assert_eq!(amount(" 12.5 "), Ok("12.50".to_string()));
+        assert_eq!(amount("0.01"), Ok("0.01".to_string()));
+        assert_eq!(amount("-3.20"), Ok("-3.20".to_string()));
+        assert_eq!(amount("999999999999.99"), Ok("999999999999.99".to_string()));
+        for s in [
+            "",
+            "abc",
+            "1e3",
+            "1.",
+            ".5",
+            "1.234",
+            "1,000",
+            "--1",
+            "1000000000000",
+        ] {
+            assert!(amount(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_amount_serde() {
+        let parse = |v: serde_json::Value| serde_json::from_value::<Amount>(v);
+        assert_eq!(parse(serde_json::json!(500)).unwrap(), Amount::from(500));
+        assert_eq!(
+            parse(serde_json::json!("12.34")).unwrap().to_string(),
+            "12.34"
+        );
+        assert_eq!(parse(serde_json::json!(0.1)).unwrap().to_string(), "0.10");
+        assert!(parse(serde_json::json!(-1)).is_err());
+        assert!(parse(serde_json::json!(1.005)).is_err());
+        assert!(parse(serde_json::json!("12,5")).is_err());
+        assert!(parse(serde_json::json!(null)).is_err());
+        assert_eq!(
+            serde_json::to_value(Amount::from(7)).unwrap(),
+            serde_json::json!("7.00")
+        );
+    }
+
+    #[test]
+    fn test_amount_compare() {
+        let budget = Amount::from(1000);
+        assert!(!Amount::from(1100).exceeds(&budget, 10));
+        assert!("1100.01".parse::<Amount>().unwrap().exceeds(&budget, 10));
+        assert!(Amount::from(1).exceeds(&Amount::zero(), 50));
+        assert_eq!(Amount::from(125).percent_of(&budget), Some(12.5));
+        assert_eq!(Amount::from(1).percent_of(&Amount::zero()), None);
+        let cny = |x| Money::new(Amount::from(x), "CNY");
+        assert!(cny(100).checked_le(&cny(100)).unwrap());
+        assert!(!cny(101).checked_le(&cny(100)).unwrap());
+        assert!(cny(1)
+            .checked_le(&Money::new(Amount::from(100), "USD"))
+            .is_err());
+        assert_eq!(parse_currency(" usd").unwrap(), "USD");
+        assert!(parse_currency("RMB1").is_err());
+        assert!(parse_currency("¥").is_err());
+    }
+}
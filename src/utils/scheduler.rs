@@ -0,0 +1,133 @@
+use std::{sync::Arc, time::Duration};
+
+use actix_web::{rt, web};
+
+use crate::{
+    error::AppError,
+    models::{notification, report, sla, webhook},
+    utils::{
+        constant::{
+            CONFIRM_SCAN_INTERVAL_SECS, EMAIL_SCAN_INTERVAL_SECS, SLA_SCAN_INTERVAL_SECS,
+            WEBHOOK_SCAN_INTERVAL_SECS,
+        },
+        mailer::{Mailer, SmtpConfig},
+    },
+    AppState,
+};
+
+fn interval_secs(key: &str, default: u64) -> u64 {
+    std::env::var(key)
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(default)
+}
+
+// 和 HttpServer 跑在同一个 runtime 里，数据库操作放到 blocking 线程池
+pub fn start(app_state: AppState) {
+    let secs = interval_secs("SLA_SCAN_INTERVAL_SECS", SLA_SCAN_INTERVAL_SECS);
+    let sla_state = app_state.clone();
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(Duration::from_secs(secs));
+        loop {
+            interval.tick().await;
+            let app_state = sla_state.clone();
+            let result = web::block(move || -> Result<usize, AppError> {
+                let mut conn = app_state.conn()?;
+                let ticket_ids = sla::escalate_overdue(&mut conn)?;
+                for ticket_id in ticket_ids.iter() {
+                    app_state.events.publish_ticket(&mut conn, *ticket_id);
+                }
+                Ok(ticket_ids.len())
+            })
+            .await;
+            match result {
+                Ok(Ok(0)) => {}
+                Ok(Ok(n)) => log::info!("escalated {} overdue tickets", n),
+                Ok(Err(e)) => log::error!("failed to escalate overdue tickets: {}", e),
+                Err(e) => log::error!("failed to escalate overdue tickets: {}", e),
+            }
+        }
+    });
+
+    let secs = interval_secs("CONFIRM_SCAN_INTERVAL_SECS", CONFIRM_SCAN_INTERVAL_SECS);
+    let confirm_state = app_state.clone();
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(Duration::from_secs(secs));
+        loop {
+            interval.tick().await;
+            let app_state = confirm_state.clone();
+            let result = web::block(move || -> Result<usize, AppError> {
+                let mut conn = app_state.conn()?;
+                let ticket_ids = report::close_unconfirmed(&mut conn)?;
+                for ticket_id in ticket_ids.iter() {
+                    app_state.events.publish_ticket(&mut conn, *ticket_id);
+                }
+                Ok(ticket_ids.len())
+            })
+            .await;
+            match result {
+                Ok(Ok(0)) => {}
+                Ok(Ok(n)) => log::info!("closed {} unconfirmed tickets", n),
+                Ok(Err(e)) => log::error!("failed to close unconfirmed tickets: {}", e),
+                Err(e) => log::error!("failed to close unconfirmed tickets: {}", e),
+            }
+        }
+    });
+
+    start_mailer(app_state.clone());
+
+    let secs = interval_secs("WEBHOOK_SCAN_INTERVAL_SECS", WEBHOOK_SCAN_INTERVAL_SECS);
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(Duration::from_secs(secs));
+        loop {
+            interval.tick().await;
+            let app_state = app_state.clone();
+            let result = web::block(move || -> Result<usize, AppError> {
+                let mut conn = app_state.conn()?;
+                webhook::deliver_due(&mut conn)
+            })
+            .await;
+            match result {
+                Ok(Ok(0)) => {}
+                Ok(Ok(n)) => log::info!("delivered {} webhooks", n),
+                Ok(Err(e)) => log::error!("failed to deliver webhooks: {}", e),
+                Err(e) => log::error!("failed to deliver webhooks: {}", e),
+            }
+        }
+    });
+}
+
+// 没配 SMTP 的时候通知还是会记下要发邮件，配好之后补发
+fn start_mailer(app_state: AppState) {
+    let Some(config) = SmtpConfig::from_env() else {
+        log::info!("SMTP_HOST not set, email notifications disabled");
+        return;
+    };
+    let mailer = match Mailer::new(&config) {
+        Ok(mailer) => Arc::new(mailer),
+        Err(e) => {
+            log::error!("failed to create mailer: {}", e);
+            return;
+        }
+    };
+    let secs = interval_secs("EMAIL_SCAN_INTERVAL_SECS", EMAIL_SCAN_INTERVAL_SECS);
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(Duration::from_secs(secs));
+        loop {
+            interval.tick().await;
+            let app_state = app_state.clone();
+            let mailer = mailer.clone();
+            let result = web::block(move || -> Result<usize, AppError> {
+                let mut conn = app_state.conn()?;
+                notification::deliver_pending_emails(&mut conn, &mailer)
+            })
+            .await;
+            match result {
+                Ok(Ok(0)) => {}
+                Ok(Ok(n)) => log::info!("sent {} notification emails", n),
+                Ok(Err(e)) => log::error!("failed to send notification emails: {}", e),
+                Err(e) => log::error!("failed to send notification emails: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,226 @@
+// 需要数据库的测试用的工具，跑之前要在 .env 里配好 DATABASE_URL
+// cargo test -- --ignored
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use actix_web::{http::StatusCode, test, web, App};
+use diesel::prelude::*;
+
+use crate::{
+    models::{
+        account::{Account, InsertAccount},
+        approval::{Approval, InsertApproval},
+        attachment::{Attachment, InsertAttachment},
+        department::{Department, EmployeeWithDepartments, InsertDepartment},
+        employee::{Employee, InsertEmployee},
+        system::System,
+        ticket::{InsertTicket, Ticket, TicketWithDepartments, UpdateTicket},
+    },
+    router,
+    schema::account_info,
+    utils::{
+        auth::Authorization,
+        broadcast::Broadcaster,
+        constant::{TicketState, ACCOUNT_TYPE_ADMIN, SEX_MALE},
+        money::Amount,
+    },
+    AppState, Manager, Pool,
+};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub fn unique_name(prefix: &str) -> String {
+    format!(
+        "{}_{}_{}_{}",
+        prefix,
+        std::process::id(),
+        chrono::Utc::now().timestamp_millis(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+pub fn pool() -> Pool {
+    dotenv::dotenv().ok();
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL not set");
+    r2d2::Pool::builder()
+        .max_size(2)
+        .build(Manager::new(database_url))
+        .expect("failed to build pool")
+}
+
+pub struct TestSystem {
+    pub system: System,
+    pub admin_token: String,
+    pub departments: Vec<Department>,
+    pub approvals: Vec<Approval>,
+}
+
+// 建一个带管理员的系统，initialized 为 true 时顺便建好部门 D1, D2 和审批层级 L1(100), L2(1000)
+pub fn create_system(conn: &mut PgConnection, initialized: bool) -> TestSystem {
+    let system = System::create(conn, &unique_name("system")).unwrap();
+    let (_, admin) = create_employee(conn, system.id, ACCOUNT_TYPE_ADMIN, None, vec![]);
+    let admin_token = admin.generate_token().unwrap();
+    System::set_admin_account_id(conn, system.id, admin.id).unwrap();
+    let mut departments = vec![];
+    let mut approvals = vec![];
+    if initialized {
+        for name in ["D1", "D2"] {
+            let department = Department::create(
+                conn,
+                InsertDepartment {
+                    department_name: name,
+                    system_id: system.id,
+                },
+            )
+            .unwrap();
+            departments.push(department);
+        }
+        for (name, amount) in [("L1", 100), ("L2", 1000)] {
+            let approval = Approval::create(
+                conn,
+                InsertApproval {
+                    approval_name: name,
+                    amount: Amount::from(amount),
+                    company: None,
+                    system_id: system.id,
+                    currency: &system.currency,
+                },
+            )
+            .unwrap();
+            approvals.push(approval);
+        }
+        System::set_initialized(conn, system.id, 1).unwrap();
+    }
+    let system = System::get_by_id(conn, system.id).unwrap();
+    TestSystem {
+        system,
+        admin_token,
+        departments,
+        approvals,
+    }
+}
+
+// 不走 bcrypt，直接插账号，测试里只需要 token
+pub fn create_employee(
+    conn: &mut PgConnection,
+    system_id: i32,
+    account_type: i16,
+    approval_id: Option<i32>,
+    department_ids: Vec<i32>,
+) -> (Employee, Account) {
+    let name = unique_name("employee");
+    let employee = Employee::create(
+        conn,
+        InsertEmployee {
+            name: &name,
+            age: 30,
+            position: None,
+            phone: "12345678901",
+            approval_id,
+            system_id,
+            sex: SEX_MALE,
+            company_name: None,
+            email: None,
+            max_wip: None,
+        },
+    )
+    .unwrap();
+    let account: Account = diesel::insert_into(account_info::table)
+        .values(InsertAccount {
+            employee_id: employee.id,
+            account_name: &name,
+            password_hash: "",
+            account_type,
+        })
+        .get_result(conn)
+        .unwrap();
+    for department_id in department_ids.into_iter() {
+        EmployeeWithDepartments::create(conn, employee.id, department_id).unwrap();
+    }
+    (employee, account)
+}
+
+pub async fn call(
+    pool: &Pool,
+    req: test::TestRequest,
+    token: &str,
+) -> (StatusCode, serde_json::Value) {
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(AppState {
+                pool: pool.clone(),
+                events: Broadcaster::default(),
+            }))
+            .wrap(Authorization)
+            .configure(router::routes),
+    )
+    .await;
+    let req = req
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let status = resp.status();
+    let body = test::read_body(resp).await;
+    let json = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+    (status, json)
+}
+
+// 业务错误目前也是 200，所以看 data.error
+pub fn is_error(status: StatusCode, body: &serde_json::Value) -> bool {
+    !status.is_success() || body["data"]["error"].is_string()
+}
+
+// 直接建一个指定状态的工单，跳过审批流程
+pub fn create_ticket(
+    conn: &mut PgConnection,
+    system_id: i32,
+    creator_id: i32,
+    department_ids: Vec<i32>,
+    state: TicketState,
+    receiver_id: Option<i32>,
+) -> Ticket {
+    let system = System::get_by_id(conn, system_id).unwrap();
+    let ticket = Ticket::create(
+        conn,
+        InsertTicket {
+            creator_id,
+            title: "测试工单",
+            amount: Amount::from(500),
+            reason: "测试",
+            address: "测试地址",
+            system_id,
+            created_time: chrono::Utc::now().naive_utc(),
+            currency: &system.currency,
+        },
+    )
+    .unwrap();
+    for department_id in department_ids.into_iter() {
+        TicketWithDepartments::create(conn, ticket.id, department_id).unwrap();
+    }
+    Ticket::update(
+        conn,
+        ticket.id,
+        UpdateTicket {
+            state: Some(state),
+            receiver_id,
+            ..Default::default()
+        },
+    )
+    .unwrap()
+}
+
+// 相当于上传了一个文件，还没挂到工单上
+pub fn create_attachment(conn: &mut PgConnection, uploader_id: i32, filename: &str) -> Attachment {
+    Attachment::create(
+        conn,
+        InsertAttachment {
+            uploader_id,
+            url: &format!("/static/{}", filename),
+            filename,
+            content_type: "image/png",
+            size: 5,
+            checksum: "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+            created_time: chrono::Utc::now().naive_local(),
+        },
+    )
+    .unwrap()
+}